@@ -1,32 +1,87 @@
-use brotli;
+#[cfg(feature = "bz2")]
 use bzip2::read::BzDecoder;
-use std::io::{self, ErrorKind};
+use std::io::ErrorKind;
+use std::ops::Range;
+use std::path::Path;
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
 use std::vec::Vec;
 use std::{
-    convert::TryInto,
-    io::{Cursor, Read, Seek},
+    convert::{TryFrom, TryInto},
+    io::{Cursor, Read, Seek, SeekFrom, Write},
 };
 
 use binread::{BinRead, BinResult, ReadOptions};
 
-#[derive(Debug, Eq, PartialEq)]
+use crate::compression::{brotli_window_bits, compress, compressed_stream_len, not_compiled};
+use crate::error::BsdumpError;
+use crate::options::{BsdiffReaderBuilder, BsdiffReaderOptions, DecompressTrace};
+#[cfg(feature = "proto")]
+use crate::proto;
+use crate::record::{SummaryRecord, SUMMARY_RECORD_LEN};
+use crate::report::PatchReport;
+use crate::stream::ControlEntryStream;
+use crate::writer::BsdiffWriter;
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
 pub enum CompressorType {
     Bz2,
     Brotli,
+    Zstd,
+    // Not written by any bsdiff release, but used by ad-hoc generators.
+    Gzip,
+}
+
+impl CompressorType {
+    pub const ALL: [CompressorType; 4] = [
+        CompressorType::Bz2,
+        CompressorType::Brotli,
+        CompressorType::Zstd,
+        CompressorType::Gzip,
+    ];
+
+    /// Whether the cargo feature for this codec is enabled.
+    pub fn is_compiled(self) -> bool {
+        return match self {
+            CompressorType::Bz2 => cfg!(feature = "bz2"),
+            CompressorType::Brotli => cfg!(feature = "brotli"),
+            CompressorType::Zstd => cfg!(feature = "zstd"),
+            CompressorType::Gzip => cfg!(feature = "gzip"),
+        };
+    }
+
+    /// The cargo feature that enables this codec.
+    pub fn feature_name(self) -> &'static str {
+        return match self {
+            CompressorType::Bz2 => "bz2",
+            CompressorType::Brotli => "brotli",
+            CompressorType::Zstd => "zstd",
+            CompressorType::Gzip => "gzip",
+        };
+    }
+
+    pub(crate) fn require_compiled(self) -> Result<(), BsdumpError> {
+        if !self.is_compiled() {
+            return Err(BsdumpError::CompressorNotCompiled {
+                compressor_type: self,
+            });
+        }
+        return Ok(());
+    }
 }
 
 const fn as_u32_be(array: &[u8; 4]) -> u32 {
     ((array[0] as u32) << 24)
         | ((array[1] as u32) << 16)
         | ((array[2] as u32) << 8)
-        | ((array[3] as u32) << 0)
+        | (array[3] as u32)
 }
 
 const fn as_u32_le(array: &[u8; 4]) -> u32 {
     ((array[3] as u32) << 24)
         | ((array[2] as u32) << 16)
         | ((array[1] as u32) << 8)
-        | ((array[0] as u32) << 0)
+        | (array[0] as u32)
 }
 
 const fn as_u64_le(arr: &[u8; 8]) -> u64 {
@@ -41,36 +96,81 @@ const fn as_u64_be(arr: &[u8; 8]) -> u64 {
 
 const LEGACY_BSDIFF_MAGIC: u64 = as_u64_be(b"BSDIFF40");
 const BSDIFF2_MAGIC: u64 = as_u64_be(b"BSDF2\x00\x00\x00");
+// BDF3 is BSDF2 with zero bytes stripped out of the diff stream. Which bytes
+// were kept is recorded in a brotli compressed mask stream stored at the end
+// of the patch, whose compressed size follows the regular header. This crate
+// reads the mask as a bitmap with one bit per byte of the full diff stream,
+// least significant bit first, where a set bit means the byte was kept.
 const BSDIFF3_MAGIC: u64 = as_u64_be(b"BDF3\x00\x00\x00\x00");
 
-fn is_valid_compressor_type(compressor_type: u8) -> bool {
-    return compressor_type == 1 || compressor_type == 2;
+pub(crate) fn is_valid_compressor_type(compressor_type: u8) -> bool {
+    return to_compressor_type(compressor_type).is_some();
+}
+
+pub(crate) fn to_compressor_type(compressor_type: u8) -> Option<CompressorType> {
+    return match compressor_type {
+        1 => Some(CompressorType::Bz2),
+        2 => Some(CompressorType::Brotli),
+        3 => Some(CompressorType::Zstd),
+        4 => Some(CompressorType::Gzip),
+        _ => None,
+    };
 }
 
-fn to_compressor_type(compressor_type: u8) -> CompressorType {
+pub(crate) fn from_compressor_type(compressor_type: CompressorType) -> u8 {
     return match compressor_type {
-        1 => CompressorType::Bz2,
-        2 => CompressorType::Brotli,
-        o => panic!("Invalid compressor type: {}", o),
+        CompressorType::Bz2 => 1,
+        CompressorType::Brotli => 2,
+        CompressorType::Zstd => 3,
+        CompressorType::Gzip => 4,
     };
 }
 
-fn is_valid_bsdiff_magic(magic: u64) -> bool {
-    let bytes = magic.to_be_bytes();
-    return (magic & BSDIFF2_MAGIC == BSDIFF2_MAGIC
-        && is_valid_compressor_type(bytes[5])
-        && is_valid_compressor_type(bytes[6])
-        && is_valid_compressor_type(bytes[7]))
-        || ((magic & BSDIFF3_MAGIC == BSDIFF3_MAGIC)
-            // && is_valid_compressor_type(bytes[4])
-            && is_valid_compressor_type(bytes[5])
-            && is_valid_compressor_type(bytes[6])
-            && is_valid_compressor_type(bytes[7]));
+// Tells an unknown magic apart from a known one with a bad compressor byte.
+fn check_magic(magic: [u8; 8]) -> Result<(), BsdumpError> {
+    if as_u64_be(&magic) == LEGACY_BSDIFF_MAGIC {
+        return Ok(());
+    }
+    // "BSDF2" fills bytes 0 to 4; "BDF3" only 0 to 3, leaving byte 4
+    // reserved as zero. Bytes 5 to 7 are the compressors in both.
+    let is_bsdiff2 = magic[..5] == BSDIFF2_MAGIC.to_be_bytes()[..5];
+    let is_bsdiff3 = magic[..4] == BSDIFF3_MAGIC.to_be_bytes()[..4];
+    if !is_bsdiff2 && !is_bsdiff3 {
+        return Err(BsdumpError::NotABsdiffPatch { magic });
+    }
+    if is_bsdiff3 && magic[4] != 0 {
+        return Err(BsdumpError::ReservedMagicByte {
+            magic,
+            index: 4,
+            value: magic[4],
+        });
+    }
+    for index in 5..8 {
+        if !is_valid_compressor_type(magic[index]) {
+            return Err(BsdumpError::InvalidCompressorType {
+                magic,
+                index,
+                value: magic[index],
+            });
+        }
+    }
+    return Ok(());
+}
+
+/// Whether a known magic with valid compressor bytes starts at `offset` of
+/// `data`. Nothing past the magic is read, so this is cheap enough to try at
+/// every offset when looking for patches embedded in a larger blob; a hit
+/// still needs a full parse to confirm.
+pub fn is_header_at(data: &[u8], offset: usize) -> bool {
+    return match data.get(offset..).and_then(|rest| rest.get(..8)) {
+        Some(magic) => check_magic(magic.try_into().unwrap()).is_ok(),
+        None => false,
+    };
 }
 
 #[derive(BinRead)]
-#[br(assert(magic == LEGACY_BSDIFF_MAGIC ||is_valid_bsdiff_magic(magic)), little)]
-#[derive(Debug, Clone, Copy)]
+#[br(little)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct BsdiffFormat {
     #[br(big)]
     pub magic: u64,
@@ -80,78 +180,711 @@ pub struct BsdiffFormat {
 }
 
 impl BsdiffFormat {
-    fn is_legacy_bsdiff_format(&self) -> bool {
+    pub(crate) fn is_legacy_bsdiff_format(&self) -> bool {
         return self.magic == LEGACY_BSDIFF_MAGIC;
     }
-    fn is_bsdiff3_format(&self) -> bool {
+    pub(crate) fn is_bsdiff3_format(&self) -> bool {
         return self.magic & BSDIFF3_MAGIC == BSDIFF3_MAGIC;
     }
-    fn get_ctrl_compressor(&self) -> CompressorType {
-        return if self.is_legacy_bsdiff_format() {
-            CompressorType::Bz2
-        } else {
-            to_compressor_type(self.magic.to_be_bytes()[5])
-        };
+    // The compressors of a header that passed `read_validated`, whose
+    // compressor bytes are known to be valid. See `compressors` for headers
+    // from `parse_unchecked`.
+    pub(crate) fn validated_compressors(&self) -> [CompressorType; 3] {
+        return self
+            .compressors()
+            .expect("compressor bytes are checked by read_validated");
+    }
+    pub(crate) fn get_ctrl_compressor(&self) -> CompressorType {
+        return self.validated_compressors()[0];
     }
     fn get_diff_compressor(&self) -> CompressorType {
-        return if self.is_legacy_bsdiff_format() {
-            CompressorType::Bz2
-        } else {
-            to_compressor_type(self.magic.to_be_bytes()[6])
-        };
+        return self.validated_compressors()[1];
     }
     fn get_extra_compressor(&self) -> CompressorType {
+        return self.validated_compressors()[2];
+    }
+    // The compressor named by magic byte `index`, 5 to 7 for the ctrl, diff
+    // and extra stream. BSDIFF40 always uses bz2.
+    fn compressor_at(&self, index: usize) -> Result<CompressorType, BsdumpError> {
+        if self.is_legacy_bsdiff_format() {
+            return Ok(CompressorType::Bz2);
+        }
+        let magic = self.magic.to_be_bytes();
+        return to_compressor_type(magic[index]).ok_or(BsdumpError::InvalidCompressorType {
+            magic,
+            index,
+            value: magic[index],
+        });
+    }
+    // A magic can match by coincidence. Reject headers whose stream sizes
+    // could not possibly fit in the file before trying to decompress.
+    fn check_plausible(&self, file_len: u64) -> Result<(), BsdumpError> {
+        let fields = [
+            ("compressed_ctrl_size", self.compressed_ctrl_size),
+            ("compressed_diff_size", self.compressed_diff_size),
+        ];
+        for (field, value) in fields {
+            if value > file_len {
+                return Err(BsdumpError::ImplausibleHeader {
+                    field,
+                    value,
+                    file_len,
+                });
+            }
+        }
+        return Ok(());
+    }
+
+    /// Decodes the header fields at the start of `data` as they are, with no
+    /// check of the magic or the sizes and no decoding of legacy lengths, to
+    /// inspect headers that fail to parse. Only fails if `data` is shorter
+    /// than a header. See `validate_magic`.
+    pub fn parse_unchecked(data: &[u8]) -> Result<BsdiffFormat, BsdumpError> {
+        return Ok(BsdiffFormat::read(&mut Cursor::new(data))?);
+    }
+
+    /// Checks that the magic is a known one with valid compressor bytes.
+    pub fn validate_magic(&self) -> Result<(), BsdumpError> {
+        return check_magic(self.magic.to_be_bytes());
+    }
+
+    // Reads the fixed size part of the header and checks the magic, but not
+    // the stream sizes. A reader holding fewer than 8 bytes fails to parse
+    // instead of being reported as not a patch.
+    pub(crate) fn read_validated(reader: &mut Cursor<&[u8]>) -> Result<BsdiffFormat, BsdumpError> {
+        if let Some(magic) = reader.get_ref().get(..8) {
+            check_magic(magic.try_into().unwrap())?;
+        }
+        let mut header = BsdiffFormat::read(reader)?;
+        if header.is_legacy_bsdiff_format() {
+            header.decode_legacy_lengths()?;
+        }
+        return Ok(header);
+    }
+
+    /// "BSDIFF40", "BSDF2" or "BDF3".
+    pub fn format_name(&self) -> &'static str {
         return if self.is_legacy_bsdiff_format() {
-            CompressorType::Bz2
+            "BSDIFF40"
+        } else if self.is_bsdiff3_format() {
+            "BDF3"
         } else {
-            to_compressor_type(self.magic.to_be_bytes()[7])
+            "BSDF2"
         };
     }
+
+    /// The compressors of the ctrl, diff and extra streams, in that order.
+    /// Fails with `BsdumpError::InvalidCompressorType` if a compressor byte
+    /// of the magic is unknown, which only a header from `parse_unchecked`
+    /// can have.
+    pub fn compressors(&self) -> Result<[CompressorType; 3], BsdumpError> {
+        return Ok([
+            self.compressor_at(5)?,
+            self.compressor_at(6)?,
+            self.compressor_at(7)?,
+        ]);
+    }
 }
 
-fn read_bsdiff_int<R: Read + Seek>(reader: &mut R, ro: &ReadOptions, _: ()) -> BinResult<i64> {
+/// Location of each compressed stream, as byte offsets into the patch file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StreamRanges {
+    pub ctrl: Range<u64>,
+    pub diff: Range<u64>,
+    pub extra: Range<u64>,
+}
+
+// The header is 32 bytes: an 8 byte magic followed by three u64 fields.
+pub(crate) const HEADER_SIZE: u64 = 32;
+// BDF3 stores the compressed mask size in an extra u64 after the header.
+pub(crate) const BSDIFF3_MASK_SIZE_LEN: u64 = 8;
+
+impl BsdiffFormat {
+    /// Computes where each stream lives in a patch file of `total_len`
+    /// bytes. The extra stream runs to the end of the file; for BDF3 this
+    /// includes the trailing mask stream. Ranges are not checked against
+    /// `total_len`, so a malformed header can produce ranges past the end.
+    pub fn stream_ranges(&self, total_len: u64) -> StreamRanges {
+        return self.stream_ranges_after(HEADER_SIZE, total_len);
+    }
+
+    // Like `stream_ranges` for a header of `header_size` bytes, which is
+    // longer than `HEADER_SIZE` for magics longer than 8 bytes.
+    fn stream_ranges_after(&self, header_size: u64, total_len: u64) -> StreamRanges {
+        let mut ctrl_start = header_size;
+        if self.is_bsdiff3_format() {
+            ctrl_start += BSDIFF3_MASK_SIZE_LEN;
+        }
+        let ctrl = ctrl_start..ctrl_start.saturating_add(self.compressed_ctrl_size);
+        let diff = ctrl.end..ctrl.end.saturating_add(self.compressed_diff_size);
+        let extra = diff.end..total_len.max(diff.end);
+        return StreamRanges { ctrl, diff, extra };
+    }
+}
+
+// Returns the bytes covered by `range`, or an error naming the stream if
+// the range runs past the end of the patch.
+fn stream_slice<'d>(
+    data: &'d [u8],
+    range: &Range<u64>,
+    name: &str,
+) -> Result<&'d [u8], BsdumpError> {
+    return data
+        .get(range.start as usize..range.end as usize)
+        .ok_or_else(|| {
+            BsdumpError::CorruptPatch(format!(
+                "{} stream {:?} extends past the end of the patch ({} bytes)",
+                name,
+                range,
+                data.len()
+            ))
+        });
+}
+
+fn decode_bsdiff_int(raw: u64) -> i64 {
     // BSPatch uses a non-standard encoding of integers.
     // Highest bit of that integer is used as a sign bit, 1 = negative
     // and 0 = positive.
     // Therefore, if the highest bit is set, flip it, then do 2's complement
     // to get the integer in standard form
-    let raw = u64::read_options(reader, ro, ())?;
     if raw & (1 << 63) == 0 {
-        return Ok(raw.try_into().unwrap());
+        return raw.try_into().unwrap();
     } else {
         let parsed: i64 = (raw & ((1 << 63) - 1)) as i64;
-        return Ok(-parsed);
+        return -parsed;
+    }
+}
+
+// Inverse of decode_bsdiff_int, matching upstream bsdiff's offtout().
+pub(crate) fn encode_bsdiff_int(value: i64) -> u64 {
+    if value < 0 {
+        return value.unsigned_abs() | 1 << 63;
+    } else {
+        return value as u64;
+    }
+}
+
+fn read_bsdiff_int<R: Read + Seek>(reader: &mut R, ro: &ReadOptions, _: ()) -> BinResult<i64> {
+    let raw = u64::read_options(reader, ro, ())?;
+    return Ok(decode_bsdiff_int(raw));
+}
+
+// Classic bspatch decodes the diff and extra sizes of control entries with
+// offtin() too, so in BSDIFF40 patches a set high bit is a sign, not part
+// of the length. BSDF2 and BDF3 store them as plain u64.
+fn read_entry_size<R: Read + Seek>(
+    reader: &mut R,
+    ro: &ReadOptions,
+    (legacy,): (bool,),
+) -> BinResult<u64> {
+    let pos = reader.stream_position()?;
+    let raw = u64::read_options(reader, ro, ())?;
+    if !legacy {
+        return Ok(raw);
+    }
+    let decoded = decode_bsdiff_int(raw);
+    if decoded < 0 {
+        return Err(binread::Error::AssertFail {
+            pos,
+            message: format!("negative control entry size {}", decoded),
+        });
+    }
+    return Ok(decoded as u64);
+}
+
+impl BsdiffFormat {
+    // The classic BSDIFF40 header stores its three lengths with the same
+    // sign bit encoding as control entries, and bspatch rejects negative
+    // ones. For non-negative values this is identical to a plain u64, so
+    // only lengths with the high bit set need decoding.
+    fn decode_legacy_lengths(&mut self) -> Result<(), BsdumpError> {
+        let fields = [
+            ("compressed_ctrl_size", &mut self.compressed_ctrl_size),
+            ("compressed_diff_size", &mut self.compressed_diff_size),
+            ("new_file_size", &mut self.new_file_size),
+        ];
+        for (name, field) in fields {
+            let decoded = decode_bsdiff_int(*field);
+            if decoded < 0 {
+                return Err(BsdumpError::CorruptPatch(format!(
+                    "BSDIFF40 header has negative {} {}",
+                    name, decoded
+                )));
+            }
+            *field = decoded as u64;
+        }
+        return Ok(());
     }
 }
 
 #[derive(BinRead)]
-#[br(little)]
-#[derive(Debug)]
+#[br(little, import(legacy: bool))]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct ControlEntry {
     // The number of bytes to copy from the source and diff stream.
+    #[br(parse_with=read_entry_size, args(legacy))]
     pub diff_size: u64,
 
     // The number of bytes to copy from the extra stream.
+    #[br(parse_with=read_entry_size, args(legacy))]
     pub extra_size: u64,
 
     // The value to add to the source pointer after patching from the diff stream.
     #[br(parse_with=read_bsdiff_int)]
-    offset_increment: i64,
+    pub offset_increment: i64,
+}
+impl ControlEntry {
+    /// Whether the entry writes nothing and only moves the source offset.
+    /// Some generators emit these; applying one just advances the source.
+    pub fn is_pure_seek(&self) -> bool {
+        return self.diff_size == 0 && self.extra_size == 0 && self.offset_increment != 0;
+    }
+
+    /// The entry as the three operations an applier performs, in order:
+    /// add `diff_size` diff bytes to the source, insert `extra_size` extra
+    /// bytes, then move the source pointer. Operations with a zero length
+    /// or delta are included, so the result always has three elements.
+    pub fn primitives(&self) -> [Primitive; 3] {
+        return [
+            Primitive::Add {
+                len: self.diff_size,
+            },
+            Primitive::Insert {
+                len: self.extra_size,
+            },
+            Primitive::Seek {
+                delta: self.offset_increment,
+            },
+        ];
+    }
+}
+
+/// One step of applying a control entry, see `ControlEntry::primitives`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Primitive {
+    // Write `len` bytes, each the sum of a diff stream byte and the source
+    // byte at the source pointer, advancing both.
+    Add { len: u64 },
+    // Copy `len` bytes from the extra stream to the new file.
+    Insert { len: u64 },
+    // Move the source pointer by `delta` bytes, which may be negative.
+    Seek { delta: i64 },
 }
+
 // Control entry has 3 u64 fields, so 24 bytes in total.
-const CONTROL_ENTRY_SIZE: usize = 24;
+pub(crate) const CONTROL_ENTRY_SIZE: usize = 24;
+
+// A control entry together with its position in the patch.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OffsetControlEntry {
+    pub index: usize,
+    // Where the entry starts reading the source. Malformed patches can
+    // seek before the start of the source, so this is signed.
+    pub source_offset: i64,
+    // Where the entry starts writing the new file.
+    pub target_offset: u64,
+    pub entry: ControlEntry,
+}
+
+/// A run of diff stream bytes that a BDF3 patch stores explicitly. Bytes
+/// of the diff stream that are not covered by any run are zero and are
+/// left out of the stored diff stream.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MaskEntry {
+    // Offset of the run in the full, zero filled diff stream.
+    pub offset: u64,
+    // Number of consecutive bytes that are stored.
+    pub len: u64,
+}
+
+/// Bytes `BsdiffReader::apply` holds at its peak, by buffer, see
+/// `BsdiffReader::apply_memory_estimate`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MemoryEstimate {
+    pub ctrl_stream: u64,
+    // BDF3 only, zero for other formats.
+    pub mask_stream: u64,
+    // The full diff stream, with the bytes a BDF3 mask strips put back.
+    pub diff_stream: u64,
+    pub extra_stream: u64,
+    pub output: u64,
+}
+
+impl MemoryEstimate {
+    pub fn total(&self) -> u64 {
+        return self.ctrl_stream
+            + self.mask_stream
+            + self.diff_stream
+            + self.extra_stream
+            + self.output;
+    }
+}
+
+/// Sizes of the diff and extra copies across all control entries, see
+/// `BsdiffReader::entry_size_stats`. All zero for a patch without entries.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct EntrySizeStats {
+    pub avg_diff: f64,
+    pub max_diff: u64,
+    pub avg_extra: f64,
+    pub max_extra: u64,
+}
+
+/// How scattered the source reads of a patch are, see
+/// `BsdiffReader::source_fragmentation`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SourceFragmentation {
+    // Control entries that read the source, i.e. have a non-zero diff_size.
+    pub num_reads: usize,
+    pub avg_read_len: f64,
+    // Reads that don't start where the previous one ended.
+    pub num_seeks: usize,
+    // Total bytes skipped over, forwards or backwards, by those seeks.
+    // Sticks at u64::MAX.
+    pub seek_distance_sum: u64,
+}
+
+/// A control entry and what applying it amounts to, see
+/// `BsdiffReader::operations`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EntryOperation {
+    pub entry: OffsetControlEntry,
+    // The entry reads the source and its diff bytes are all zero, so the
+    // add changes nothing and the source bytes are copied as they are.
+    pub is_pure_copy: bool,
+}
+
+/// The estimated apply cost of one control entry, see
+/// `BsdiffReader::cost_profile`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EntryCost {
+    pub entry: OffsetControlEntry,
+    pub cost: u64,
+}
+
+/// Cost units per diff byte in `BsdiffReader::cost_profile`: one for
+/// reading the source byte and one for the add.
+pub const DIFF_BYTE_COST: u64 = 2;
+/// Cost units per extra byte in `BsdiffReader::cost_profile`.
+pub const EXTRA_BYTE_COST: u64 = 1;
+/// Cost units an entry with a non-zero `offset_increment` pays for moving
+/// the source pointer, in `BsdiffReader::cost_profile`.
+pub const SEEK_COST: u64 = 4096;
+
+/// A stream that compressed to at least its own size, see
+/// `BsdiffReader::expanded_streams`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExpandedStream {
+    // "ctrl", "diff", "extra" or "mask".
+    pub stream: &'static str,
+    pub compressor: CompressorType,
+    pub compressed_len: u64,
+    pub decompressed_len: u64,
+}
+
+impl ExpandedStream {
+    /// Compressed size over decompressed size, at least 1.
+    pub fn ratio(&self) -> f64 {
+        return self.compressed_len as f64 / self.decompressed_len as f64;
+    }
+}
+
+impl std::fmt::Display for ExpandedStream {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        return write!(
+            f,
+            "{} stream expanded under {:?} ({:.2}x)",
+            self.stream,
+            self.compressor,
+            self.ratio()
+        );
+    }
+}
+
+pub struct MaskEntryIter<'a> {
+    mask: &'a [u8],
+    bit: u64,
+}
+
+impl<'a> MaskEntryIter<'a> {
+    fn is_set(&self, bit: u64) -> bool {
+        return self.mask[(bit / 8) as usize] & (1 << (bit % 8)) != 0;
+    }
+}
+
+impl<'a> Iterator for MaskEntryIter<'a> {
+    type Item = MaskEntry;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let total_bits = self.mask.len() as u64 * 8;
+        while self.bit < total_bits && !self.is_set(self.bit) {
+            self.bit += 1;
+        }
+        if self.bit >= total_bits {
+            return None;
+        }
+        let offset = self.bit;
+        while self.bit < total_bits && self.is_set(self.bit) {
+            self.bit += 1;
+        }
+        return Some(MaskEntry {
+            offset,
+            len: self.bit - offset,
+        });
+    }
+}
+
+// Rebuilds the full diff stream of a BDF3 patch by putting back the zero
+// bytes that the mask says were stripped.
+fn expand_masked_diff_stream(stored: &[u8], mask: &[u8]) -> Result<Vec<u8>, BsdumpError> {
+    let mut diff_stream = vec![0_u8; mask.len() * 8];
+    let mut stored_pos = 0;
+    let runs = MaskEntryIter { mask, bit: 0 };
+    for run in runs {
+        let len = run.len as usize;
+        let bytes = stored.get(stored_pos..stored_pos + len).ok_or_else(|| {
+            BsdumpError::CorruptPatch(format!(
+                "mask marks more bytes than the {} stored in the diff stream",
+                stored.len()
+            ))
+        })?;
+        diff_stream[run.offset as usize..][..len].copy_from_slice(bytes);
+        stored_pos += len;
+    }
+    if stored_pos != stored.len() {
+        return Err(BsdumpError::CorruptPatch(format!(
+            "mask marks {} bytes, but the diff stream stores {}",
+            stored_pos,
+            stored.len()
+        )));
+    }
+    return Ok(diff_stream);
+}
+
+// 128 bit FNV-1a, for `BsdiffReader::logical_id`.
+struct Fnv1a128(u128);
+
+impl Fnv1a128 {
+    const OFFSET_BASIS: u128 = 0x6c62272e07bb014262b821756295c58d;
+    const PRIME: u128 = 0x0000000001000000000000000000013b;
+
+    fn new() -> Fnv1a128 {
+        return Fnv1a128(Self::OFFSET_BASIS);
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        for byte in data {
+            self.0 = (self.0 ^ *byte as u128).wrapping_mul(Self::PRIME);
+        }
+    }
+}
+
+// Raw patch bytes, either borrowed from the caller or kept alive by a
+// reference counted `bytes::Bytes` handle.
+enum PatchData<'a> {
+    Borrowed(&'a [u8]),
+    #[cfg(feature = "bytes")]
+    Shared(bytes::Bytes),
+}
+
+impl<'a> PatchData<'a> {
+    fn as_slice(&self) -> &[u8] {
+        return match self {
+            PatchData::Borrowed(data) => data,
+            #[cfg(feature = "bytes")]
+            PatchData::Shared(data) => data.as_ref(),
+        };
+    }
+
+    // Drops the first `n` bytes, which must exist.
+    fn skip(self, n: usize) -> PatchData<'a> {
+        return match self {
+            PatchData::Borrowed(data) => PatchData::Borrowed(&data[n..]),
+            #[cfg(feature = "bytes")]
+            PatchData::Shared(data) => PatchData::Shared(data.slice(n..)),
+        };
+    }
+}
+
+// Wraps the compressed input of a decompressor to report how much of it has
+// been consumed.
+struct ProgressReader<'a, R: Read> {
+    inner: R,
+    done: u64,
+    total: u64,
+    options: &'a BsdiffReaderOptions,
+}
+
+impl<'a, R: Read> Read for ProgressReader<'a, R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.done += n as u64;
+        self.options.report_progress(self.done, self.total);
+        return Ok(n);
+    }
+}
+
+// Wraps a decompressor to count its output and report a `DecompressTrace`
+// once it reaches the end of the stream or fails.
+struct TraceReader<'a, R: Read> {
+    inner: R,
+    trace: DecompressTrace,
+    start: Instant,
+    done: bool,
+    options: &'a BsdiffReaderOptions,
+}
+
+impl<'a, R: Read> TraceReader<'a, R> {
+    fn finish(&mut self, error: Option<String>) {
+        if self.done {
+            return;
+        }
+        self.done = true;
+        self.trace.elapsed = self.start.elapsed();
+        self.trace.error = error;
+        self.options.report_trace(&self.trace);
+    }
+}
+
+impl<'a, R: Read> Read for TraceReader<'a, R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        return match self.inner.read(buf) {
+            Ok(0) if !buf.is_empty() => {
+                self.finish(None);
+                Ok(0)
+            }
+            Ok(n) => {
+                self.trace.output_len += n as u64;
+                self.trace.zero_bytes += buf[..n].iter().filter(|byte| **byte == 0).count() as u64;
+                Ok(n)
+            }
+            Err(e) if e.kind() == ErrorKind::Interrupted => Err(e),
+            Err(e) => {
+                self.finish(Some(e.to_string()));
+                Err(e)
+            }
+        };
+    }
+}
+
+// Computes the CRC32 of everything written through it.
+#[cfg(feature = "crc32fast")]
+struct Crc32Writer<W: Write> {
+    inner: W,
+    hasher: crc32fast::Hasher,
+}
+
+#[cfg(feature = "crc32fast")]
+impl<W: Write> Write for Crc32Writer<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.hasher.update(&buf[..n]);
+        return Ok(n);
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        return self.inner.flush();
+    }
+}
+
+// Where a run of bytes of a patch's new file comes from: diff bytes added
+// to the source, or extra bytes copied as is. Offsets index the source and
+// the decompressed diff or extra stream.
+#[derive(Clone, Copy)]
+enum Origin {
+    Source { source: u64, diff: usize },
+    Extra { extra: usize },
+}
+
+// A run of `len` bytes of the new file starting at `target`.
+struct Segment {
+    target: u64,
+    len: u64,
+    origin: Origin,
+}
+
+// Collects the runs of a composed patch and turns them into control
+// entries, merging runs that read the source contiguously.
+struct Composer {
+    writer: BsdiffWriter,
+    diff: Vec<u8>,
+    extra: Vec<u8>,
+    // Source offset the pending entry starts reading at.
+    source: u64,
+}
+
+impl Composer {
+    fn push_diff(&mut self, source: u64, diff: impl Iterator<Item = u8>) {
+        let read_end = self.source + self.diff.len() as u64;
+        if !self.extra.is_empty() || source != read_end {
+            let offset_increment = source as i64 - read_end as i64;
+            self.writer
+                .add_entry(&self.diff, &self.extra, offset_increment);
+            self.diff.clear();
+            self.extra.clear();
+            self.source = source;
+        }
+        self.diff.extend(diff);
+    }
+
+    fn push_extra(&mut self, extra: impl Iterator<Item = u8>) {
+        self.extra.extend(extra);
+    }
 
-pub trait BinreadReader: Read + Seek {}
+    fn finish(mut self) -> std::io::Result<Vec<u8>> {
+        if !self.diff.is_empty() || !self.extra.is_empty() {
+            self.writer.add_entry(&self.diff, &self.extra, 0);
+        }
+        return self.writer.finish();
+    }
+}
 
+/// A parsed bsdiff patch.
+///
+/// The reader is `Send + Sync`: it only borrows the patch immutably (or
+/// holds a `bytes::Bytes` handle), and the progress callback is required to
+/// be `Send` and is called under a lock. A single reader can be shared
+/// across threads; every accessor takes `&self` and iterators are
+/// independent of each other.
 pub struct BsdiffReader<'a> {
-    data: &'a [u8],
+    data: PatchData<'a>,
     decompressed_ctrl_stream: Vec<u8>,
+    // BDF3 only, zero and `None` for other formats.
+    compressed_mask_size: u64,
+    decompressed_mask_stream: Option<Vec<u8>>,
+    base_offset: u64,
+    options: BsdiffReaderOptions,
+    // Target offset each control entry starts writing at, followed by the
+    // end of the new file. Computed on the first `seek_to_target`.
+    target_offsets: OnceLock<Vec<u64>>,
+    // Offset into the diff stream each control entry starts reading at,
+    // followed by the total. Computed on the first `diff_offset_for_entry`.
+    diff_offsets: OnceLock<Vec<u64>>,
+    // Source offset each control entry starts reading at, followed by the
+    // offset after the last one. Computed on the first
+    // `source_offset_for_entry`. `None` from the first entry whose
+    // offset doesn't fit in an i64 on.
+    source_offsets: OnceLock<Vec<Option<i64>>>,
+    // Computed on the first `has_backward_seeks`.
+    has_backward_seeks: OnceLock<bool>,
+    // Decompressed length of the stored diff stream, counted in `parse`.
+    diff_stream_len: u64,
+    // Computed on the first `apply_memory_estimate`.
+    extra_stream_len: OnceLock<u64>,
     pub header: BsdiffFormat,
 }
 
+/// Control entries in file order, see `BsdiffReader::control_entries`.
 pub struct ControlEntryIter<'a> {
     control_entry_reader: Cursor<&'a Vec<u8>>,
     control_entry_stream_len: usize,
+    // Number of entries left before hitting `max_control_entries`.
+    remaining: Option<usize>,
+    limit: Option<usize>,
+    truncated: bool,
+    target_offsets: &'a OnceLock<Vec<u64>>,
+    // Whether entry sizes use the BSDIFF40 sign bit encoding.
+    legacy: bool,
 }
 
 impl<'a> Iterator for ControlEntryIter<'a> {
@@ -163,113 +896,2174 @@ impl<'a> Iterator for ControlEntryIter<'a> {
         {
             return None;
         }
-        return Some(ControlEntry::read(&mut self.control_entry_reader).unwrap());
+        if let Some(remaining) = &mut self.remaining {
+            if *remaining == 0 {
+                self.truncated = true;
+                return None;
+            }
+            *remaining -= 1;
+        }
+        return Some(
+            ControlEntry::read_args(&mut self.control_entry_reader, (self.legacy,)).unwrap(),
+        );
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let position = self.control_entry_reader.position() as usize;
+        let mut len = self.control_entry_stream_len.saturating_sub(position) / CONTROL_ENTRY_SIZE;
+        if let Some(remaining) = self.remaining {
+            len = len.min(remaining);
+        }
+        return (len, Some(len));
+    }
+}
+
+impl<'a> ExactSizeIterator for ControlEntryIter<'a> {}
+
+pub struct BestEffortControlEntryIter<'a> {
+    chunks: std::iter::Enumerate<std::slice::Chunks<'a, u8>>,
+    legacy: bool,
+}
+
+impl<'a> Iterator for BestEffortControlEntryIter<'a> {
+    type Item = Result<ControlEntry, BsdumpError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (index, chunk) = self.chunks.next()?;
+        if chunk.len() < CONTROL_ENTRY_SIZE {
+            return Some(Err(BsdumpError::TruncatedControlEntry {
+                index,
+                offset: (index * CONTROL_ENTRY_SIZE) as u64,
+                available: chunk.len(),
+            }));
+        }
+        return Some(
+            ControlEntry::read_args(&mut Cursor::new(chunk), (self.legacy,))
+                .map_err(BsdumpError::from),
+        );
+    }
+}
+
+impl OffsetControlEntry {
+    /// The source bytes this entry adds to the diff stream. Parts that lie
+    /// before the start of the source are clipped off, since nothing can be
+    /// read there, and the end is capped at `i64::MAX`.
+    pub fn source_range(&self) -> Range<u64> {
+        let start = self.source_offset.max(0) as u64;
+        let diff_size = i64::try_from(self.entry.diff_size).unwrap_or(i64::MAX);
+        let end = self.source_offset.saturating_add(diff_size).max(0) as u64;
+        return start..end;
+    }
+}
+
+pub struct ControlEntryOffsetIter<'a> {
+    entries: ControlEntryIter<'a>,
+    index: usize,
+    source_offset: i64,
+    target_offset: u64,
+}
+
+impl<'a> ControlEntryOffsetIter<'a> {
+    /// See `ControlEntryIter::is_truncated`.
+    pub fn is_truncated(&self) -> bool {
+        return self.entries.is_truncated();
+    }
+}
+
+impl<'a> Iterator for ControlEntryOffsetIter<'a> {
+    type Item = OffsetControlEntry;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let entry = self.entries.next()?;
+        let item = OffsetControlEntry {
+            index: self.index,
+            source_offset: self.source_offset,
+            target_offset: self.target_offset,
+            entry,
+        };
+        self.index += 1;
+        // Sizes and seeks come straight from the patch, so a malformed one
+        // can push the offsets past what fits. They stick at the limit
+        // instead, which is far outside any real file either way.
+        let diff_size = i64::try_from(item.entry.diff_size).unwrap_or(i64::MAX);
+        self.source_offset = self
+            .source_offset
+            .saturating_add(diff_size)
+            .saturating_add(item.entry.offset_increment);
+        self.target_offset = self
+            .target_offset
+            .saturating_add(item.entry.diff_size)
+            .saturating_add(item.entry.extra_size);
+        return Some(item);
     }
 }
 
 impl<'a> ControlEntryIter<'a> {
     fn new(
-        mut control_entry_reader: Cursor<&Vec<u8>>,
+        mut control_entry_reader: Cursor<&'a Vec<u8>>,
         control_entry_stream_len: usize,
-    ) -> ControlEntryIter {
+        limit: Option<usize>,
+        target_offsets: &'a OnceLock<Vec<u64>>,
+        legacy: bool,
+    ) -> ControlEntryIter<'a> {
         control_entry_reader
             .seek(std::io::SeekFrom::Start(0))
             .expect("Failed to seek to beginning of control stream");
         return ControlEntryIter {
             control_entry_reader,
             control_entry_stream_len,
+            remaining: limit,
+            limit,
+            truncated: false,
+            target_offsets,
+            legacy,
         };
     }
-}
 
-impl<'a> BsdiffReader<'a> {
-    fn decompress(data: &[u8], compressor_type: CompressorType) -> Result<Vec<u8>, std::io::Error> {
-        let mut buf = Vec::new();
-        match compressor_type {
-            CompressorType::Brotli => {
-                let mut reader = brotli::Decompressor::new(data, 4096 /* buffer size */);
-                reader.read_to_end(&mut buf)?;
-            }
-            CompressorType::Bz2 => {
-                let mut reader = BzDecoder::new(data);
-                reader.read_to_end(&mut buf)?;
+    /// Positions the iterator so that the next entry is the one writing
+    /// target byte `offset`, and returns the target offset that entry
+    /// starts at. Empty entries are skipped over. If `offset` is at or past
+    /// the end of the new file the iterator is moved to the end and `None`
+    /// is returned. `max_control_entries` still counts from the first
+    /// entry, so seeking past it yields nothing.
+    pub fn seek_to_target(&mut self, offset: u64) -> Option<u64> {
+        let stream = *self.control_entry_reader.get_ref();
+        let len = self.control_entry_stream_len;
+        let legacy = self.legacy;
+        let offsets = self.target_offsets.get_or_init(|| {
+            let mut offsets = vec![0];
+            let mut target = 0_u64;
+            for chunk in stream[..len].chunks_exact(CONTROL_ENTRY_SIZE) {
+                let entry = ControlEntry::read_args(&mut Cursor::new(chunk), (legacy,)).unwrap();
+                // Saturating keeps the offsets sorted for the search below.
+                target = target
+                    .saturating_add(entry.diff_size)
+                    .saturating_add(entry.extra_size);
+                offsets.push(target);
             }
-        };
-        return Ok(buf);
-    }
-    pub fn new(data: &'a [u8]) -> Result<BsdiffReader<'a>, binread::Error> {
-        let mut reader = Cursor::new(data);
-        let header = BsdiffFormat::read(&mut reader)?;
-        if header.is_bsdiff3_format() {
-            let mut buf = [0 as u8; 8];
-            reader.read_exact(&mut buf).unwrap();
-            let compressed_mask_size = as_u64_le(&buf);
-            let compressed_diff_size = header.compressed_diff_size;
-            let compressed_diff_data = &data[32 + 8 + header.compressed_ctrl_size as usize..]
-                [..header.compressed_diff_size as usize];
-            let decompressed_diff_size =
-                Self::decompress(compressed_diff_data, header.get_ctrl_compressor())
-                    .unwrap()
-                    .len();
-            let compressed_mask_data = &data[data.len() - compressed_mask_size as usize..];
-            let decompressed_mask_size =
-                Self::decompress(compressed_mask_data, CompressorType::Brotli)
-                    .unwrap()
-                    .len();
-            println!(
-                "Mask data: {}/{} = {}, diff data: {}/{} = {}",
-                compressed_mask_size,
-                decompressed_mask_size,
-                compressed_mask_size as f32 / decompressed_mask_size as f32,
-                compressed_diff_size,
-                decompressed_diff_size,
-                compressed_diff_size as f32 / decompressed_diff_size as f32,
-            );
-            return Err(binread::Error::Io(std::io::Error::new(
-                ErrorKind::InvalidData,
-                "unsupported bsdiff3 format",
-            )));
-        }
-        // header takes up 32 bytes, so control stream start at offset 32.
-        let decompressed_ctrl_stream = Self::decompress(&data[32..], header.get_ctrl_compressor())?;
-        if decompressed_ctrl_stream.len() % CONTROL_ENTRY_SIZE != 0 {
-            return Err(binread::Error::Io(std::io::Error::new(
-                ErrorKind::InvalidData,
-                format!(
-                    "Decompressed ctrl stream has length {}, which is not a multiple of {}",
-                    decompressed_ctrl_stream.len(),
-                    CONTROL_ENTRY_SIZE
-                ),
-            )));
+            return offsets;
+        });
+        // `offsets` has one more element than there are entries, the end
+        // of the new file.
+        let (starts, end) = offsets.split_at(offsets.len() - 1);
+        if offset >= end[0] {
+            self.position_at(starts.len());
+            return None;
         }
-        let compressed_diff_stream = &data[32 + header.compressed_ctrl_size as usize..]
-            [..header.compressed_diff_size as usize];
-        let decompressed_diff_stream =
-            Self::decompress(compressed_diff_stream, header.get_diff_compressor())?;
-        let diff_stream_size = decompressed_diff_stream.len();
-        let diff_stream_zero_count = decompressed_diff_stream
-            .into_iter()
-            .map(|x| (x == 0) as u32)
-            .sum::<u32>();
-        println!(
-            "Diff stream has {}/{} = {}% zeros",
-            diff_stream_zero_count,
-            diff_stream_size,
-            (diff_stream_zero_count as f64) / diff_stream_size as f64 * 100.0
-        );
+        // The last entry starting at or before `offset` is the one covering
+        // it; empty entries before it start at the same offset.
+        let index = starts.partition_point(|start| *start <= offset) - 1;
+        self.position_at(index);
+        return Some(starts[index]);
+    }
 
-        return Ok(BsdiffReader {
-            data,
-            decompressed_ctrl_stream,
-            header,
-        });
+    fn position_at(&mut self, index: usize) {
+        self.control_entry_reader
+            .set_position((index * CONTROL_ENTRY_SIZE) as u64);
+        self.remaining = self.limit.map(|limit| limit.saturating_sub(index));
+        self.truncated = false;
     }
-    pub fn control_entries(&self) -> ControlEntryIter {
-        let control_entry_reader = Cursor::new(&self.decompressed_ctrl_stream);
-        return ControlEntryIter::new(control_entry_reader, self.decompressed_ctrl_stream.len());
+
+    /// Whether iteration stopped at `max_control_entries` even though the
+    /// control stream has more entries.
+    pub fn is_truncated(&self) -> bool {
+        return self.truncated;
+    }
+}
+
+fn read_all<R: Read>(mut reader: R) -> Result<Vec<u8>, std::io::Error> {
+    let mut buf = Vec::new();
+    reader.read_to_end(&mut buf)?;
+    return Ok(buf);
+}
+
+// Some versions of brotli's reader end a truncated stream with a short read
+// rather than an error. Catch that by comparing what a brotli stream
+// decompressed to against what the control entries need from it.
+fn check_brotli_len(
+    name: &'static str,
+    compressor_type: CompressorType,
+    expected: u64,
+    found: u64,
+) -> Result<(), BsdumpError> {
+    if compressor_type == CompressorType::Brotli && found < expected {
+        return Err(BsdumpError::TruncatedStream {
+            name,
+            len: expected,
+            available: found,
+        });
+    }
+    return Ok(());
+}
+
+// The smallest `data` compresses to with any compiled codec, or its own
+// length if none is compiled.
+fn best_compressed_len(data: &[u8]) -> u64 {
+    return CompressorType::ALL
+        .iter()
+        .filter(|compressor_type| compressor_type.is_compiled())
+        .filter_map(|compressor_type| compress(data, *compressor_type).ok())
+        .map(|compressed| compressed.len() as u64)
+        .min()
+        .unwrap_or(data.len() as u64);
+}
+
+// Sums the diff and extra sizes of the whole entries in a decompressed ctrl
+// stream, saturating rather than overflowing on corrupt sizes.
+fn entry_sizes_total(ctrl_stream: &[u8]) -> (u64, u64) {
+    let mut totals = (0_u64, 0_u64);
+    for chunk in ctrl_stream.chunks_exact(CONTROL_ENTRY_SIZE) {
+        let diff_size = u64::from_le_bytes(chunk[0..8].try_into().unwrap());
+        let extra_size = u64::from_le_bytes(chunk[8..16].try_into().unwrap());
+        totals.0 = totals.0.saturating_add(diff_size);
+        totals.1 = totals.1.saturating_add(extra_size);
+    }
+    return totals;
+}
+
+// Counts the zero bytes `reader` yields in fixed size chunks, so the data is
+// never held in memory as a whole. Returns the zero count and total length.
+fn count_zeros<R: Read>(mut reader: R) -> Result<(u64, u64), std::io::Error> {
+    let mut buf = vec![0_u8; 64 * 1024];
+    let mut zeros = 0_u64;
+    let mut total = 0_u64;
+    loop {
+        let n = match reader.read(&mut buf) {
+            Ok(0) => return Ok((zeros, total)),
+            Ok(n) => n,
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        zeros += buf[..n].iter().filter(|byte| **byte == 0).count() as u64;
+        total += n as u64;
+    }
+}
+
+impl<'a> BsdiffReader<'a> {
+    #[cfg_attr(not(feature = "zstd"), allow(unused_variables))]
+    fn decoder<'r, R: Read + 'r>(
+        data: R,
+        compressor_type: CompressorType,
+        options: &BsdiffReaderOptions,
+    ) -> Result<Box<dyn Read + 'r>, std::io::Error> {
+        return match compressor_type {
+            #[cfg(feature = "brotli")]
+            CompressorType::Brotli => Ok(Box::new(brotli::Decompressor::new(
+                data, 4096, /* buffer size */
+            ))),
+            #[cfg(feature = "bz2")]
+            CompressorType::Bz2 => Ok(Box::new(BzDecoder::new(data))),
+            #[cfg(feature = "zstd")]
+            CompressorType::Zstd => {
+                let dictionary = options.zstd_dictionary.as_deref().unwrap_or(&[]);
+                Ok(Box::new(zstd::Decoder::with_dictionary(
+                    std::io::BufReader::new(data),
+                    dictionary,
+                )?))
+            }
+            #[cfg(feature = "gzip")]
+            CompressorType::Gzip => Ok(Box::new(flate2::read::GzDecoder::new(data))),
+            #[allow(unreachable_patterns)]
+            _ => Err(not_compiled(compressor_type)),
+        };
+    }
+    // Opens a decoder over the `name` stream `data`, reporting progress and
+    // tracing through `options` if requested.
+    fn open_decoder<'r>(
+        name: &'static str,
+        data: &'r [u8],
+        compressor_type: CompressorType,
+        options: &'r BsdiffReaderOptions,
+    ) -> Result<Box<dyn Read + 'r>, BsdumpError> {
+        let decoder = Self::open_untraced_decoder(data, compressor_type, options)?;
+        if options.trace.is_none() {
+            return Ok(decoder);
+        }
+        return Ok(Box::new(TraceReader {
+            inner: decoder,
+            trace: DecompressTrace {
+                stream: name,
+                compressor: compressor_type,
+                input_len: data.len() as u64,
+                output_len: 0,
+                zero_bytes: 0,
+                elapsed: Duration::ZERO,
+                error: None,
+            },
+            start: Instant::now(),
+            done: false,
+            options,
+        }));
+    }
+    fn open_untraced_decoder<'r>(
+        data: &'r [u8],
+        compressor_type: CompressorType,
+        options: &'r BsdiffReaderOptions,
+    ) -> Result<Box<dyn Read + 'r>, BsdumpError> {
+        compressor_type.require_compiled()?;
+        #[cfg(feature = "zstd")]
+        if compressor_type == CompressorType::Zstd && options.zstd_dictionary.is_none() {
+            // A frame that was compressed against a dictionary records the
+            // dictionary id in its header. Without the dictionary zstd only
+            // reports a generic decode failure, so catch it up front.
+            if let Some(dictionary_id) = zstd::zstd_safe::get_dict_id_from_frame(data) {
+                return Err(BsdumpError::MissingDictionary {
+                    dictionary_id: dictionary_id.get(),
+                });
+            }
+        }
+        if let (CompressorType::Brotli, Some(max)) = (compressor_type, options.max_brotli_window) {
+            // An unreadable header is left for the decoder to report.
+            if let Some(window_bits) = brotli_window_bits(data) {
+                let window_size = 1_u64 << window_bits;
+                if window_size > max {
+                    return Err(BsdumpError::BrotliWindowTooLarge { window_size, max });
+                }
+            }
+        }
+        if options.progress.is_none() {
+            return Ok(Self::decoder(data, compressor_type, options)?);
+        }
+        let reader = ProgressReader {
+            inner: data,
+            done: 0,
+            total: data.len() as u64,
+            options,
+        };
+        return Ok(Self::decoder(reader, compressor_type, options)?);
+    }
+    fn decompress(
+        name: &'static str,
+        data: &[u8],
+        compressor_type: CompressorType,
+        options: &BsdiffReaderOptions,
+    ) -> Result<Vec<u8>, BsdumpError> {
+        let decoder = Self::open_decoder(name, data, compressor_type, options)?;
+        return Ok(read_all(decoder)?);
+    }
+    pub fn new(data: &'a [u8]) -> Result<BsdiffReader<'a>, BsdumpError> {
+        return Self::parse(PatchData::Borrowed(data), BsdiffReaderOptions::default());
+    }
+
+    /// Reads the header from `reader` and streams the control entries as
+    /// the ctrl stream decompresses, for input that can't be seeked or held
+    /// in memory. Call `ControlEntryStream::into_inner` afterwards to read
+    /// the diff stream and what follows.
+    pub fn stream_from<'r, R: Read + 'r>(
+        reader: R,
+    ) -> Result<ControlEntryStream<'r, R>, BsdumpError> {
+        return ControlEntryStream::new(reader, &BsdiffReaderOptions::default());
+    }
+
+    /// `stream_from` with options. The zstd dictionary, `skip_prefix`,
+    /// `magic_len`, `max_control_entries` and `allow_partial_ctrl_entry`
+    /// apply. The others, including `max_brotli_window` and the progress
+    /// and trace callbacks, are ignored.
+    pub fn stream_from_with_options<'r, R: Read + 'r>(
+        reader: R,
+        options: BsdiffReaderOptions,
+    ) -> Result<ControlEntryStream<'r, R>, BsdumpError> {
+        return ControlEntryStream::new(reader, &options);
+    }
+
+    /// Starts configuring a reader, see `BsdiffReaderBuilder`.
+    pub fn builder() -> BsdiffReaderBuilder {
+        return BsdiffReaderBuilder::new();
+    }
+
+    pub fn with_options(
+        data: &'a [u8],
+        options: BsdiffReaderOptions,
+    ) -> Result<BsdiffReader<'a>, BsdumpError> {
+        return Self::parse(PatchData::Borrowed(data), options);
+    }
+
+    /// Parses a patch held in a `bytes::Bytes` buffer. The reader keeps the
+    /// handle alive, so the compressed stream accessors can hand out cheap
+    /// `Bytes` views instead of copying.
+    #[cfg(feature = "bytes")]
+    pub fn from_bytes(data: bytes::Bytes) -> Result<BsdiffReader<'static>, BsdumpError> {
+        return BsdiffReader::parse(PatchData::Shared(data), BsdiffReaderOptions::default());
+    }
+
+    // Reads and validates the header. Also returns the compressed mask size
+    // for BDF3 patches, zero otherwise.
+    fn parse_header(data: &[u8]) -> Result<(BsdiffFormat, u64), BsdumpError> {
+        return Self::parse_long_magic_header(data, 0);
+    }
+
+    // `parse_header` for a magic with `extra_magic_len` bytes after the
+    // standard 8, which are skipped.
+    fn parse_long_magic_header(
+        data: &[u8],
+        extra_magic_len: usize,
+    ) -> Result<(BsdiffFormat, u64), BsdumpError> {
+        let mut header_bytes = data[..data.len().min(8)].to_vec();
+        let fields = data.get(8 + extra_magic_len..).unwrap_or_default();
+        let fields_len = (HEADER_SIZE + BSDIFF3_MASK_SIZE_LEN) as usize - 8;
+        header_bytes.extend_from_slice(&fields[..fields.len().min(fields_len)]);
+        // Check the magic first, so a short file that isn't a patch at all
+        // is reported as such rather than as a truncated header.
+        let mut reader = Cursor::new(&header_bytes[..]);
+        let header = BsdiffFormat::read_validated(&mut reader)?;
+        header.check_plausible(data.len() as u64)?;
+        let mut compressed_mask_size = 0;
+        if header.is_bsdiff3_format() {
+            let mut buf = [0_u8; 8];
+            reader.read_exact(&mut buf)?;
+            compressed_mask_size = as_u64_le(&buf);
+        }
+        return Ok((header, compressed_mask_size));
+    }
+
+    /// Length of the patch at the start of `data`. The header does not
+    /// record the size of the extra stream, so this decodes the extra
+    /// stream to find where it ends; bytes after the patch are ignored.
+    pub fn patch_len(data: &[u8]) -> Result<usize, BsdumpError> {
+        let (header, compressed_mask_size) = Self::parse_header(data)?;
+        let ranges = header.stream_ranges(data.len() as u64);
+        let extra_data = stream_slice(data, &ranges.extra, "extra")?;
+        header.get_extra_compressor().require_compiled()?;
+        let extra_len = compressed_stream_len(extra_data, header.get_extra_compressor())?;
+        let len = (ranges.extra.start + extra_len as u64).saturating_add(compressed_mask_size);
+        if len > data.len() as u64 {
+            return Err(BsdumpError::CorruptPatch(format!(
+                "patch needs {} bytes, only {} available",
+                len,
+                data.len()
+            )));
+        }
+        return Ok(len as usize);
+    }
+
+    /// Looks for a `compressed_diff_size` that is off by one, which some
+    /// generators write, leaving the extra stream misaligned. If the patch
+    /// doesn't parse or its extra stream doesn't decompress, but exactly one
+    /// of the sizes next to the declared one fixes both, returns that size.
+    /// Returns `None` for a patch that is fine as it is, and the original
+    /// error if no neighbouring size helps.
+    pub fn find_diff_size_repair(data: &[u8]) -> Result<Option<u64>, BsdumpError> {
+        let check = |data: &[u8]| -> Result<(), BsdumpError> {
+            BsdiffReader::new(data)?.decompressed_extra_stream()?;
+            return Ok(());
+        };
+        let error = match check(data) {
+            Ok(()) => return Ok(None),
+            Err(e) => e,
+        };
+        let declared = BsdiffFormat::parse_unchecked(data)?.compressed_diff_size;
+        let fixes: Vec<u64> = [declared.checked_sub(1), declared.checked_add(1)]
+            .iter()
+            .flatten()
+            .copied()
+            .filter(|size| check(&Self::with_compressed_diff_size(data, *size)).is_ok())
+            .collect();
+        return match fixes[..] {
+            [size] => Ok(Some(size)),
+            _ => Err(error),
+        };
+    }
+
+    /// A copy of the patch in `data` with `compressed_diff_size` in the
+    /// header replaced by `size`, e.g. one found by `find_diff_size_repair`.
+    /// `data` must hold at least a full header.
+    pub fn with_compressed_diff_size(data: &[u8], size: u64) -> Vec<u8> {
+        let mut patched = data.to_vec();
+        // The field follows the magic and `compressed_ctrl_size`.
+        patched[16..24].copy_from_slice(&size.to_le_bytes());
+        return patched;
+    }
+
+    /// Parses every patch in a buffer of back to back patches.
+    pub fn parse_all(data: &'a [u8]) -> Result<Vec<BsdiffReader<'a>>, BsdumpError> {
+        let mut readers = Vec::new();
+        let mut offset = 0;
+        while offset < data.len() {
+            let len = Self::patch_len(&data[offset..])?;
+            let mut reader = Self::new(&data[offset..offset + len])?;
+            reader.base_offset = offset as u64;
+            readers.push(reader);
+            offset += len;
+        }
+        return Ok(readers);
+    }
+
+    /// Parses only the patch at index `n` of a buffer of back to back
+    /// patches, like `parse_all(data)?[n]`. The patches before it are
+    /// skipped with `patch_len`, which only decodes their extra streams to
+    /// find where they end; their ctrl and diff streams are not touched.
+    pub fn parse_nth(data: &'a [u8], n: usize) -> Result<BsdiffReader<'a>, BsdumpError> {
+        let mut offset = 0;
+        let mut index = 0;
+        loop {
+            if offset == data.len() {
+                return Err(BsdumpError::CorruptPatch(format!(
+                    "asked for patch {}, but the buffer holds only {}",
+                    n, index
+                )));
+            }
+            let len = Self::patch_len(&data[offset..])?;
+            if index == n {
+                let mut reader = Self::new(&data[offset..offset + len])?;
+                reader.base_offset = offset as u64;
+                return Ok(reader);
+            }
+            offset += len;
+            index += 1;
+        }
+    }
+
+    /// Offset of this patch within the buffer passed to `parse_all` or
+    /// `parse_nth`, or the length of the prefix skipped with
+    /// `BsdiffReaderOptions::skip_prefix`.
+    /// Zero for patches parsed on their own.
+    pub fn base_offset(&self) -> u64 {
+        return self.base_offset;
+    }
+
+    fn parse(
+        patch_data: PatchData<'a>,
+        options: BsdiffReaderOptions,
+    ) -> Result<BsdiffReader<'a>, BsdumpError> {
+        let prefix_len = options.skip_prefix;
+        if prefix_len > patch_data.as_slice().len() {
+            return Err(BsdumpError::CorruptPatch(format!(
+                "prefix of {} bytes is longer than the {} byte input",
+                prefix_len,
+                patch_data.as_slice().len()
+            )));
+        }
+        // Everything below works on the patch alone, only `base_offset`
+        // remembers where it started.
+        let patch_data = patch_data.skip(prefix_len);
+        let data = patch_data.as_slice();
+        let (header, compressed_mask_size) =
+            Self::parse_long_magic_header(data, options.extra_magic_len)?;
+        let header_size = HEADER_SIZE + options.extra_magic_len as u64;
+        let diff_end = header
+            .stream_ranges_after(header_size, data.len() as u64)
+            .diff
+            .end;
+        if options.footer_len as u64 > (data.len() as u64).saturating_sub(diff_end) {
+            return Err(BsdumpError::CorruptPatch(format!(
+                "footer of {} bytes does not fit after the diff stream",
+                options.footer_len
+            )));
+        }
+        let ranges =
+            header.stream_ranges_after(header_size, (data.len() - options.footer_len) as u64);
+        let mut decompressed_mask_stream = None;
+        if header.is_bsdiff3_format() {
+            // The mask stream is stored at the very end, after the extra stream.
+            let available = ranges.extra.end - ranges.extra.start;
+            if compressed_mask_size > available {
+                return Err(BsdumpError::TruncatedStream {
+                    name: "mask",
+                    len: compressed_mask_size,
+                    available,
+                });
+            }
+            let mask_range = ranges.extra.end - compressed_mask_size..ranges.extra.end;
+            let compressed_mask_data = stream_slice(data, &mask_range, "mask")?;
+            let mask_stream = Self::decompress(
+                "mask",
+                compressed_mask_data,
+                CompressorType::Brotli,
+                &options,
+            )?;
+            decompressed_mask_stream = Some(mask_stream);
+        }
+        let decompressed_ctrl_stream = Self::decompress(
+            "ctrl",
+            stream_slice(data, &ranges.ctrl, "ctrl")?,
+            header.get_ctrl_compressor(),
+            &options,
+        )?;
+        if decompressed_ctrl_stream.len() % CONTROL_ENTRY_SIZE != 0
+            && !options.allow_partial_ctrl_entry
+        {
+            let len = decompressed_ctrl_stream.len();
+            check_brotli_len(
+                "ctrl",
+                header.get_ctrl_compressor(),
+                (len + CONTROL_ENTRY_SIZE - len % CONTROL_ENTRY_SIZE) as u64,
+                len as u64,
+            )?;
+            return Err(BsdumpError::Io(std::io::Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "Decompressed ctrl stream has length {}, which is not a multiple of {}",
+                    decompressed_ctrl_stream.len(),
+                    CONTROL_ENTRY_SIZE
+                ),
+            )));
+        }
+        // The entry iterators assume every whole entry decodes, which only
+        // fails for negative BSDIFF40 sizes.
+        if header.is_legacy_bsdiff_format() {
+            for (index, chunk) in decompressed_ctrl_stream
+                .chunks_exact(CONTROL_ENTRY_SIZE)
+                .enumerate()
+            {
+                if let Err(e) = ControlEntry::read_args(&mut Cursor::new(chunk), (true,)) {
+                    return Err(BsdumpError::CorruptPatch(format!(
+                        "BSDIFF40 control entry {}: {}",
+                        index, e
+                    )));
+                }
+            }
+        }
+        // Fail early on a diff stream that doesn't decompress.
+        let compressed_diff_stream = stream_slice(data, &ranges.diff, "diff")?;
+        let (_, diff_stream_size) = Self::stream_zeros(
+            "diff",
+            compressed_diff_stream,
+            header.get_diff_compressor(),
+            &options,
+        )?;
+        // The BDF3 mask strips zeros, so the stored diff stream is shorter
+        // than the entries read.
+        if !header.is_bsdiff3_format() {
+            check_brotli_len(
+                "diff",
+                header.get_diff_compressor(),
+                entry_sizes_total(&decompressed_ctrl_stream).0,
+                diff_stream_size,
+            )?;
+        }
+
+        return Ok(BsdiffReader {
+            data: patch_data,
+            decompressed_ctrl_stream,
+            compressed_mask_size,
+            decompressed_mask_stream,
+            base_offset: prefix_len as u64,
+            options,
+            target_offsets: OnceLock::new(),
+            diff_offsets: OnceLock::new(),
+            source_offsets: OnceLock::new(),
+            has_backward_seeks: OnceLock::new(),
+            diff_stream_len: diff_stream_size,
+            extra_stream_len: OnceLock::new(),
+            header,
+        });
+    }
+
+    /// See `BsdiffFormat::stream_ranges`. Accounts for a magic longer than
+    /// 8 bytes, see `BsdiffReaderOptions::magic_len`.
+    pub fn stream_ranges(&self, total_len: u64) -> StreamRanges {
+        let header_size = HEADER_SIZE + self.options.extra_magic_len as u64;
+        return self.header.stream_ranges_after(header_size, total_len);
+    }
+
+    /// The bytes of a long magic after the standard 8, such as a generator
+    /// version and flags, see `BsdiffReaderOptions::magic_len`. Empty for
+    /// the standard 8 byte magic.
+    pub fn magic_extension(&self) -> &[u8] {
+        return &self.data.as_slice()[8..][..self.options.extra_magic_len];
+    }
+
+    // All ranges were bounds checked in `parse`, so they can be used to
+    // index `data` directly.
+    fn local_stream_ranges(&self) -> StreamRanges {
+        return self.stream_ranges(self.footer_start() as u64);
+    }
+
+    fn footer_start(&self) -> usize {
+        return self.data.as_slice().len() - self.options.footer_len;
+    }
+
+    /// Size of the patch in bytes, including any footer but not a prefix
+    /// skipped with `BsdiffReaderOptions::skip_prefix`.
+    pub fn patch_size(&self) -> u64 {
+        return self.data.as_slice().len() as u64;
+    }
+
+    /// The trailing bytes set aside with `BsdiffReaderOptions::footer_len`,
+    /// empty if no footer was configured.
+    pub fn footer(&self) -> &[u8] {
+        return &self.data.as_slice()[self.footer_start()..];
+    }
+
+    fn compressed_ctrl_range(&self) -> Range<usize> {
+        let range = self.local_stream_ranges().ctrl;
+        return range.start as usize..range.end as usize;
+    }
+
+    fn compressed_diff_range(&self) -> Range<usize> {
+        let range = self.local_stream_ranges().diff;
+        return range.start as usize..range.end as usize;
+    }
+
+    fn compressed_extra_range(&self) -> Range<usize> {
+        let range = self.local_stream_ranges().extra;
+        return range.start as usize..(range.end - self.compressed_mask_size) as usize;
+    }
+
+    pub fn compressed_ctrl_stream(&self) -> &[u8] {
+        return &self.data.as_slice()[self.compressed_ctrl_range()];
+    }
+
+    pub fn compressed_diff_stream(&self) -> &[u8] {
+        return &self.data.as_slice()[self.compressed_diff_range()];
+    }
+
+    #[cfg(feature = "bytes")]
+    fn slice_bytes(&self, range: Range<usize>) -> bytes::Bytes {
+        return match &self.data {
+            PatchData::Borrowed(data) => bytes::Bytes::copy_from_slice(&data[range]),
+            PatchData::Shared(data) => data.slice(range),
+        };
+    }
+
+    /// Same as `compressed_ctrl_stream`, but returns a `Bytes` view. This is
+    /// zero-copy when the reader was created with `from_bytes`.
+    #[cfg(feature = "bytes")]
+    pub fn compressed_ctrl_bytes(&self) -> bytes::Bytes {
+        return self.slice_bytes(self.compressed_ctrl_range());
+    }
+
+    /// Same as `compressed_diff_stream`, but returns a `Bytes` view. This is
+    /// zero-copy when the reader was created with `from_bytes`.
+    #[cfg(feature = "bytes")]
+    pub fn compressed_diff_bytes(&self) -> bytes::Bytes {
+        return self.slice_bytes(self.compressed_diff_range());
+    }
+
+    fn control_entries_up_to(&self, limit: Option<usize>) -> ControlEntryIter<'_> {
+        let control_entry_reader = Cursor::new(&self.decompressed_ctrl_stream);
+        // Leave out a trailing partial entry, see `allow_partial_ctrl_entry`.
+        let whole_entries_len = self.num_control_entries() * CONTROL_ENTRY_SIZE;
+        return ControlEntryIter::new(
+            control_entry_reader,
+            whole_entries_len,
+            limit,
+            &self.target_offsets,
+            self.header.is_legacy_bsdiff_format(),
+        );
+    }
+
+    /// Iterates over every control entry, ignoring `max_control_entries`,
+    /// and yields a final `BsdumpError::TruncatedControlEntry` if the
+    /// control stream ends partway through an entry. Such streams are
+    /// only accepted with `BsdiffReaderOptions::allow_partial_ctrl_entry`.
+    pub fn control_entries_best_effort(&self) -> BestEffortControlEntryIter<'_> {
+        return BestEffortControlEntryIter {
+            chunks: self
+                .decompressed_ctrl_stream
+                .chunks(CONTROL_ENTRY_SIZE)
+                .enumerate(),
+            legacy: self.header.is_legacy_bsdiff_format(),
+        };
+    }
+
+    /// Bytes of the ctrl stream after the last whole control entry. Always
+    /// zero unless `BsdiffReaderOptions::lenient_control` let a partial
+    /// entry through.
+    pub fn trailing_ctrl_bytes(&self) -> usize {
+        return self.decompressed_ctrl_stream.len() % CONTROL_ENTRY_SIZE;
+    }
+
+    /// Total number of control entries, regardless of `max_control_entries`.
+    pub fn num_control_entries(&self) -> usize {
+        return self.decompressed_ctrl_stream.len() / CONTROL_ENTRY_SIZE;
+    }
+
+    /// Offset into the decompressed diff stream where the diff bytes of
+    /// control entry `index` start, so they can be sliced out directly.
+    /// `num_control_entries()` gives the end of the last entry. Offsets past
+    /// `u64::MAX` stick there. The offsets are computed on the first call.
+    /// Panics if `index` is larger.
+    pub fn diff_offset_for_entry(&self, index: usize) -> u64 {
+        let offsets = self.diff_offsets.get_or_init(|| {
+            let mut offsets = vec![0];
+            let mut diff_offset = 0_u64;
+            for entry in self.all_control_entries() {
+                diff_offset = diff_offset.saturating_add(entry.diff_size);
+                offsets.push(diff_offset);
+            }
+            return offsets;
+        });
+        return offsets[index];
+    }
+
+    /// Source offset control entry `index` starts reading at: the sum of
+    /// the diff sizes and offset increments of the entries before it.
+    /// Signed, since a malformed patch can seek before the start of the
+    /// source. `None` if the sum overflows an i64 at or before `index`.
+    /// `num_control_entries()` gives the offset after the last entry. The
+    /// offsets are computed on the first call. Panics if `index` is larger.
+    pub fn source_offset_for_entry(&self, index: usize) -> Option<i64> {
+        let offsets = self.source_offsets.get_or_init(|| {
+            let mut offsets = vec![Some(0)];
+            let mut source_offset = Some(0_i64);
+            for entry in self.all_control_entries() {
+                source_offset = source_offset
+                    .zip(i64::try_from(entry.diff_size).ok())
+                    .and_then(|(offset, diff_size)| offset.checked_add(diff_size))
+                    .and_then(|offset| offset.checked_add(entry.offset_increment));
+                offsets.push(source_offset);
+            }
+            return offsets;
+        });
+        return offsets[index];
+    }
+
+    /// Whether any control entry moves the source offset backwards. A patch
+    /// without backward seeks reads the source front to back, so it can be
+    /// prefetched sequentially. Scans every entry, regardless of
+    /// `max_control_entries`, on the first call only.
+    pub fn has_backward_seeks(&self) -> bool {
+        return *self.has_backward_seeks.get_or_init(|| {
+            return self
+                .all_control_entries()
+                .any(|entry| entry.offset_increment < 0);
+        });
+    }
+
+    /// Index ranges of runs of two or more consecutive identical control
+    /// entries. Correct generators rarely emit these, so they usually point
+    /// at a bloated patch.
+    pub fn duplicate_entry_runs(&self) -> Vec<Range<usize>> {
+        let mut runs = Vec::new();
+        let mut previous: Option<ControlEntry> = None;
+        let mut run_start = 0;
+        let mut len = 0;
+        for (index, entry) in self.control_entries().enumerate() {
+            if previous.as_ref() != Some(&entry) {
+                if index - run_start > 1 {
+                    runs.push(run_start..index);
+                }
+                run_start = index;
+            }
+            previous = Some(entry);
+            len = index + 1;
+        }
+        if len - run_start > 1 {
+            runs.push(run_start..len);
+        }
+        return runs;
+    }
+
+    /// Iterates over the control entries, stopping after
+    /// `BsdiffReaderOptions::max_control_entries` if set. Entries come in
+    /// the order they are stored in the ctrl stream, which is the order
+    /// they are applied in. Every call starts over from the first entry,
+    /// so repeated and interleaved iterations yield the same sequence.
+    pub fn control_entries(&self) -> ControlEntryIter<'_> {
+        return self.control_entries_up_to(self.options.max_control_entries);
+    }
+
+    // Every control entry regardless of `max_control_entries`, for
+    // operations that are wrong on a partial view, like applying.
+    pub(crate) fn all_control_entries(&self) -> ControlEntryIter<'_> {
+        return self.control_entries_up_to(None);
+    }
+
+    fn with_offsets(entries: ControlEntryIter<'_>) -> ControlEntryOffsetIter<'_> {
+        return ControlEntryOffsetIter {
+            entries,
+            index: 0,
+            source_offset: 0,
+            target_offset: 0,
+        };
+    }
+
+    /// Like `control_entries`, but also tracks the source and target
+    /// offsets each entry starts at.
+    pub fn control_entries_with_offsets(&self) -> ControlEntryOffsetIter<'_> {
+        return Self::with_offsets(self.control_entries());
+    }
+
+    fn all_control_entries_with_offsets(&self) -> ControlEntryOffsetIter<'_> {
+        return Self::with_offsets(self.all_control_entries());
+    }
+
+    /// Runs of stored diff bytes described by the BDF3 mask stream. Empty
+    /// for formats without a mask.
+    pub fn mask_entries(&self) -> MaskEntryIter<'_> {
+        let mask = self.decompressed_mask_stream.as_deref().unwrap_or(&[]);
+        return MaskEntryIter { mask, bit: 0 };
+    }
+
+    /// Number of diff stream bytes the BDF3 mask strips out, i.e. the zero
+    /// bytes left out of the stored diff stream. Zero for formats without
+    /// a mask.
+    pub fn masked_bytes(&self) -> u64 {
+        let mask = self.decompressed_mask_stream.as_deref().unwrap_or(&[]);
+        let stored: u64 = self.mask_entries().map(|run| run.len).sum();
+        return mask.len() as u64 * 8 - stored;
+    }
+
+    pub fn has_mask(&self) -> bool {
+        return self.decompressed_mask_stream.is_some();
+    }
+
+    pub fn decompressed_ctrl_stream(&self) -> &[u8] {
+        return &self.decompressed_ctrl_stream;
+    }
+
+    /// Decompresses the diff stream. For BDF3 patches the zero bytes
+    /// stripped by the mask are put back.
+    pub fn decompressed_diff_stream(&self) -> Result<Vec<u8>, BsdumpError> {
+        let diff_stream = Self::decompress(
+            "diff",
+            &self.data.as_slice()[self.compressed_diff_range()],
+            self.header.get_diff_compressor(),
+            &self.options,
+        )?;
+        return match &self.decompressed_mask_stream {
+            Some(mask) => expand_masked_diff_stream(&diff_stream, mask),
+            None => Ok(diff_stream),
+        };
+    }
+
+    /// Number of zero bytes in the stored diff stream and its length,
+    /// counted while decompressing so the stream is never held in memory.
+    /// For BDF3 patches this covers only the bytes the mask kept.
+    pub fn diff_stream_zeros(&self) -> Result<(u64, u64), BsdumpError> {
+        return Self::stream_zeros(
+            "diff",
+            &self.data.as_slice()[self.compressed_diff_range()],
+            self.header.get_diff_compressor(),
+            &self.options,
+        );
+    }
+
+    fn stream_zeros(
+        name: &'static str,
+        data: &[u8],
+        compressor_type: CompressorType,
+        options: &BsdiffReaderOptions,
+    ) -> Result<(u64, u64), BsdumpError> {
+        let decoder = Self::open_decoder(name, data, compressor_type, options)?;
+        return Ok(count_zeros(decoder)?);
+    }
+
+    /// Estimates the memory `apply` needs: the decompressed streams held at
+    /// once plus the output buffer. Decoder state and the stored BDF3 diff
+    /// stream, which is freed once expanded, are not counted. The extra
+    /// stream is decompressed once, without being kept, to learn its size.
+    pub fn apply_memory_estimate(&self) -> Result<MemoryEstimate, BsdumpError> {
+        let extra_stream = self.extra_stream_len()?;
+        let mask_stream = self.decompressed_mask_stream.as_ref().map(Vec::len);
+        return Ok(MemoryEstimate {
+            ctrl_stream: self.decompressed_ctrl_stream.len() as u64,
+            mask_stream: mask_stream.unwrap_or(0) as u64,
+            diff_stream: self.expanded_diff_stream_len(),
+            extra_stream,
+            output: self.header.new_file_size,
+        });
+    }
+
+    // Length of the diff stream the control entries read from, i.e. with
+    // the bytes a BDF3 mask strips put back.
+    fn expanded_diff_stream_len(&self) -> u64 {
+        return self
+            .decompressed_mask_stream
+            .as_ref()
+            .map_or(self.diff_stream_len, |mask| mask.len() as u64 * 8);
+    }
+
+    // Decompressed length of the extra stream, decompressing it without
+    // keeping the data the first time.
+    fn extra_stream_len(&self) -> Result<u64, BsdumpError> {
+        if let Some(len) = self.extra_stream_len.get() {
+            return Ok(*len);
+        }
+        let (_, len) = Self::stream_zeros(
+            "extra",
+            self.compressed_extra_stream(),
+            self.header.get_extra_compressor(),
+            &self.options,
+        )?;
+        return Ok(*self.extra_stream_len.get_or_init(|| len));
+    }
+
+    /// The non-empty streams whose compressed size is at least their
+    /// decompressed size, where the codec hurt rather than helped. Streams
+    /// of a few bytes always end up here, since every codec adds framing.
+    /// The diff stream is measured as stored, i.e. after a BDF3 mask.
+    pub fn expanded_streams(&self) -> Result<Vec<ExpandedStream>, BsdumpError> {
+        let mut streams = vec![
+            (
+                "ctrl",
+                self.header.get_ctrl_compressor(),
+                self.compressed_ctrl_stream().len(),
+                self.decompressed_ctrl_stream.len() as u64,
+            ),
+            (
+                "diff",
+                self.header.get_diff_compressor(),
+                self.compressed_diff_stream().len(),
+                self.diff_stream_len,
+            ),
+            (
+                "extra",
+                self.header.get_extra_compressor(),
+                self.compressed_extra_stream().len(),
+                self.extra_stream_len()?,
+            ),
+        ];
+        if let Some(mask) = &self.decompressed_mask_stream {
+            streams.push((
+                "mask",
+                CompressorType::Brotli,
+                self.compressed_mask_size as usize,
+                mask.len() as u64,
+            ));
+        }
+        let expanded = streams
+            .into_iter()
+            .filter(|(_, _, compressed_len, decompressed_len)| {
+                *decompressed_len > 0 && *compressed_len as u64 >= *decompressed_len
+            })
+            .map(
+                |(stream, compressor, compressed_len, decompressed_len)| ExpandedStream {
+                    stream,
+                    compressor,
+                    compressed_len: compressed_len as u64,
+                    decompressed_len,
+                },
+            )
+            .collect();
+        return Ok(expanded);
+    }
+
+    pub fn decompressed_extra_stream(&self) -> Result<Vec<u8>, BsdumpError> {
+        let extra_stream = Self::decompress(
+            "extra",
+            &self.data.as_slice()[self.compressed_extra_range()],
+            self.header.get_extra_compressor(),
+            &self.options,
+        )?;
+        check_brotli_len(
+            "extra",
+            self.header.get_extra_compressor(),
+            entry_sizes_total(&self.decompressed_ctrl_stream).1,
+            extra_stream.len() as u64,
+        )?;
+        return Ok(extra_stream);
+    }
+
+    /// The compressed extra stream: everything after the diff stream, minus
+    /// the trailing mask stream of BDF3 patches.
+    pub fn compressed_extra_stream(&self) -> &[u8] {
+        return &self.data.as_slice()[self.compressed_extra_range()];
+    }
+
+    /// Same as `compressed_extra_stream`, but returns a `Bytes` view. This is
+    /// zero-copy when the reader was created with `from_bytes`.
+    #[cfg(feature = "bytes")]
+    pub fn compressed_extra_bytes(&self) -> bytes::Bytes {
+        return self.slice_bytes(self.compressed_extra_range());
     }
 
     pub fn get_new_file_size(&self) -> u64 {
         return self.header.new_file_size;
     }
+
+    /// The source range read by each control entry, in apply order. Useful
+    /// to prefetch the source before applying.
+    pub fn source_read_ranges(
+        &self,
+    ) -> std::iter::Map<ControlEntryOffsetIter<'_>, fn(OffsetControlEntry) -> Range<u64>> {
+        return self
+            .all_control_entries_with_offsets()
+            .map(|entry| entry.source_range());
+    }
+
+    /// The source ranges to fetch before applying the patch to a source of
+    /// `source_len` bytes, in read order: `source_read_ranges` clipped to
+    /// the source, without empty reads, and with reads that continue where
+    /// the previous one ended merged. Ranges are not deduplicated, a range
+    /// read twice appears twice.
+    pub fn source_plan(&self, source_len: u64) -> Vec<Range<u64>> {
+        let mut plan: Vec<Range<u64>> = Vec::new();
+        for range in self.source_read_ranges() {
+            let range = range.start.min(source_len)..range.end.min(source_len);
+            if range.is_empty() {
+                continue;
+            }
+            match plan.last_mut() {
+                Some(last) if last.end == range.start => last.end = range.end,
+                _ => plan.push(range),
+            }
+        }
+        return plan;
+    }
+
+    /// The parts of a source of `source_len` bytes that no control entry
+    /// reads, sorted and merged. A large unread region can mean the patch
+    /// was made against a different source than expected. Reads past
+    /// `source_len` are ignored.
+    pub fn unreferenced_source_ranges(&self, source_len: u64) -> Vec<Range<u64>> {
+        let mut reads: Vec<Range<u64>> = self
+            .source_read_ranges()
+            .filter(|range| !range.is_empty())
+            .collect();
+        reads.sort_unstable_by_key(|range| range.start);
+        let mut unread = Vec::new();
+        // Everything before `pos` is known to be read or already recorded.
+        let mut pos = 0;
+        for range in reads {
+            if range.start >= source_len {
+                break;
+            }
+            if range.start > pos {
+                unread.push(pos..range.start);
+            }
+            pos = pos.max(range.end);
+        }
+        if pos < source_len {
+            unread.push(pos..source_len);
+        }
+        return unread;
+    }
+
+    /// Average and largest `diff_size` and `extra_size` over every control
+    /// entry, regardless of `max_control_entries`.
+    pub fn entry_size_stats(&self) -> EntrySizeStats {
+        let mut stats = EntrySizeStats::default();
+        let mut count = 0_u64;
+        // Sizes straight from a malformed patch can add up past u64.
+        let mut total_diff = 0_u128;
+        let mut total_extra = 0_u128;
+        for entry in self.all_control_entries() {
+            count += 1;
+            total_diff += u128::from(entry.diff_size);
+            total_extra += u128::from(entry.extra_size);
+            stats.max_diff = stats.max_diff.max(entry.diff_size);
+            stats.max_extra = stats.max_extra.max(entry.extra_size);
+        }
+        if count > 0 {
+            stats.avg_diff = total_diff as f64 / count as f64;
+            stats.avg_extra = total_extra as f64 / count as f64;
+        }
+        return stats;
+    }
+
+    /// How the source is read while applying, over every control entry.
+    /// Many short reads and long seeks make a patch slow to apply from
+    /// slow storage even if it is small. Reading starts at offset zero, so
+    /// a first read elsewhere counts as a seek.
+    pub fn source_fragmentation(&self) -> SourceFragmentation {
+        let mut stats = SourceFragmentation::default();
+        let mut total_read = 0_u128;
+        let mut position = 0_i64;
+        for entry in self.all_control_entries_with_offsets() {
+            let diff_size = entry.entry.diff_size;
+            if diff_size == 0 {
+                continue;
+            }
+            stats.num_reads += 1;
+            total_read += u128::from(diff_size);
+            if entry.source_offset != position {
+                stats.num_seeks += 1;
+                stats.seek_distance_sum = stats
+                    .seek_distance_sum
+                    .saturating_add(entry.source_offset.abs_diff(position));
+            }
+            position = entry
+                .source_offset
+                .saturating_add(i64::try_from(diff_size).unwrap_or(i64::MAX));
+        }
+        if stats.num_reads > 0 {
+            stats.avg_read_len = total_read as f64 / stats.num_reads as f64;
+        }
+        return stats;
+    }
+
+    /// Every control entry with what applying it amounts to, in file order.
+    /// Scans the diff bytes of each entry to find pure copies, entries
+    /// whose diff bytes are all zero. Many of those mean the diff stream
+    /// spends bytes on zero runs a better generator would leave out.
+    /// Decompresses the diff stream.
+    pub fn operations(&self) -> Result<Vec<EntryOperation>, BsdumpError> {
+        let diff_stream = self.decompressed_diff_stream()?;
+        let diff_total = self
+            .all_control_entries()
+            .fold(0_u64, |total, entry| total.saturating_add(entry.diff_size));
+        if diff_total > diff_stream.len() as u64 {
+            return Err(BsdumpError::DiffLengthMismatch {
+                expected: diff_total,
+                found: diff_stream.len() as u64,
+            });
+        }
+        let mut diff_pos = 0;
+        let mut operations = Vec::new();
+        for entry in self.all_control_entries_with_offsets() {
+            let diff = &diff_stream[diff_pos..][..entry.entry.diff_size as usize];
+            diff_pos += diff.len();
+            operations.push(EntryOperation {
+                is_pure_copy: !diff.is_empty() && diff.iter().all(|byte| *byte == 0),
+                entry,
+            });
+        }
+        return Ok(operations);
+    }
+
+    /// The `top_n` control entries that are most expensive to apply, most
+    /// expensive first, without applying anything. An entry costs
+    /// `DIFF_BYTE_COST` per diff byte, `EXTRA_BYTE_COST` per extra byte and
+    /// `SEEK_COST` if its `offset_increment` is non-zero. Entries of equal
+    /// cost keep their file order. Complements `source_fragmentation` when
+    /// looking for why a patch applies slowly.
+    pub fn cost_profile(&self, top_n: usize) -> Vec<EntryCost> {
+        let mut costs: Vec<EntryCost> = self
+            .all_control_entries_with_offsets()
+            .map(|entry| {
+                let mut cost = entry
+                    .entry
+                    .diff_size
+                    .saturating_mul(DIFF_BYTE_COST)
+                    .saturating_add(entry.entry.extra_size.saturating_mul(EXTRA_BYTE_COST));
+                if entry.entry.offset_increment != 0 {
+                    cost = cost.saturating_add(SEEK_COST);
+                }
+                return EntryCost { entry, cost };
+            })
+            .collect();
+        costs.sort_by_key(|cost| std::cmp::Reverse(cost.cost));
+        costs.truncate(top_n);
+        return costs;
+    }
+
+    /// Share of the new file that comes from the extra stream rather than
+    /// from the source, over every control entry. Close to 1 means the
+    /// patch barely reuses the source. Zero for an empty new file.
+    pub fn new_data_fraction(&self) -> f64 {
+        let new_file_size = self.header.new_file_size;
+        if new_file_size == 0 {
+            return 0.0;
+        }
+        let extra_bytes = self
+            .all_control_entries()
+            .fold(0_u64, |total, entry| total.saturating_add(entry.extra_size));
+        return extra_bytes as f64 / new_file_size as f64;
+    }
+
+    /// Whether no control entry reads the source, i.e. every `diff_size`
+    /// is zero. Such a patch is a compressed copy of the new file and can
+    /// be applied to any source. Checks every entry, regardless of
+    /// `max_control_entries`.
+    pub fn is_full_image(&self) -> bool {
+        return self.all_control_entries().all(|entry| entry.diff_size == 0);
+    }
+
+    /// Estimates the smallest this patch could get by recompressing it:
+    /// the size of a BSDF2 patch with the same entries, whose ctrl, diff
+    /// and extra streams each use the compiled codec that compresses them
+    /// best, as `--what-if` lists. Compare with the patch size to see the
+    /// headroom. A patch compressed at a higher level than this crate uses
+    /// can already be smaller than the estimate.
+    pub fn minimum_size_estimate(&self) -> Result<u64, BsdumpError> {
+        let diff_stream = self.decompressed_diff_stream()?;
+        let extra_stream = self.decompressed_extra_stream()?;
+        let mut size = HEADER_SIZE;
+        for stream in [&self.decompressed_ctrl_stream, &diff_stream, &extra_stream] {
+            size += best_compressed_len(stream);
+        }
+        return Ok(size);
+    }
+
+    /// Header, sizes, entry statistics, diff stream statistics and
+    /// `self_check` results in one struct, e.g. to store per patch.
+    pub fn report(&self) -> PatchReport {
+        return PatchReport::new(self);
+    }
+
+    /// A fixed size digest of the patch for indexing, see `SummaryRecord`
+    /// for the layout and the decoder.
+    pub fn summary_record(&self) -> [u8; SUMMARY_RECORD_LEN] {
+        return SummaryRecord::new(self).encode();
+    }
+
+    /// A 128 bit FNV-1a hash over the logical content of the patch:
+    /// `new_file_size`, every control entry, and the diff and extra bytes
+    /// the entries read. The format, the compressors, the compressed sizes
+    /// and any stream bytes past what the entries read are left out, so
+    /// copies of the same delta compressed differently, or converted
+    /// between BSDIFF40, BSDF2 and BDF3, get the same id. The id is stable
+    /// across versions and platforms, but not cryptographic: good for
+    /// deduplicating a cache, not for telling apart patches crafted to
+    /// collide. Decompresses the diff and extra streams.
+    pub fn logical_id(&self) -> Result<u128, BsdumpError> {
+        let diff_stream = self.decompressed_diff_stream()?;
+        let extra_stream = self.decompressed_extra_stream()?;
+        self.check_stream_lengths_of(diff_stream.len(), extra_stream.len())?;
+        let mut hasher = Fnv1a128::new();
+        // Versioned, so a change to the layout below can't collide with
+        // ids computed before it.
+        hasher.update(b"bsdump logical id v2\0");
+        hasher.update(&self.header.new_file_size.to_le_bytes());
+        hasher.update(&(self.num_control_entries() as u64).to_le_bytes());
+        let mut diff_total = 0_usize;
+        let mut extra_total = 0_usize;
+        for entry in self.all_control_entries() {
+            hasher.update(&entry.diff_size.to_le_bytes());
+            hasher.update(&entry.extra_size.to_le_bytes());
+            hasher.update(&entry.offset_increment.to_le_bytes());
+            diff_total += entry.diff_size as usize;
+            extra_total += entry.extra_size as usize;
+        }
+        hasher.update(&diff_stream[..diff_total]);
+        hasher.update(&extra_stream[..extra_total]);
+        return Ok(hasher.0);
+    }
+
+    /// The header and control entries as a protobuf message, see
+    /// `proto/bsdump.proto` and `proto::decode`.
+    #[cfg(feature = "proto")]
+    pub fn to_proto(&self) -> Vec<u8> {
+        return proto::encode(self);
+    }
+
+    /// Checks everything that can be checked without the source: the
+    /// control stream holds whole entries, no size is negative in BSDIFF40's
+    /// sign bit encoding or past `i64::MAX` in the newer formats, the
+    /// entries fit in the diff and extra streams and
+    /// produce exactly `new_file_size` bytes. Unlike the other validation
+    /// methods this keeps going and returns every problem found.
+    pub fn self_check(&self) -> Result<(), Vec<BsdumpError>> {
+        let mut problems = Vec::new();
+        if let Some(Err(e)) = self.control_entries_best_effort().last() {
+            problems.push(e);
+        }
+        let diff_len = self
+            .decompressed_diff_stream()
+            .map(|stream| stream.len() as u64)
+            .map_err(|e| problems.push(e))
+            .ok();
+        let extra_len = self
+            .decompressed_extra_stream()
+            .map(|stream| stream.len() as u64)
+            .map_err(|e| problems.push(e))
+            .ok();
+        let mut diff_total = 0_u64;
+        let mut extra_total = 0_u64;
+        for (i, entry) in self.all_control_entries().enumerate() {
+            for (name, value) in [
+                ("diff_size", entry.diff_size),
+                ("extra_size", entry.extra_size),
+            ] {
+                if value <= i64::MAX as u64 {
+                    continue;
+                }
+                // Only BSDIFF40 sizes are sign bit encoded, BSDF2 and BDF3
+                // store plain u64s that bspatch reads as i64.
+                let problem = if self.header.is_legacy_bsdiff_format() {
+                    format!(
+                        "control entry {} has negative {} {}",
+                        i,
+                        name,
+                        decode_bsdiff_int(value)
+                    )
+                } else {
+                    format!(
+                        "control entry {} has {} {}, more than i64::MAX",
+                        i, name, value
+                    )
+                };
+                problems.push(BsdumpError::CorruptPatch(problem));
+            }
+            diff_total = diff_total.saturating_add(entry.diff_size);
+            extra_total = extra_total.saturating_add(entry.extra_size);
+        }
+        if let Some(found) = diff_len.filter(|len| diff_total > *len) {
+            problems.push(BsdumpError::DiffLengthMismatch {
+                expected: diff_total,
+                found,
+            });
+        }
+        if let Some(found) = extra_len.filter(|len| extra_total > *len) {
+            problems.push(BsdumpError::ExtraLengthMismatch {
+                expected: extra_total,
+                found,
+            });
+        }
+        let produced = diff_total.saturating_add(extra_total);
+        if produced != self.header.new_file_size {
+            problems.push(BsdumpError::CorruptPatch(format!(
+                "control entries produce {} bytes, expected {}",
+                produced, self.header.new_file_size
+            )));
+        }
+        if problems.is_empty() {
+            return Ok(());
+        }
+        return Err(problems);
+    }
+
+    /// Checks that the diff and extra streams hold at least as many bytes as
+    /// the control entries read from them. Streams may be longer, e.g. a
+    /// BDF3 diff stream is padded to whole mask bytes.
+    pub fn check_stream_lengths(&self) -> Result<(), BsdumpError> {
+        let diff_len = self.decompressed_diff_stream()?.len();
+        let extra_len = self.decompressed_extra_stream()?.len();
+        return self.check_stream_lengths_of(diff_len, extra_len);
+    }
+
+    // `check_stream_lengths` for callers that already decompressed the streams.
+    fn check_stream_lengths_of(
+        &self,
+        diff_len: usize,
+        extra_len: usize,
+    ) -> Result<(), BsdumpError> {
+        let (diff_total, extra_total) =
+            self.all_control_entries()
+                .fold((0_u64, 0_u64), |(diff, extra), entry| {
+                    (
+                        diff.saturating_add(entry.diff_size),
+                        extra.saturating_add(entry.extra_size),
+                    )
+                });
+        if diff_total > diff_len as u64 {
+            return Err(BsdumpError::DiffLengthMismatch {
+                expected: diff_total,
+                found: diff_len as u64,
+            });
+        }
+        if extra_total > extra_len as u64 {
+            return Err(BsdumpError::ExtraLengthMismatch {
+                expected: extra_total,
+                found: extra_len as u64,
+            });
+        }
+        return Ok(());
+    }
+
+    /// Walks the source and target pointers together and checks that every
+    /// source read stays within `[0, source_len]` and every target write
+    /// within `[0, new_file_size]`. Returns the first entry that doesn't.
+    pub fn validate_against_source(&self, source_len: u64) -> Result<(), BsdumpError> {
+        let new_file_size = self.header.new_file_size;
+        for entry in self.all_control_entries_with_offsets() {
+            let diff_size = entry.entry.diff_size;
+            // A read or write whose end doesn't fit in a u64 is out of
+            // bounds of any file.
+            let source_end = u64::try_from(entry.source_offset)
+                .ok()
+                .and_then(|start| start.checked_add(diff_size));
+            let source_ok = diff_size == 0 || matches!(source_end, Some(end) if end <= source_len);
+            let target_end = entry
+                .target_offset
+                .checked_add(diff_size)
+                .and_then(|end| end.checked_add(entry.entry.extra_size));
+            let target_ok = matches!(target_end, Some(end) if end <= new_file_size);
+            if !source_ok || !target_ok {
+                return Err(BsdumpError::EntryOutOfBounds {
+                    entry,
+                    source_len,
+                    new_file_size,
+                });
+            }
+        }
+        return Ok(());
+    }
+
+    /// Checks that the patch can be applied to a source of `source_len`
+    /// bytes without touching the source: every entry stays in bounds and
+    /// the entries produce exactly `new_file_size` bytes. Returns the size
+    /// of the new file.
+    pub fn check_applicable(&self, source_len: u64) -> Result<u64, BsdumpError> {
+        self.validate_against_source(source_len)?;
+        let produced = self.all_control_entries().try_fold(0_u64, |total, entry| {
+            return total
+                .checked_add(entry.diff_size)?
+                .checked_add(entry.extra_size);
+        });
+        return match produced {
+            Some(produced) if produced == self.header.new_file_size => Ok(produced),
+            Some(produced) => Err(BsdumpError::CorruptPatch(format!(
+                "control entries produce {} bytes, expected {}",
+                produced, self.header.new_file_size
+            ))),
+            None => Err(BsdumpError::CorruptPatch(format!(
+                "control entries produce more than {} bytes, expected {}",
+                u64::MAX,
+                self.header.new_file_size
+            ))),
+        };
+    }
+
+    /// Simulates `apply` on a source of `source_len` bytes without reading
+    /// any source content, entry by entry in file order. Each entry must
+    /// read the source in bounds, write within `new_file_size` and find its
+    /// bytes left in the diff and extra streams, and together the entries
+    /// must produce exactly `new_file_size` bytes. Unlike the size sums of
+    /// `check_stream_lengths`, this catches entries that are fine alone but
+    /// not after the entries before them moved the pointers. Returns the
+    /// size of the new file, or the first problem with the index of the
+    /// entry that caused it. Decompresses the extra stream once to learn
+    /// its length.
+    pub fn dry_apply(&self, source_len: u64) -> Result<u64, BsdumpError> {
+        let new_file_size = self.header.new_file_size;
+        let diff_len = self.expanded_diff_stream_len();
+        let extra_len = self.extra_stream_len()?;
+        let mut diff_pos = 0_u64;
+        let mut extra_pos = 0_u64;
+        let mut produced = 0_u64;
+        for entry in self.all_control_entries_with_offsets() {
+            let diff_size = entry.entry.diff_size;
+            let extra_size = entry.entry.extra_size;
+            let source_ok = diff_size == 0
+                || (entry.source_offset >= 0
+                    && (entry.source_offset as u64).saturating_add(diff_size) <= source_len);
+            let target_end = entry
+                .target_offset
+                .saturating_add(diff_size)
+                .saturating_add(extra_size);
+            let target_ok = target_end <= new_file_size;
+            if !source_ok || !target_ok {
+                return Err(BsdumpError::EntryOutOfBounds {
+                    entry,
+                    source_len,
+                    new_file_size,
+                });
+            }
+            for (name, pos, size, len) in [
+                ("diff", &mut diff_pos, diff_size, diff_len),
+                ("extra", &mut extra_pos, extra_size, extra_len),
+            ] {
+                if pos.saturating_add(size) > len {
+                    return Err(BsdumpError::CorruptPatch(format!(
+                        "control entry {} reads {} stream bytes [{}, {}), but it has only {}",
+                        entry.index,
+                        name,
+                        pos,
+                        pos.saturating_add(size),
+                        len
+                    )));
+                }
+                *pos += size;
+            }
+            produced = target_end;
+        }
+        if produced != new_file_size {
+            return Err(BsdumpError::CorruptPatch(format!(
+                "control entries produce {} bytes, expected {}",
+                produced, new_file_size
+            )));
+        }
+        return Ok(produced);
+    }
+
+    // Every apply refuses to write past `new_file_size`, so checking it
+    // against `max_output` up front bounds the output before anything is
+    // allocated or decompressed.
+    fn check_output_size(&self) -> Result<(), BsdumpError> {
+        if let Some(max) = self.options.max_output {
+            if self.header.new_file_size > max {
+                return Err(BsdumpError::OutputTooLarge {
+                    size: self.header.new_file_size,
+                    max,
+                });
+            }
+        }
+        return Ok(());
+    }
+
+    // Checks that the control entries produce exactly `new_file_size` bytes
+    // and that the diff and extra streams hold every byte they read, so the
+    // header's size is backed by real data before an output of that size
+    // is allocated. Decompresses the extra stream once, without keeping it,
+    // to learn its length.
+    fn check_output_backed(&self) -> Result<(), BsdumpError> {
+        let new_file_size = self.header.new_file_size;
+        // Saturating, so huge sizes can't wrap around into totals that pass
+        // the length checks below.
+        let (diff_total, extra_total) =
+            self.all_control_entries()
+                .fold((0_u64, 0_u64), |(diff, extra), entry| {
+                    (
+                        diff.saturating_add(entry.diff_size),
+                        extra.saturating_add(entry.extra_size),
+                    )
+                });
+        let produced = diff_total.saturating_add(extra_total);
+        if produced != new_file_size {
+            return Err(BsdumpError::CorruptPatch(format!(
+                "control entries produce {} bytes, expected {}",
+                produced, new_file_size
+            )));
+        }
+        let diff_len = self.expanded_diff_stream_len();
+        if diff_total > diff_len {
+            return Err(BsdumpError::DiffLengthMismatch {
+                expected: diff_total,
+                found: diff_len,
+            });
+        }
+        let extra_len = self.extra_stream_len()?;
+        if extra_total > extra_len {
+            return Err(BsdumpError::ExtraLengthMismatch {
+                expected: extra_total,
+                found: extra_len,
+            });
+        }
+        return Ok(());
+    }
+
+    // How much of the output buffer `apply` reserves up front. The header's
+    // size isn't checked against the streams until they are decompressed,
+    // so a larger output grows the buffer as it is written instead.
+    fn initial_output_capacity(&self) -> usize {
+        const MAX_INITIAL_CAPACITY: u64 = 64 << 20;
+        return self.header.new_file_size.min(MAX_INITIAL_CAPACITY) as usize;
+    }
+
+    /// Applies the patch to `source` and writes the new file to `out`.
+    /// Like the reference bspatch, source bytes outside of `source` are
+    /// treated as zero. A seek or read that moves the source pointer past
+    /// the range of i64 is `EntryOutOfBounds`.
+    pub fn apply_to<W: Write>(&self, source: &[u8], out: &mut W) -> Result<(), BsdumpError> {
+        self.check_output_size()?;
+        let diff_stream = self.decompressed_diff_stream()?;
+        let extra_stream = self.decompressed_extra_stream()?;
+        self.check_stream_lengths_of(diff_stream.len(), extra_stream.len())?;
+        let new_file_size = self.header.new_file_size;
+        let mut diff_pos = 0_usize;
+        let mut extra_pos = 0_usize;
+        let mut old_pos = 0_i64;
+        let mut new_pos = 0_u64;
+        let mut buf = Vec::new();
+        let (tail_start, tail_len) = self.trailing_inserts();
+        for (i, entry) in self.all_control_entries().enumerate() {
+            if i == tail_start {
+                // Only extra bytes are left, append them in one go.
+                if !matches!(new_pos.checked_add(tail_len), Some(end) if end <= new_file_size) {
+                    return Err(BsdumpError::CorruptPatch(format!(
+                        "control entries from {} on write past the new file size {}",
+                        i, new_file_size
+                    )));
+                }
+                out.write_all(&extra_stream[extra_pos..][..tail_len as usize])?;
+                new_pos += tail_len;
+                self.options.report_progress(new_pos, new_file_size);
+                break;
+            }
+            let end = new_pos
+                .checked_add(entry.diff_size)
+                .and_then(|end| end.checked_add(entry.extra_size));
+            if !matches!(end, Some(end) if end <= new_file_size) {
+                return Err(BsdumpError::CorruptPatch(format!(
+                    "control entry {} writes past the new file size {}",
+                    i, new_file_size
+                )));
+            }
+            // Where the source pointer moves after this entry. Past the
+            // ends of i64 it can't be followed, and a real source is never
+            // that large anyway.
+            let next_old_pos = i64::try_from(entry.diff_size)
+                .ok()
+                .and_then(|diff_size| old_pos.checked_add(diff_size))
+                .and_then(|end| end.checked_add(entry.offset_increment));
+            let next_old_pos = match next_old_pos {
+                Some(pos) => pos,
+                None => {
+                    return Err(BsdumpError::EntryOutOfBounds {
+                        entry: OffsetControlEntry {
+                            index: i,
+                            source_offset: old_pos,
+                            target_offset: new_pos,
+                            entry,
+                        },
+                        source_len: source.len() as u64,
+                        new_file_size,
+                    });
+                }
+            };
+            let diff = &diff_stream[diff_pos..diff_pos + entry.diff_size as usize];
+            buf.clear();
+            buf.extend_from_slice(diff);
+            for (j, byte) in buf.iter_mut().enumerate() {
+                let src = old_pos + j as i64;
+                if src >= 0 && (src as usize) < source.len() {
+                    *byte = byte.wrapping_add(source[src as usize]);
+                }
+            }
+            out.write_all(&buf)?;
+            diff_pos += entry.diff_size as usize;
+
+            let extra = &extra_stream[extra_pos..extra_pos + entry.extra_size as usize];
+            out.write_all(extra)?;
+            extra_pos += entry.extra_size as usize;
+
+            new_pos += entry.diff_size + entry.extra_size;
+            old_pos = next_old_pos;
+            self.options.report_progress(new_pos, new_file_size);
+        }
+        if new_pos != new_file_size {
+            return Err(BsdumpError::CorruptPatch(format!(
+                "control entries produce {} bytes, expected {}",
+                new_pos, new_file_size
+            )));
+        }
+        return Ok(());
+    }
+
+    // The index of the first of the control entries at the end that only
+    // insert extra bytes, and how many bytes they insert. The index is the
+    // entry count if the last entry reads the source.
+    fn trailing_inserts(&self) -> (usize, u64) {
+        let mut start = 0;
+        let mut len = 0_u64;
+        for (i, entry) in self.all_control_entries().enumerate() {
+            if entry.diff_size > 0 {
+                start = i + 1;
+                len = 0;
+            } else {
+                len = len.saturating_add(entry.extra_size);
+            }
+        }
+        return (start, len);
+    }
+
+    /// How many bytes at the end of the new file come from the extra
+    /// stream alone, after the last byte derived from the source. These
+    /// can be appended without touching the source, which `apply_to` does
+    /// in one write. The whole new file for a full image.
+    pub fn tail_append_len(&self) -> u64 {
+        let (start, len) = self.trailing_inserts();
+        // The extra bytes of the last entry reading the source follow its
+        // diff bytes, so they are part of the tail too.
+        let last_read_extra = match start.checked_sub(1) {
+            Some(index) => self.all_control_entries().nth(index).unwrap().extra_size,
+            None => 0,
+        };
+        return len.saturating_add(last_read_extra);
+    }
+
+    pub fn apply(&self, source: &[u8]) -> Result<Vec<u8>, BsdumpError> {
+        self.check_output_size()?;
+        let mut out = Vec::with_capacity(self.initial_output_capacity());
+        self.apply_to(source, &mut out)?;
+        return Ok(out);
+    }
+
+    /// Same as `apply_to`, but reads the source from `source` instead of
+    /// needing it in memory. Only the regions the control entries use are
+    /// read, one chunk of `BsdiffReaderOptions::source_chunk_size` bytes at
+    /// a time, so the source can be larger than RAM.
+    pub fn apply_from_source<R: Read + Seek, W: Write>(
+        &self,
+        mut source: R,
+        out: &mut W,
+    ) -> Result<(), BsdumpError> {
+        self.check_output_size()?;
+        let source_len = source.seek(SeekFrom::End(0))?;
+        let chunk_size = self.options.source_chunk_size.unwrap_or(64 * 1024);
+        let diff_stream = self.decompressed_diff_stream()?;
+        let extra_stream = self.decompressed_extra_stream()?;
+        self.check_stream_lengths_of(diff_stream.len(), extra_stream.len())?;
+        let new_file_size = self.header.new_file_size;
+        let mut diff_pos = 0_usize;
+        let mut extra_pos = 0_usize;
+        let mut buf = Vec::with_capacity(chunk_size);
+        let mut window = Vec::with_capacity(chunk_size);
+        for entry in self.all_control_entries_with_offsets() {
+            let end = entry
+                .target_offset
+                .saturating_add(entry.entry.diff_size)
+                .saturating_add(entry.entry.extra_size);
+            if end > new_file_size {
+                return Err(BsdumpError::CorruptPatch(format!(
+                    "control entry {} writes past the new file size {}",
+                    entry.index, new_file_size
+                )));
+            }
+            // As in `apply_to`, a read that ends past i64 can't be followed.
+            let read_end = i64::try_from(entry.entry.diff_size)
+                .ok()
+                .and_then(|diff_size| entry.source_offset.checked_add(diff_size));
+            if read_end.is_none() {
+                return Err(BsdumpError::EntryOutOfBounds {
+                    entry,
+                    source_len,
+                    new_file_size,
+                });
+            }
+            let diff_size = entry.entry.diff_size as usize;
+            for chunk_start in (0..diff_size).step_by(chunk_size) {
+                let len = chunk_size.min(diff_size - chunk_start);
+                buf.clear();
+                buf.extend_from_slice(&diff_stream[diff_pos + chunk_start..][..len]);
+                // Like `apply_to`, source bytes outside of the source are
+                // treated as zero, so only the overlap is read.
+                let src = entry.source_offset + chunk_start as i64;
+                let read_start = src.clamp(0, source_len as i64) as u64;
+                let read_end = (src + len as i64).clamp(0, source_len as i64) as u64;
+                if read_start < read_end {
+                    window.resize((read_end - read_start) as usize, 0);
+                    source.seek(SeekFrom::Start(read_start))?;
+                    source.read_exact(&mut window)?;
+                    let skip = (read_start as i64 - src) as usize;
+                    for (byte, old) in buf[skip..].iter_mut().zip(&window) {
+                        *byte = byte.wrapping_add(*old);
+                    }
+                }
+                out.write_all(&buf)?;
+            }
+            diff_pos += diff_size;
+
+            let extra_size = entry.entry.extra_size as usize;
+            out.write_all(&extra_stream[extra_pos..][..extra_size])?;
+            extra_pos += extra_size;
+            self.options.report_progress(end, new_file_size);
+        }
+        let produced = diff_pos as u64 + extra_pos as u64;
+        if produced != new_file_size {
+            return Err(BsdumpError::CorruptPatch(format!(
+                "control entries produce {} bytes, expected {}",
+                produced, new_file_size
+            )));
+        }
+        return Ok(());
+    }
+
+    /// Same as `apply`, but consumes the reader to keep peak memory low.
+    /// The diff bytes are applied in a first pass and freed before the
+    /// extra stream is decompressed for a second pass, so at most one of
+    /// the two is held next to the output, and the mask and control stream
+    /// are dropped once done. The output is allocated whole, so the entries
+    /// are first checked against the stream lengths, which decompresses the
+    /// extra stream one more time.
+    pub fn into_apply(mut self, source: &[u8]) -> Result<Vec<u8>, BsdumpError> {
+        self.check_output_size()?;
+        let new_file_size = self.header.new_file_size;
+        self.check_output_backed()?;
+        let mut out = vec![0_u8; new_file_size as usize];
+
+        let diff_stream = self.decompressed_diff_stream()?;
+        self.decompressed_mask_stream = None;
+        let mut diff_pos = 0;
+        for entry in self.all_control_entries_with_offsets() {
+            // As in `apply_to`, a read that ends past i64 can't be followed.
+            let read_end = i64::try_from(entry.entry.diff_size)
+                .ok()
+                .and_then(|diff_size| entry.source_offset.checked_add(diff_size));
+            if read_end.is_none() {
+                return Err(BsdumpError::EntryOutOfBounds {
+                    entry,
+                    source_len: source.len() as u64,
+                    new_file_size,
+                });
+            }
+            let target = entry.target_offset as usize;
+            let diff_size = entry.entry.diff_size as usize;
+            let diff = &diff_stream[diff_pos..diff_pos + diff_size];
+            for (j, (byte, delta)) in out[target..target + diff_size]
+                .iter_mut()
+                .zip(diff)
+                .enumerate()
+            {
+                let src = entry.source_offset + j as i64;
+                *byte = *delta;
+                if src >= 0 && (src as usize) < source.len() {
+                    *byte = byte.wrapping_add(source[src as usize]);
+                }
+            }
+            diff_pos += diff_size;
+        }
+        drop(diff_stream);
+
+        let extra_stream = self.decompressed_extra_stream()?;
+        let mut extra_pos = 0;
+        for entry in self.all_control_entries_with_offsets() {
+            let target = (entry.target_offset + entry.entry.diff_size) as usize;
+            let extra_size = entry.entry.extra_size as usize;
+            out[target..target + extra_size]
+                .copy_from_slice(&extra_stream[extra_pos..extra_pos + extra_size]);
+            extra_pos += extra_size;
+            self.options
+                .report_progress((target + extra_size) as u64, new_file_size);
+        }
+        return Ok(out);
+    }
+
+    /// Applies the patch into a new file at `out_path`, which is created or
+    /// truncated, sized to `new_file_size` and mapped into memory, so the
+    /// new file is written in place and never held on the heap. Where the
+    /// file can't be mapped, e.g. for an empty new file, it is written
+    /// through a buffer instead. The entries are checked against the
+    /// stream lengths before the file is created, which decompresses the
+    /// extra stream one more time, so a bogus header size can't create a
+    /// huge sparse file. If applying fails anyway, the file is removed.
+    pub fn apply_to_mmap(&self, source: &[u8], out_path: &Path) -> Result<(), BsdumpError> {
+        self.check_output_size()?;
+        self.check_output_backed()?;
+        let file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(out_path)?;
+        let result = self.apply_to_file(source, file);
+        if result.is_err() {
+            // Best effort, the apply error is the one worth reporting.
+            let _ = std::fs::remove_file(out_path);
+        }
+        return result;
+    }
+
+    fn apply_to_file(&self, source: &[u8], file: std::fs::File) -> Result<(), BsdumpError> {
+        let new_file_size = self.header.new_file_size;
+        file.set_len(new_file_size)?;
+        // Mapping an empty file fails on most platforms.
+        let map = match new_file_size {
+            0 => None,
+            // The file was just created for this output, nothing else is
+            // expected to resize it while it is mapped.
+            _ => unsafe { memmap::MmapMut::map_mut(&file) }.ok(),
+        };
+        let mut map = match map {
+            Some(map) => map,
+            None => {
+                let mut out = std::io::BufWriter::new(file);
+                self.apply_to(source, &mut out)?;
+                out.flush()?;
+                return Ok(());
+            }
+        };
+        let mut out: &mut [u8] = &mut map;
+        self.apply_to(source, &mut out)?;
+        map.flush()?;
+        return Ok(());
+    }
+
+    /// Applies the patch over `buf`, which holds the source on entry and the
+    /// new file on return, without allocating a separate output. This only
+    /// works when no entry reads source bytes that an earlier write already
+    /// replaced, i.e. every entry reads at or after the position it writes.
+    /// Otherwise returns `BsdumpError::InPlaceUnsafe`. `buf` is only
+    /// modified once the whole patch has been checked.
+    pub fn apply_in_place(&self, buf: &mut Vec<u8>) -> Result<(), BsdumpError> {
+        self.check_output_size()?;
+        let new_file_size = self.check_applicable(buf.len() as u64)?;
+        for entry in self.all_control_entries_with_offsets() {
+            if entry.entry.diff_size > 0 && entry.source_offset < entry.target_offset as i64 {
+                return Err(BsdumpError::InPlaceUnsafe { index: entry.index });
+            }
+        }
+        let diff_stream = self.decompressed_diff_stream()?;
+        let extra_stream = self.decompressed_extra_stream()?;
+        self.check_stream_lengths_of(diff_stream.len(), extra_stream.len())?;
+
+        buf.resize(buf.len().max(new_file_size as usize), 0);
+        let mut diff_pos = 0;
+        let mut extra_pos = 0;
+        for entry in self.all_control_entries_with_offsets() {
+            let target = entry.target_offset as usize;
+            let source = entry.source_offset.max(0) as usize;
+            let diff_size = entry.entry.diff_size as usize;
+            let extra_size = entry.entry.extra_size as usize;
+            // Reads are at or after writes, so going forwards reads every
+            // source byte before it is overwritten.
+            for i in 0..diff_size {
+                buf[target + i] = diff_stream[diff_pos + i].wrapping_add(buf[source + i]);
+            }
+            diff_pos += diff_size;
+            buf[target + diff_size..][..extra_size]
+                .copy_from_slice(&extra_stream[extra_pos..extra_pos + extra_size]);
+            extra_pos += extra_size;
+            self.options
+                .report_progress((target + diff_size + extra_size) as u64, new_file_size);
+        }
+        buf.truncate(new_file_size as usize);
+        return Ok(());
+    }
+
+    /// Same as `apply`, but also returns the CRC32 of the new file,
+    /// computed as it is written.
+    #[cfg(feature = "crc32fast")]
+    pub fn apply_with_crc32(&self, source: &[u8]) -> Result<(Vec<u8>, u32), BsdumpError> {
+        self.check_output_size()?;
+        let mut writer = Crc32Writer {
+            inner: Vec::with_capacity(self.initial_output_capacity()),
+            hasher: crc32fast::Hasher::new(),
+        };
+        self.apply_to(source, &mut writer)?;
+        return Ok((writer.inner, writer.hasher.finalize()));
+    }
+
+    /// Same as `apply`, but checks the source and the new file against the
+    /// CRC32s given with `BsdiffReaderOptions::sidecar`, if any. Fails with
+    /// `BsdumpError::ChecksumMismatch` on the first one that differs.
+    #[cfg(feature = "crc32fast")]
+    pub fn apply_verified(&self, source: &[u8]) -> Result<Vec<u8>, BsdumpError> {
+        if let Some(expected) = self.options.source_crc32 {
+            let found = crc32fast::hash(source);
+            if found != expected {
+                return Err(BsdumpError::ChecksumMismatch {
+                    file: "source",
+                    expected,
+                    found,
+                });
+            }
+        }
+        let (target, found) = self.apply_with_crc32(source)?;
+        if let Some(expected) = self.options.target_crc32 {
+            if found != expected {
+                return Err(BsdumpError::ChecksumMismatch {
+                    file: "new file",
+                    expected,
+                    found,
+                });
+            }
+        }
+        return Ok(target);
+    }
+
+    /// Rewrites the patch as a BSDF2 patch that produces the same new file,
+    /// for appliers that don't understand BDF3 masks or the legacy header.
+    /// The diff stream is stored in full, with the bytes a BDF3 mask strips
+    /// put back; control entries and compressors are kept. Fails if the
+    /// control stream ends in a partial entry or the entries don't fit the
+    /// streams, since the result could not be equivalent.
+    pub fn to_bsdf2(&self) -> Result<Vec<u8>, BsdumpError> {
+        return self.rewrite(BsdiffWriter::new(self.header.validated_compressors()));
+    }
+
+    /// Rewrites the patch in the canonical layout of upstream bsdiff 4.x,
+    /// a BSDIFF40 patch with every stream in bzip2, to cross-check with the
+    /// stock `bspatch`. Otherwise the same as `to_bsdf2`.
+    pub fn to_bsdiff40(&self) -> Result<Vec<u8>, BsdumpError> {
+        return self.rewrite(BsdiffWriter::bsdiff40());
+    }
+
+    // Feeds every control entry with its diff and extra bytes to `writer`,
+    // see `to_bsdf2`.
+    fn rewrite(&self, mut writer: BsdiffWriter) -> Result<Vec<u8>, BsdumpError> {
+        if self.num_control_entries() * CONTROL_ENTRY_SIZE != self.decompressed_ctrl_stream.len() {
+            return Err(BsdumpError::CorruptPatch(format!(
+                "ctrl stream of {} bytes ends in a partial control entry",
+                self.decompressed_ctrl_stream.len()
+            )));
+        }
+        let diff_stream = self.decompressed_diff_stream()?;
+        let extra_stream = self.decompressed_extra_stream()?;
+        self.check_stream_lengths_of(diff_stream.len(), extra_stream.len())?;
+        let mut diff_pos = 0;
+        let mut extra_pos = 0;
+        for entry in self.all_control_entries() {
+            let diff_size = entry.diff_size as usize;
+            let extra_size = entry.extra_size as usize;
+            writer.add_entry(
+                &diff_stream[diff_pos..][..diff_size],
+                &extra_stream[extra_pos..][..extra_size],
+                entry.offset_increment,
+            );
+            diff_pos += diff_size;
+            extra_pos += extra_size;
+        }
+        if writer.new_file_size() != self.header.new_file_size {
+            return Err(BsdumpError::CorruptPatch(format!(
+                "control entries produce {} bytes, expected {}",
+                writer.new_file_size(),
+                self.header.new_file_size
+            )));
+        }
+        return Ok(writer.finish()?);
+    }
+
+    /// Composes `first`, a patch from A to B, and `second`, a patch from B
+    /// to C, into one BSDF2 patch from A to C, e.g. to chain OTA updates.
+    /// `source` is A. Instead of re-diffing, every byte of C is traced back
+    /// through both patches: bytes that come from A keep reading A with the
+    /// two diff bytes summed, the rest become extra bytes. The result uses
+    /// the compressors of `second`.
+    pub fn compose(
+        first: &BsdiffReader,
+        second: &BsdiffReader,
+        source: &[u8],
+    ) -> Result<Vec<u8>, BsdumpError> {
+        let intermediate_len = first.check_applicable(source.len() as u64)?;
+        second.check_applicable(intermediate_len)?;
+        let first_diff = first.decompressed_diff_stream()?;
+        let first_extra = first.decompressed_extra_stream()?;
+        first.check_stream_lengths_of(first_diff.len(), first_extra.len())?;
+        let second_diff = second.decompressed_diff_stream()?;
+        let second_extra = second.decompressed_extra_stream()?;
+        second.check_stream_lengths_of(second_diff.len(), second_extra.len())?;
+
+        // Where each byte of B comes from, in target order.
+        let mut segments = Vec::new();
+        let mut diff_pos = 0;
+        let mut extra_pos = 0;
+        for entry in first.all_control_entries_with_offsets() {
+            let diff_size = entry.entry.diff_size;
+            let extra_size = entry.entry.extra_size;
+            if diff_size > 0 {
+                segments.push(Segment {
+                    target: entry.target_offset,
+                    len: diff_size,
+                    origin: Origin::Source {
+                        source: entry.source_offset as u64,
+                        diff: diff_pos,
+                    },
+                });
+            }
+            if extra_size > 0 {
+                segments.push(Segment {
+                    target: entry.target_offset + diff_size,
+                    len: extra_size,
+                    origin: Origin::Extra { extra: extra_pos },
+                });
+            }
+            diff_pos += diff_size as usize;
+            extra_pos += extra_size as usize;
+        }
+
+        let mut composer = Composer {
+            writer: BsdiffWriter::new(second.header.validated_compressors()),
+            diff: Vec::new(),
+            extra: Vec::new(),
+            source: 0,
+        };
+        let mut diff_pos = 0;
+        let mut extra_pos = 0;
+        for entry in second.all_control_entries_with_offsets() {
+            let diff_size = entry.entry.diff_size;
+            let extra_size = entry.entry.extra_size as usize;
+            let start = entry.source_offset as u64;
+            let end = start + diff_size;
+            let first_segment = segments.partition_point(|seg| seg.target + seg.len <= start);
+            for segment in segments[first_segment..]
+                .iter()
+                .take_while(|seg| diff_size > 0 && seg.target < end)
+            {
+                let from = start.max(segment.target);
+                let to = end.min(segment.target + segment.len);
+                let len = (to - from) as usize;
+                let into_segment = (from - segment.target) as usize;
+                let second_bytes = &second_diff[diff_pos + (from - start) as usize..][..len];
+                match segment.origin {
+                    Origin::Source { source, diff } => {
+                        let first_bytes = &first_diff[diff + into_segment..][..len];
+                        composer.push_diff(
+                            source + into_segment as u64,
+                            first_bytes
+                                .iter()
+                                .zip(second_bytes)
+                                .map(|(a, b)| a.wrapping_add(*b)),
+                        );
+                    }
+                    Origin::Extra { extra } => {
+                        let first_bytes = &first_extra[extra + into_segment..][..len];
+                        composer.push_extra(
+                            first_bytes
+                                .iter()
+                                .zip(second_bytes)
+                                .map(|(a, b)| a.wrapping_add(*b)),
+                        );
+                    }
+                }
+            }
+            composer.push_extra(second_extra[extra_pos..][..extra_size].iter().copied());
+            diff_pos += diff_size as usize;
+            extra_pos += extra_size;
+        }
+        return Ok(composer.finish()?);
+    }
 }
@@ -0,0 +1,149 @@
+use crate::bsdiff_format::{BsdiffReader, ControlEntry, OffsetControlEntry};
+use crate::error::BsdumpError;
+
+/// A difference between the control entries of two patches.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EntryChange {
+    // Present only in the second patch.
+    Added(OffsetControlEntry),
+    // Present only in the first patch.
+    Removed(OffsetControlEntry),
+    // Both patches have an entry at this target offset, but they differ.
+    Changed {
+        old: OffsetControlEntry,
+        new: OffsetControlEntry,
+    },
+}
+
+/// Compares the control entries of two patches. Entries are aligned by the
+/// target offset they start writing at, so an inserted entry does not make
+/// every following entry show up as changed.
+pub fn diff_control_entries(old: &BsdiffReader, new: &BsdiffReader) -> Vec<EntryChange> {
+    let mut changes = Vec::new();
+    let mut old_entries = old.control_entries_with_offsets().peekable();
+    let mut new_entries = new.control_entries_with_offsets().peekable();
+    loop {
+        let old_offset = old_entries.peek().map(|e| e.target_offset);
+        let new_offset = new_entries.peek().map(|e| e.target_offset);
+        match (old_offset, new_offset) {
+            (None, None) => break,
+            (Some(_), None) => changes.push(EntryChange::Removed(old_entries.next().unwrap())),
+            (None, Some(_)) => changes.push(EntryChange::Added(new_entries.next().unwrap())),
+            (Some(a), Some(b)) if a < b => {
+                changes.push(EntryChange::Removed(old_entries.next().unwrap()))
+            }
+            (Some(a), Some(b)) if a > b => {
+                changes.push(EntryChange::Added(new_entries.next().unwrap()))
+            }
+            _ => {
+                let old_entry = old_entries.next().unwrap();
+                let new_entry = new_entries.next().unwrap();
+                if old_entry.entry != new_entry.entry {
+                    changes.push(EntryChange::Changed {
+                        old: old_entry,
+                        new: new_entry,
+                    });
+                }
+            }
+        }
+    }
+    return changes;
+}
+
+/// Where two patches first differ, see `first_divergence`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Divergence {
+    // A header field differs.
+    Header {
+        field: &'static str,
+        old: u64,
+        new: u64,
+    },
+    // The control entries differ at `index`. `None` means that patch has
+    // no entry there.
+    ControlEntry {
+        index: usize,
+        old: Option<ControlEntry>,
+        new: Option<ControlEntry>,
+    },
+    // The decompressed stream differs at `offset`, or one of them ends there.
+    Stream {
+        name: &'static str,
+        offset: u64,
+    },
+}
+
+/// Finds the first point where two patches differ: the magic and new file
+/// size, then the control entries in order, then the decompressed diff and
+/// extra streams. Compressed sizes are not compared, since recompressing
+/// the same content with other settings would otherwise always differ
+/// first. Returns `None` if the patches are equivalent.
+pub fn first_divergence(
+    old: &BsdiffReader,
+    new: &BsdiffReader,
+) -> Result<Option<Divergence>, BsdumpError> {
+    let header_fields = [
+        ("magic", old.header.magic, new.header.magic),
+        (
+            "new_file_size",
+            old.header.new_file_size,
+            new.header.new_file_size,
+        ),
+    ];
+    for (field, old_value, new_value) in header_fields {
+        if old_value != new_value {
+            return Ok(Some(Divergence::Header {
+                field,
+                old: old_value,
+                new: new_value,
+            }));
+        }
+    }
+
+    let mut old_entries = old.control_entries();
+    let mut new_entries = new.control_entries();
+    for index in 0.. {
+        let old_entry = old_entries.next();
+        let new_entry = new_entries.next();
+        if old_entry.is_none() && new_entry.is_none() {
+            break;
+        }
+        if old_entry != new_entry {
+            return Ok(Some(Divergence::ControlEntry {
+                index,
+                old: old_entry,
+                new: new_entry,
+            }));
+        }
+    }
+
+    let streams = [
+        (
+            "diff",
+            old.decompressed_diff_stream()?,
+            new.decompressed_diff_stream()?,
+        ),
+        (
+            "extra",
+            old.decompressed_extra_stream()?,
+            new.decompressed_extra_stream()?,
+        ),
+    ];
+    for (name, old_stream, new_stream) in streams {
+        if let Some(offset) = first_difference(&old_stream, &new_stream) {
+            return Ok(Some(Divergence::Stream { name, offset }));
+        }
+    }
+    return Ok(None);
+}
+
+/// Offset of the first byte where `old` and `new` differ, or where the
+/// shorter one ends. `None` if they are equal.
+pub fn first_difference(old: &[u8], new: &[u8]) -> Option<u64> {
+    let offset = old
+        .iter()
+        .zip(new)
+        .position(|(a, b)| a != b)
+        .or_else(|| (old.len() != new.len()).then(|| old.len().min(new.len())));
+    return offset.map(|offset| offset as u64);
+}
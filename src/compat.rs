@@ -0,0 +1,121 @@
+use std::fmt;
+
+use crate::bsdiff_format::{BsdiffFormat, CompressorType};
+use crate::error::BsdumpError;
+
+/// The formats and compressors a patch applier can handle.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ApplierProfile {
+    pub name: &'static str,
+    pub description: &'static str,
+    // Format names as returned by `BsdiffFormat::format_name`.
+    pub formats: &'static [&'static str],
+    pub compressors: &'static [CompressorType],
+}
+
+/// The built-in applier profiles, looked up by `find_applier`.
+pub const APPLIERS: [ApplierProfile; 3] = [
+    ApplierProfile {
+        name: "bspatch",
+        description: "the original bsdiff 4.x bspatch",
+        formats: &["BSDIFF40"],
+        compressors: &[CompressorType::Bz2],
+    },
+    ApplierProfile {
+        name: "android-ota",
+        description: "the bspatch in Android's update_engine",
+        formats: &["BSDIFF40", "BSDF2"],
+        compressors: &[CompressorType::Bz2, CompressorType::Brotli],
+    },
+    ApplierProfile {
+        name: "bsdump",
+        description: "this crate with every codec feature enabled",
+        formats: &["BSDIFF40", "BSDF2", "BDF3"],
+        compressors: &CompressorType::ALL,
+    },
+];
+
+/// The built-in profile called `name`.
+pub fn find_applier(name: &str) -> Option<&'static ApplierProfile> {
+    return APPLIERS.iter().find(|profile| profile.name == name);
+}
+
+/// Why an applier can't apply a patch, see `ApplierProfile::check`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Incompatibility {
+    // The applier does not know the patch format.
+    Format(&'static str),
+    // The applier can't decompress the named stream.
+    Compressor {
+        stream: &'static str,
+        compressor_type: CompressorType,
+    },
+    // The magic has an unknown compressor byte for the named stream, so no
+    // applier can decompress it.
+    UnknownCompressor {
+        stream: &'static str,
+        value: u8,
+    },
+}
+
+impl fmt::Display for Incompatibility {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return match self {
+            Incompatibility::Format(format) => write!(f, "unsupported format {}", format),
+            Incompatibility::Compressor {
+                stream,
+                compressor_type,
+            } => write!(
+                f,
+                "unsupported {:?} compression of the {} stream",
+                compressor_type, stream
+            ),
+            Incompatibility::UnknownCompressor { stream, value } => {
+                write!(
+                    f,
+                    "unknown compressor type {} for the {} stream",
+                    value, stream
+                )
+            }
+        };
+    }
+}
+
+impl ApplierProfile {
+    /// Checks the format and the compressors of every stream in `header`
+    /// against this profile. Only the header is looked at, the patch
+    /// content itself is not validated. An empty result means the applier
+    /// can handle the patch.
+    pub fn check(&self, header: &BsdiffFormat) -> Vec<Incompatibility> {
+        let mut problems = Vec::new();
+        let format = header.format_name();
+        if !self.formats.contains(&format) {
+            problems.push(Incompatibility::Format(format));
+        }
+        let [ctrl, diff, extra] = match header.compressors() {
+            Ok(compressors) => compressors,
+            Err(e) => {
+                // Only an unknown compressor byte makes `compressors` fail.
+                if let BsdumpError::InvalidCompressorType { index, value, .. } = e {
+                    let stream = ["ctrl", "diff", "extra"][index - 5];
+                    problems.push(Incompatibility::UnknownCompressor { stream, value });
+                }
+                return problems;
+            }
+        };
+        let mut streams = vec![("ctrl", ctrl), ("diff", diff), ("extra", extra)];
+        // BDF3 always compresses its mask with brotli.
+        if header.is_bsdiff3_format() {
+            streams.push(("mask", CompressorType::Brotli));
+        }
+        for (stream, compressor_type) in streams {
+            if !self.compressors.contains(&compressor_type) {
+                problems.push(Incompatibility::Compressor {
+                    stream,
+                    compressor_type,
+                });
+            }
+        }
+        return problems;
+    }
+}
@@ -0,0 +1,324 @@
+use std::io::{self, Write};
+
+use crate::bsdiff_format::CompressorType;
+
+// Brotli settings used by Android's bsdiff.
+#[cfg(feature = "brotli")]
+const BROTLI_QUALITY: u32 = 9;
+#[cfg(feature = "brotli")]
+const BROTLI_WINDOW_BITS: u32 = 22;
+#[cfg(feature = "brotli")]
+const BROTLI_BUFFER_SIZE: usize = 4096;
+#[cfg(feature = "zstd")]
+const ZSTD_LEVEL: i32 = 19;
+
+/// A compressing writer for any of the codecs, writing the compressed
+/// stream to `W` as data comes in. Call `finish` to end the stream.
+pub enum Encoder<W: Write> {
+    #[cfg(feature = "bz2")]
+    Bz2(bzip2::write::BzEncoder<W>),
+    #[cfg(feature = "brotli")]
+    Brotli(Box<brotli::CompressorWriter<W>>),
+    #[cfg(feature = "zstd")]
+    Zstd(zstd::stream::write::Encoder<'static, W>),
+    #[cfg(feature = "gzip")]
+    Gzip(flate2::write::GzEncoder<W>),
+    // Keeps `W` used when every codec is disabled.
+    #[cfg(not(any(
+        feature = "bz2",
+        feature = "brotli",
+        feature = "zstd",
+        feature = "gzip"
+    )))]
+    None(std::convert::Infallible, std::marker::PhantomData<W>),
+}
+
+#[cfg_attr(
+    not(any(
+        feature = "bz2",
+        feature = "brotli",
+        feature = "zstd",
+        feature = "gzip"
+    )),
+    allow(unreachable_code, unused_variables)
+)]
+impl<W: Write> Encoder<W> {
+    /// Starts a stream of the given type at a high compression level.
+    /// Fails with `ErrorKind::Unsupported` if the codec's feature is
+    /// disabled.
+    pub fn new(out: W, compressor_type: CompressorType) -> Result<Encoder<W>, io::Error> {
+        return match compressor_type {
+            #[cfg(feature = "bz2")]
+            CompressorType::Bz2 => Ok(Encoder::Bz2(bzip2::write::BzEncoder::new(
+                out,
+                bzip2::Compression::best(),
+            ))),
+            #[cfg(feature = "brotli")]
+            CompressorType::Brotli => Ok(Encoder::Brotli(Box::new(brotli::CompressorWriter::new(
+                out,
+                BROTLI_BUFFER_SIZE,
+                BROTLI_QUALITY,
+                BROTLI_WINDOW_BITS,
+            )))),
+            #[cfg(feature = "zstd")]
+            CompressorType::Zstd => Ok(Encoder::Zstd(zstd::stream::write::Encoder::new(
+                out, ZSTD_LEVEL,
+            )?)),
+            #[cfg(feature = "gzip")]
+            CompressorType::Gzip => Ok(Encoder::Gzip(flate2::write::GzEncoder::new(
+                out,
+                flate2::Compression::best(),
+            ))),
+            #[allow(unreachable_patterns)]
+            _ => Err(not_compiled(compressor_type)),
+        };
+    }
+
+    /// Writes the end of the stream and returns the underlying writer.
+    pub fn finish(self) -> Result<W, io::Error> {
+        return match self {
+            #[cfg(feature = "bz2")]
+            Encoder::Bz2(encoder) => encoder.finish(),
+            // The brotli stream is terminated when the writer is unwrapped.
+            #[cfg(feature = "brotli")]
+            Encoder::Brotli(encoder) => Ok(encoder.into_inner()),
+            #[cfg(feature = "zstd")]
+            Encoder::Zstd(encoder) => encoder.finish(),
+            #[cfg(feature = "gzip")]
+            Encoder::Gzip(encoder) => encoder.finish(),
+            #[cfg(not(any(
+                feature = "bz2",
+                feature = "brotli",
+                feature = "zstd",
+                feature = "gzip"
+            )))]
+            Encoder::None(never, _) => match never {},
+        };
+    }
+}
+
+#[cfg_attr(
+    not(any(
+        feature = "bz2",
+        feature = "brotli",
+        feature = "zstd",
+        feature = "gzip"
+    )),
+    allow(unreachable_code, unused_variables)
+)]
+impl<W: Write> Write for Encoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        return match self {
+            #[cfg(feature = "bz2")]
+            Encoder::Bz2(encoder) => encoder.write(buf),
+            #[cfg(feature = "brotli")]
+            Encoder::Brotli(encoder) => encoder.write(buf),
+            #[cfg(feature = "zstd")]
+            Encoder::Zstd(encoder) => encoder.write(buf),
+            #[cfg(feature = "gzip")]
+            Encoder::Gzip(encoder) => encoder.write(buf),
+            #[cfg(not(any(
+                feature = "bz2",
+                feature = "brotli",
+                feature = "zstd",
+                feature = "gzip"
+            )))]
+            Encoder::None(never, _) => match *never {},
+        };
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        return match self {
+            #[cfg(feature = "bz2")]
+            Encoder::Bz2(encoder) => encoder.flush(),
+            #[cfg(feature = "brotli")]
+            Encoder::Brotli(encoder) => encoder.flush(),
+            #[cfg(feature = "zstd")]
+            Encoder::Zstd(encoder) => encoder.flush(),
+            #[cfg(feature = "gzip")]
+            Encoder::Gzip(encoder) => encoder.flush(),
+            #[cfg(not(any(
+                feature = "bz2",
+                feature = "brotli",
+                feature = "zstd",
+                feature = "gzip"
+            )))]
+            Encoder::None(never, _) => match *never {},
+        };
+    }
+}
+
+/// Compresses `data` with the given compressor at a high compression level.
+/// Fails with `ErrorKind::Unsupported` if the codec's feature is disabled.
+pub fn compress(data: &[u8], compressor_type: CompressorType) -> Result<Vec<u8>, io::Error> {
+    let mut encoder = Encoder::new(Vec::new(), compressor_type)?;
+    encoder.write_all(data)?;
+    return encoder.finish();
+}
+
+#[cfg(any(feature = "bz2", feature = "brotli"))]
+fn truncated(compressor_type: CompressorType) -> io::Error {
+    return io::Error::new(
+        io::ErrorKind::UnexpectedEof,
+        format!(
+            "{:?} stream ends before its end of stream marker",
+            compressor_type
+        ),
+    );
+}
+
+#[cfg_attr(
+    all(
+        feature = "bz2",
+        feature = "brotli",
+        feature = "zstd",
+        feature = "gzip"
+    ),
+    allow(dead_code)
+)]
+pub(crate) fn not_compiled(compressor_type: CompressorType) -> io::Error {
+    return io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!(
+            "{:?} support is not compiled in, enable the {} feature",
+            compressor_type,
+            compressor_type.feature_name()
+        ),
+    );
+}
+
+#[cfg(feature = "brotli")]
+fn brotli_stream_len(data: &[u8]) -> Result<usize, io::Error> {
+    let mut state = brotli::BrotliState::new(
+        brotli::HeapAlloc::<u8>::new(0),
+        brotli::HeapAlloc::<u32>::new(0),
+        brotli::HeapAlloc::<brotli::HuffmanCode>::new(Default::default()),
+    );
+    let mut output = vec![0_u8; 64 * 1024];
+    let mut available_in = data.len();
+    let mut input_offset = 0;
+    loop {
+        let mut available_out = output.len();
+        let mut output_offset = 0;
+        let mut written = 0;
+        let result = brotli::BrotliDecompressStream(
+            &mut available_in,
+            &mut input_offset,
+            data,
+            &mut available_out,
+            &mut output_offset,
+            &mut output,
+            &mut written,
+            &mut state,
+        );
+        match result {
+            brotli::BrotliResult::ResultSuccess => return Ok(input_offset),
+            brotli::BrotliResult::NeedsMoreOutput => continue,
+            brotli::BrotliResult::NeedsMoreInput => return Err(truncated(CompressorType::Brotli)),
+            brotli::BrotliResult::ResultFailure => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "invalid brotli stream",
+                ))
+            }
+        }
+    }
+}
+
+// Reads `count` bits, least significant first, starting at bit `*pos`.
+fn read_bits(data: &[u8], pos: &mut usize, count: usize) -> Option<u32> {
+    let mut value = 0;
+    for i in 0..count {
+        let byte = *data.get(*pos / 8)?;
+        value |= ((byte >> (*pos % 8)) as u32 & 1) << i;
+        *pos += 1;
+    }
+    return Some(value);
+}
+
+/// The window size a brotli stream declares in its first bits, as the
+/// base two logarithm of the window in bytes. Also handles the large
+/// window extension. `None` if `data` is too short or the header invalid.
+pub fn brotli_window_bits(data: &[u8]) -> Option<u32> {
+    let mut pos = 0;
+    if read_bits(data, &mut pos, 1)? == 0 {
+        return Some(16);
+    }
+    let n = read_bits(data, &mut pos, 3)?;
+    if n != 0 {
+        return Some(17 + n);
+    }
+    return match read_bits(data, &mut pos, 3)? {
+        0 => Some(17),
+        // Large window streams follow with a zero bit and six window bits.
+        1 => match (read_bits(data, &mut pos, 1)?, read_bits(data, &mut pos, 6)?) {
+            (0, bits @ 10..=30) => Some(bits),
+            _ => None,
+        },
+        m => Some(8 + m),
+    };
+}
+
+// A gzip stream can hold several members; like the other readers, only
+// the first is read. The bufread decoder consumes exactly that member.
+#[cfg(feature = "gzip")]
+fn gzip_stream_len(data: &[u8]) -> Result<usize, io::Error> {
+    let mut decoder = flate2::bufread::GzDecoder::new(data);
+    io::copy(&mut decoder, &mut io::sink())?;
+    return Ok(data.len() - decoder.into_inner().len());
+}
+
+#[cfg(feature = "bz2")]
+fn bz2_stream_len(data: &[u8]) -> Result<usize, io::Error> {
+    let mut decompress = bzip2::Decompress::new(false);
+    let mut output = vec![0_u8; 64 * 1024];
+    loop {
+        let total_in = decompress.total_in();
+        let total_out = decompress.total_out();
+        let status = decompress
+            .decompress(&data[total_in as usize..], &mut output)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        if status == bzip2::Status::StreamEnd {
+            return Ok(decompress.total_in() as usize);
+        }
+        // No progress means the decoder is waiting for input that isn't there.
+        if decompress.total_in() == total_in && decompress.total_out() == total_out {
+            return Err(truncated(CompressorType::Bz2));
+        }
+    }
+}
+
+/// Finds the length of the compressed stream at the start of `data`, for
+/// formats where the stream length isn't stored anywhere. Anything after
+/// the end of stream marker is ignored.
+#[cfg_attr(
+    not(any(
+        feature = "bz2",
+        feature = "brotli",
+        feature = "zstd",
+        feature = "gzip"
+    )),
+    allow(unused_variables)
+)]
+pub fn compressed_stream_len(
+    data: &[u8],
+    compressor_type: CompressorType,
+) -> Result<usize, io::Error> {
+    return match compressor_type {
+        #[cfg(feature = "bz2")]
+        CompressorType::Bz2 => bz2_stream_len(data),
+        #[cfg(feature = "brotli")]
+        CompressorType::Brotli => brotli_stream_len(data),
+        #[cfg(feature = "zstd")]
+        CompressorType::Zstd => zstd::zstd_safe::find_frame_compressed_size(data).map_err(|code| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                zstd::zstd_safe::get_error_name(code),
+            )
+        }),
+        #[cfg(feature = "gzip")]
+        CompressorType::Gzip => gzip_stream_len(data),
+        #[allow(unreachable_patterns)]
+        _ => Err(not_compiled(compressor_type)),
+    };
+}
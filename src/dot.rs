@@ -0,0 +1,55 @@
+use std::convert::TryFrom;
+use std::io::{self, Write};
+
+use crate::bsdiff_format::BsdiffReader;
+
+/// Writes a Graphviz graph of the patch. Each control entry becomes a node
+/// for the target block it writes, with an edge to a node for the source
+/// region its diff bytes are added to. Pure inserts have no edge.
+pub fn write_dot<W: Write>(reader: &BsdiffReader, w: &mut W) -> io::Result<()> {
+    writeln!(w, "digraph patch {{")?;
+    writeln!(w, "  rankdir=LR;")?;
+    writeln!(w, "  node [shape=box];")?;
+    writeln!(w, "  subgraph cluster_target {{")?;
+    writeln!(w, "    label=\"target\";")?;
+    for entry in reader.control_entries_with_offsets() {
+        let end = entry
+            .target_offset
+            .saturating_add(entry.entry.diff_size)
+            .saturating_add(entry.entry.extra_size);
+        writeln!(
+            w,
+            "    t{} [label=\"#{} [{}, {})\\ndiff {} extra {}\"];",
+            entry.index,
+            entry.index,
+            entry.target_offset,
+            end,
+            entry.entry.diff_size,
+            entry.entry.extra_size
+        )?;
+    }
+    writeln!(w, "  }}")?;
+    writeln!(w, "  subgraph cluster_source {{")?;
+    writeln!(w, "    label=\"source\";")?;
+    for entry in reader.control_entries_with_offsets() {
+        if entry.entry.diff_size == 0 {
+            continue;
+        }
+        let diff_size = i64::try_from(entry.entry.diff_size).unwrap_or(i64::MAX);
+        writeln!(
+            w,
+            "    s{} [label=\"[{}, {})\"];",
+            entry.index,
+            entry.source_offset,
+            entry.source_offset.saturating_add(diff_size)
+        )?;
+    }
+    writeln!(w, "  }}")?;
+    for entry in reader.control_entries_with_offsets() {
+        if entry.entry.diff_size != 0 {
+            writeln!(w, "  t{} -> s{};", entry.index, entry.index)?;
+        }
+    }
+    writeln!(w, "}}")?;
+    return Ok(());
+}
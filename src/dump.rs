@@ -0,0 +1,216 @@
+use std::io::{self, Write};
+
+use crate::bsdiff_format::{BsdiffFormat, BsdiffReader};
+
+/// How `dump` renders a patch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    // The header followed by one line per control entry.
+    Text,
+    // A single object with the header fields and an array of entries.
+    Json,
+    // One row per control entry, for spreadsheets and scripts.
+    Csv,
+    // The header followed by the control entries in aligned columns, for
+    // reading in a terminal.
+    Table,
+}
+
+/// Writes the header and control entries of the patch in the given format.
+/// Entries stop at `BsdiffReaderOptions::max_control_entries` if set; text
+/// output then ends with a note and JSON output sets `truncated`.
+pub fn dump<W: Write>(reader: &BsdiffReader, w: &mut W, format: OutputFormat) -> io::Result<()> {
+    return match format {
+        OutputFormat::Text => {
+            write_header(reader, w)?;
+            write_entries(reader, w)
+        }
+        OutputFormat::Json => write_json(reader, w),
+        OutputFormat::Csv => write_csv(reader, w),
+        OutputFormat::Table => {
+            write_header(reader, w)?;
+            write_table(reader, w)
+        }
+    };
+}
+
+/// The header and compressor lines of the text format.
+pub fn write_header<W: Write>(reader: &BsdiffReader, w: &mut W) -> io::Result<()> {
+    writeln!(w, "{:?}", reader.header)?;
+    let [ctrl, diff, extra] = reader.header.validated_compressors();
+    writeln!(
+        w,
+        "compressors: ctrl {:?}, diff {:?}, extra {:?}",
+        ctrl, diff, extra
+    )?;
+    return Ok(());
+}
+
+/// The control entry lines of the text format.
+pub fn write_entries<W: Write>(reader: &BsdiffReader, w: &mut W) -> io::Result<()> {
+    let mut entries = reader.control_entries();
+    let mut count = 0;
+    for entry in entries.by_ref() {
+        writeln!(w, "{:?}", entry)?;
+        count += 1;
+    }
+    if entries.is_truncated() {
+        writeln!(w, "... stopped after {} entries", count)?;
+    }
+    return Ok(());
+}
+
+/// The control entries as a table with a header row and right aligned
+/// columns: index, diff size, extra size, offset increment, and the source
+/// and target offsets the entry starts at.
+pub fn write_table<W: Write>(reader: &BsdiffReader, w: &mut W) -> io::Result<()> {
+    const HEADERS: [&str; 6] = ["idx", "diff", "extra", "off", "src", "tgt"];
+    let mut entries = reader.control_entries_with_offsets();
+    let rows: Vec<[String; 6]> = entries
+        .by_ref()
+        .map(|entry| {
+            [
+                entry.index.to_string(),
+                entry.entry.diff_size.to_string(),
+                entry.entry.extra_size.to_string(),
+                entry.entry.offset_increment.to_string(),
+                entry.source_offset.to_string(),
+                entry.target_offset.to_string(),
+            ]
+        })
+        .collect();
+    let mut widths = HEADERS.map(str::len);
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.len());
+        }
+    }
+    write_table_row(w, &HEADERS, &widths)?;
+    for row in &rows {
+        write_table_row(w, row, &widths)?;
+    }
+    if entries.is_truncated() {
+        writeln!(w, "... stopped after {} entries", rows.len())?;
+    }
+    return Ok(());
+}
+
+fn write_table_row<W: Write, S: AsRef<str>>(
+    w: &mut W,
+    cells: &[S],
+    widths: &[usize],
+) -> io::Result<()> {
+    let cells: Vec<String> = cells
+        .iter()
+        .zip(widths)
+        .map(|(cell, width)| format!("{:>width$}", cell.as_ref(), width = width))
+        .collect();
+    writeln!(w, "{}", cells.join("  "))?;
+    return Ok(());
+}
+
+/// The magic as a number, in hex, bytewise and as escaped text.
+pub fn write_raw_magic<W: Write>(header: &BsdiffFormat, w: &mut W) -> io::Result<()> {
+    let bytes = header.magic.to_be_bytes();
+    let hex_bytes: Vec<String> = bytes.iter().map(|b| format!("0x{:02x}", b)).collect();
+    writeln!(
+        w,
+        "magic: {} (0x{:016x}) bytes [{}] \"{}\"",
+        header.magic,
+        header.magic,
+        hex_bytes.join(", "),
+        bytes.escape_ascii()
+    )?;
+    return Ok(());
+}
+
+/// `BsdiffReader::source_plan` as a JSON array of `{"start", "len"}`
+/// objects, one per line, in read order.
+pub fn write_source_plan<W: Write>(
+    reader: &BsdiffReader,
+    source_len: u64,
+    w: &mut W,
+) -> io::Result<()> {
+    write!(w, "[")?;
+    for (i, range) in reader.source_plan(source_len).iter().enumerate() {
+        let separator = if i == 0 { "" } else { "," };
+        write!(
+            w,
+            "{}\n  {{\"start\": {}, \"len\": {}}}",
+            separator,
+            range.start,
+            range.end - range.start
+        )?;
+    }
+    writeln!(w, "\n]")?;
+    return Ok(());
+}
+
+fn write_json<W: Write>(reader: &BsdiffReader, w: &mut W) -> io::Result<()> {
+    let header = &reader.header;
+    let compressors: Vec<String> = header
+        .validated_compressors()
+        .iter()
+        .map(|c| format!("\"{:?}\"", c))
+        .collect();
+    writeln!(w, "{{")?;
+    // The magic is not valid UTF-8 in general, so it goes out as hex.
+    writeln!(w, "  \"magic\": \"{:016x}\",", header.magic)?;
+    writeln!(
+        w,
+        "  \"compressed_ctrl_size\": {},",
+        header.compressed_ctrl_size
+    )?;
+    writeln!(
+        w,
+        "  \"compressed_diff_size\": {},",
+        header.compressed_diff_size
+    )?;
+    writeln!(w, "  \"new_file_size\": {},", header.new_file_size)?;
+    writeln!(w, "  \"compressors\": [{}],", compressors.join(", "))?;
+    write!(w, "  \"control_entries\": [")?;
+    // Besides the raw fields, each entry carries the source range it reads
+    // and the target range it writes, so consumers need not track offsets.
+    // `src_start` is signed, a corrupt patch can seek before the source.
+    let mut entries = reader.control_entries_with_offsets();
+    for (i, offset_entry) in entries.by_ref().enumerate() {
+        let separator = if i == 0 { "" } else { "," };
+        let entry = &offset_entry.entry;
+        write!(
+            w,
+            "{}\n    {{\"diff_size\": {}, \"extra_size\": {}, \"offset_increment\": {}, \"src_start\": {}, \"src_len\": {}, \"tgt_start\": {}, \"tgt_len\": {}}}",
+            separator,
+            entry.diff_size,
+            entry.extra_size,
+            entry.offset_increment,
+            offset_entry.source_offset,
+            entry.diff_size,
+            offset_entry.target_offset,
+            entry.diff_size.saturating_add(entry.extra_size)
+        )?;
+    }
+    writeln!(w, "\n  ],")?;
+    writeln!(w, "  \"truncated\": {}", entries.is_truncated())?;
+    writeln!(w, "}}")?;
+    return Ok(());
+}
+
+fn write_csv<W: Write>(reader: &BsdiffReader, w: &mut W) -> io::Result<()> {
+    writeln!(
+        w,
+        "index,source_offset,target_offset,diff_size,extra_size,offset_increment"
+    )?;
+    for entry in reader.control_entries_with_offsets() {
+        writeln!(
+            w,
+            "{},{},{},{},{},{}",
+            entry.index,
+            entry.source_offset,
+            entry.target_offset,
+            entry.entry.diff_size,
+            entry.entry.extra_size,
+            entry.entry.offset_increment
+        )?;
+    }
+    return Ok(());
+}
@@ -0,0 +1,261 @@
+use std::convert::TryFrom;
+use std::fmt;
+use std::io;
+
+use crate::bsdiff_format::{CompressorType, OffsetControlEntry};
+
+#[derive(Debug)]
+pub enum BsdumpError {
+    // The first 8 bytes do not match any known bsdiff magic.
+    NotABsdiffPatch {
+        magic: [u8; 8],
+    },
+    // The magic names a BSDF2 or BDF3 patch, but byte `index` of it is
+    // not a known compressor type.
+    InvalidCompressorType {
+        magic: [u8; 8],
+        index: usize,
+        value: u8,
+    },
+    // The magic names a BDF3 patch, but reserved byte `index` of it is not
+    // zero.
+    ReservedMagicByte {
+        magic: [u8; 8],
+        index: usize,
+        value: u8,
+    },
+    // A header field declares a stream larger than the whole patch file.
+    ImplausibleHeader {
+        field: &'static str,
+        value: u64,
+        file_len: u64,
+    },
+    // A stream uses a codec whose cargo feature is disabled.
+    CompressorNotCompiled {
+        compressor_type: CompressorType,
+    },
+    // A zstd stream needs a dictionary that was not supplied.
+    MissingDictionary {
+        dictionary_id: u32,
+    },
+    // A control entry reads past the source or writes past the new file.
+    EntryOutOfBounds {
+        entry: OffsetControlEntry,
+        source_len: u64,
+        new_file_size: u64,
+    },
+    // The patch would read source bytes that an earlier entry already
+    // overwrote, so it cannot be applied in place.
+    InPlaceUnsafe {
+        index: usize,
+    },
+    // The control stream ends partway through an entry.
+    TruncatedControlEntry {
+        index: usize,
+        // Byte offset of the partial entry in the decompressed ctrl stream.
+        offset: u64,
+        available: usize,
+    },
+    // A stream is declared longer than the bytes left for it in the patch,
+    // or decompressed to fewer bytes than the control entries need.
+    TruncatedStream {
+        name: &'static str,
+        len: u64,
+        available: u64,
+    },
+    // The control entries read more bytes from the diff stream than it has.
+    DiffLengthMismatch {
+        expected: u64,
+        found: u64,
+    },
+    // The control entries read more bytes from the extra stream than it has.
+    ExtraLengthMismatch {
+        expected: u64,
+        found: u64,
+    },
+    // A brotli stream declares a window larger than
+    // `BsdiffReaderOptions::max_brotli_window`.
+    BrotliWindowTooLarge {
+        window_size: u64,
+        max: u64,
+    },
+    // Applying would produce more than `BsdiffReaderOptions::max_output`.
+    OutputTooLarge {
+        size: u64,
+        max: u64,
+    },
+    // The source or new file does not have the CRC32 a sidecar expects.
+    ChecksumMismatch {
+        file: &'static str,
+        expected: u32,
+        found: u32,
+    },
+    // Line `line` of a sidecar file is malformed or uses an unsupported
+    // key.
+    InvalidSidecar {
+        line: usize,
+        message: String,
+    },
+    // A message given to `proto::decode` is not a valid `bsdump.Patch`.
+    InvalidProto(String),
+    // The patch parsed, but its content is inconsistent.
+    CorruptPatch(String),
+    Parse(binread::Error),
+    Io(io::Error),
+}
+
+impl fmt::Display for BsdumpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return match self {
+            BsdumpError::NotABsdiffPatch { magic } => write!(
+                f,
+                "not a bsdiff patch, magic is {:02x?} (\"{}\")",
+                magic,
+                magic.escape_ascii()
+            ),
+            BsdumpError::InvalidCompressorType {
+                magic,
+                index,
+                value,
+            } => write!(
+                f,
+                "\"{}\" has invalid compressor type {} at magic byte {}",
+                magic.escape_ascii(),
+                value,
+                index
+            ),
+            BsdumpError::ReservedMagicByte {
+                magic,
+                index,
+                value,
+            } => write!(
+                f,
+                "\"{}\" has {} in reserved magic byte {}, expected 0",
+                magic.escape_ascii(),
+                value,
+                index
+            ),
+            BsdumpError::ImplausibleHeader {
+                field,
+                value,
+                file_len,
+            } => write!(
+                f,
+                "implausible header, {} is {} but the patch is only {} bytes",
+                field, value, file_len
+            ),
+            BsdumpError::CompressorNotCompiled { compressor_type } => write!(
+                f,
+                "{:?} support is not compiled in, enable the {} feature",
+                compressor_type,
+                compressor_type.feature_name()
+            ),
+            BsdumpError::MissingDictionary { dictionary_id } => write!(
+                f,
+                "zstd stream requires dictionary {}, supply one with zstd_dictionary()",
+                dictionary_id
+            ),
+            BsdumpError::EntryOutOfBounds {
+                entry,
+                source_len,
+                new_file_size,
+            } => write!(
+                f,
+                "control entry {} reads source [{}, {}) of {} bytes and writes target [{}, {}) of {} bytes",
+                entry.index,
+                entry.source_offset,
+                entry
+                    .source_offset
+                    .saturating_add(i64::try_from(entry.entry.diff_size).unwrap_or(i64::MAX)),
+                source_len,
+                entry.target_offset,
+                entry
+                    .target_offset
+                    .saturating_add(entry.entry.diff_size)
+                    .saturating_add(entry.entry.extra_size),
+                new_file_size
+            ),
+            BsdumpError::InPlaceUnsafe { index } => write!(
+                f,
+                "cannot apply in place, control entry {} reads source bytes that were already overwritten",
+                index
+            ),
+            BsdumpError::TruncatedControlEntry {
+                index,
+                offset,
+                available,
+            } => write!(
+                f,
+                "control entry {} at ctrl stream offset {} is truncated, only {} of 24 bytes present",
+                index, offset, available
+            ),
+            BsdumpError::TruncatedStream {
+                name,
+                len,
+                available,
+            } => write!(
+                f,
+                "{} stream needs {} bytes, but only {} are available",
+                name, len, available
+            ),
+            BsdumpError::DiffLengthMismatch { expected, found } => write!(
+                f,
+                "control entries read {} bytes from the diff stream, but it has {}",
+                expected, found
+            ),
+            BsdumpError::ExtraLengthMismatch { expected, found } => write!(
+                f,
+                "control entries read {} bytes from the extra stream, but it has {}",
+                expected, found
+            ),
+            BsdumpError::BrotliWindowTooLarge { window_size, max } => write!(
+                f,
+                "brotli stream needs a {} byte window, more than the allowed {}",
+                window_size, max
+            ),
+            BsdumpError::OutputTooLarge { size, max } => write!(
+                f,
+                "new file of {} bytes is larger than the allowed {}",
+                size, max
+            ),
+            BsdumpError::ChecksumMismatch {
+                file,
+                expected,
+                found,
+            } => write!(
+                f,
+                "{} has CRC32 {:08x}, expected {:08x}",
+                file, found, expected
+            ),
+            BsdumpError::InvalidSidecar { line, message } => {
+                write!(f, "invalid sidecar, line {}: {}", line, message)
+            }
+            BsdumpError::InvalidProto(msg) => write!(f, "malformed protobuf message: {}", msg),
+            BsdumpError::CorruptPatch(msg) => write!(f, "corrupt patch: {}", msg),
+            BsdumpError::Parse(e) => write!(f, "failed to parse patch: {}", e),
+            BsdumpError::Io(e) => write!(f, "{}", e),
+        };
+    }
+}
+
+impl std::error::Error for BsdumpError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        return match self {
+            BsdumpError::Parse(e) => Some(e),
+            BsdumpError::Io(e) => Some(e),
+            _ => None,
+        };
+    }
+}
+
+impl From<binread::Error> for BsdumpError {
+    fn from(e: binread::Error) -> Self {
+        return BsdumpError::Parse(e);
+    }
+}
+
+impl From<io::Error> for BsdumpError {
+    fn from(e: io::Error) -> Self {
+        return BsdumpError::Io(e);
+    }
+}
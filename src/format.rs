@@ -0,0 +1,20 @@
+const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+
+/// Formats a byte count for display. With `human` set, sizes of 1 KiB and
+/// up are scaled to binary units with one decimal, e.g. "1.5 MiB";
+/// otherwise the exact count is printed as a bare integer for scripts.
+pub fn format_size(n: u64, human: bool) -> String {
+    if !human {
+        return n.to_string();
+    }
+    if n < 1024 {
+        return format!("{} B", n);
+    }
+    let mut value = n as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit + 1 < UNITS.len() {
+        value /= 1024.0;
+        unit += 1;
+    }
+    return format!("{:.1} {}", value, UNITS[unit]);
+}
@@ -0,0 +1,16 @@
+pub mod bsdiff_format;
+pub mod compare;
+pub mod compat;
+pub mod compression;
+pub mod dot;
+pub mod dump;
+pub mod error;
+pub mod format;
+pub mod options;
+#[cfg(feature = "proto")]
+pub mod proto;
+pub mod record;
+pub mod report;
+pub mod sidecar;
+pub mod stream;
+pub mod writer;
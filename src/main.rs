@@ -1,33 +1,1171 @@
-use bsdiff_format::BsdiffReader;
+use bsdump::bsdiff_format::{
+    BsdiffFormat, BsdiffReader, CompressorType, ControlEntry, OffsetControlEntry,
+};
+use bsdump::compare::{
+    diff_control_entries, first_difference, first_divergence, Divergence, EntryChange,
+};
+use bsdump::compat::{find_applier, APPLIERS};
+use bsdump::compression::compress;
+use bsdump::dot::write_dot;
+use bsdump::dump::{
+    dump, write_entries, write_header, write_raw_magic, write_source_plan, OutputFormat,
+};
+use bsdump::error::BsdumpError;
+use bsdump::format::format_size;
+use bsdump::options::BsdiffReaderOptions;
 use std::fs;
+use std::io::Write;
 
-mod bsdiff_format;
+// Process exit codes, so scripts can tell failure classes apart.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ExitCode {
+    Usage = 1,
+    FileNotFound = 2,
+    ParseError = 3,
+    ValidationFailed = 4,
+    ApplyFailed = 5,
+    WriteFailed = 6,
+}
 
-fn dump_bspatch(payload: &[u8]) {
-    let reader = BsdiffReader::new(payload).expect("Failed to parse bsdiff header");
-    let header = reader.header;
-    println!("{:?}", header);
-    for entry in reader.control_entries() {
-        println!("{:?}", entry);
+impl From<&BsdumpError> for ExitCode {
+    fn from(e: &BsdumpError) -> Self {
+        return match e {
+            BsdumpError::CorruptPatch(_)
+            | BsdumpError::EntryOutOfBounds { .. }
+            | BsdumpError::InPlaceUnsafe { .. }
+            | BsdumpError::TruncatedControlEntry { .. }
+            | BsdumpError::TruncatedStream { .. }
+            | BsdumpError::ChecksumMismatch { .. }
+            | BsdumpError::OutputTooLarge { .. }
+            | BsdumpError::DiffLengthMismatch { .. }
+            | BsdumpError::ExtraLengthMismatch { .. }
+            | BsdumpError::BrotliWindowTooLarge { .. } => ExitCode::ValidationFailed,
+            BsdumpError::NotABsdiffPatch { .. }
+            | BsdumpError::InvalidCompressorType { .. }
+            | BsdumpError::ReservedMagicByte { .. }
+            | BsdumpError::ImplausibleHeader { .. }
+            | BsdumpError::MissingDictionary { .. }
+            | BsdumpError::InvalidSidecar { .. }
+            | BsdumpError::InvalidProto(_)
+            | BsdumpError::CompressorNotCompiled { .. }
+            | BsdumpError::Parse(_)
+            | BsdumpError::Io(_) => ExitCode::ParseError,
+        };
     }
 }
 
-fn main() -> Result<(), i32> {
-    let args: Vec<String> = std::env::args().collect();
-    if args.len() != 2 {
-        println!("Usage: {} <bsdiff patch>", args[0]);
-        return Err(1);
+// Entries smaller than either minimum are left out of the dump.
+#[derive(Debug, Clone, Copy, Default)]
+struct SizeFilter {
+    min_diff: u64,
+    min_extra: u64,
+}
+
+impl SizeFilter {
+    fn is_set(&self) -> bool {
+        return self.min_diff > 0 || self.min_extra > 0;
+    }
+
+    fn matches(&self, entry: &ControlEntry) -> bool {
+        return entry.diff_size >= self.min_diff && entry.extra_size >= self.min_extra;
+    }
+}
+
+enum Command {
+    Dump {
+        verbose: bool,
+        summary: bool,
+        limit: Option<usize>,
+        filter: SizeFilter,
+        // Print exact byte counts instead of KiB/MiB.
+        raw_bytes: bool,
+        format: OutputFormat,
+        // Log every stream decompression to stderr.
+        trace_decompress: bool,
+        // Dump the whole entries of a ctrl stream that ends mid-entry.
+        lenient: bool,
+        path: String,
+    },
+    WhatIf(String),
+    Dot(String),
+    Count(String),
+    CtrlInfo(String),
+    // The aggregate numbers of `PatchReport` as JSON.
+    StatsJson(String),
+    // Rewrite a patch as BSDIFF40 to stdout, for the stock bspatch.
+    Canonical(String),
+    // The decompressed ctrl stream bytes to stdout, as stored.
+    RawCtrl(String),
+    // Re-print the summary every time the patch file changes.
+    #[cfg(feature = "follow")]
+    Follow(String),
+    DiffEntries {
+        old_path: String,
+        new_path: String,
+    },
+    FirstDiff {
+        old_path: String,
+        new_path: String,
+    },
+    // Apply a patch and compare the result with the expected target.
+    Verify {
+        source_path: String,
+        patch_path: String,
+        target_path: String,
+    },
+    // Fix an off-by-one compressed_diff_size, writing the result if an
+    // output is given.
+    Repair {
+        path: String,
+        output_path: Option<String>,
+    },
+    // The source ranges the patch reads, as JSON.
+    SourcePlan {
+        source_len: u64,
+        path: String,
+    },
+    // Check the format and compressors against a built-in applier profile.
+    CheckCompat {
+        applier: String,
+        path: String,
+    },
+    // Rewrite a patch as BSDF2.
+    Convert {
+        path: String,
+        output_path: String,
+    },
+    Apply {
+        source_path: String,
+        output_path: Option<String>,
+        dry_run: bool,
+        raw_bytes: bool,
+        trace_decompress: bool,
+        path: String,
+    },
+}
+
+fn parse_args(args: &[String]) -> Option<Command> {
+    // Modes that compare two patches.
+    type TwoPatchCommand = fn(String, String) -> Command;
+    let two_patch_mode = match args.get(1).map(String::as_str) {
+        Some("diff-entries") => Some(
+            (|old_path, new_path| Command::DiffEntries { old_path, new_path }) as TwoPatchCommand,
+        ),
+        Some("--first-diff") => Some(
+            (|old_path, new_path| Command::FirstDiff { old_path, new_path }) as TwoPatchCommand,
+        ),
+        _ => None,
+    };
+    if let Some(command) = two_patch_mode {
+        if args.len() != 4 {
+            return None;
+        }
+        return Some(command(args[2].clone(), args[3].clone()));
+    }
+    if args.get(1).map(String::as_str) == Some("verify") {
+        let mut source_path = None;
+        let mut patch_path = None;
+        let mut target_path = None;
+        let mut iter = args[2..].iter();
+        while let Some(arg) = iter.next() {
+            let slot = match arg.as_str() {
+                "--source" => &mut source_path,
+                "--patch" => &mut patch_path,
+                "--target" => &mut target_path,
+                _ => return None,
+            };
+            *slot = Some(iter.next()?.clone());
+        }
+        return Some(Command::Verify {
+            source_path: source_path?,
+            patch_path: patch_path?,
+            target_path: target_path?,
+        });
+    }
+    if args.get(1).map(String::as_str) == Some("--repair") {
+        return match &args[2..] {
+            [path] => Some(Command::Repair {
+                path: path.clone(),
+                output_path: None,
+            }),
+            [path, flag, output_path] if flag == "--output" => Some(Command::Repair {
+                path: path.clone(),
+                output_path: Some(output_path.clone()),
+            }),
+            _ => None,
+        };
+    }
+    if args.get(1).map(String::as_str) == Some("--source-plan") {
+        if args.len() != 5 || args[2] != "--source-len" {
+            return None;
+        }
+        return Some(Command::SourcePlan {
+            source_len: args[3].parse().ok()?,
+            path: args[4].clone(),
+        });
+    }
+    if args.get(1).map(String::as_str) == Some("check-compat") {
+        if args.len() != 5 || args[2] != "--applier" {
+            return None;
+        }
+        return Some(Command::CheckCompat {
+            applier: args[3].clone(),
+            path: args[4].clone(),
+        });
+    }
+    if args.get(1).map(String::as_str) == Some("convert") {
+        // Only BSDF2 can be written, but name the target format explicitly.
+        if args.len() != 6 || args[2] != "--to" || args[3] != "bsdf2" {
+            return None;
+        }
+        return Some(Command::Convert {
+            path: args[4].clone(),
+            output_path: args[5].clone(),
+        });
+    }
+    // Modes that take nothing but a patch.
+    let single_patch_mode = match args.get(1).map(String::as_str) {
+        Some("--what-if") => Some(Command::WhatIf as fn(String) -> Command),
+        Some("--count") => Some(Command::Count as fn(String) -> Command),
+        Some("--ctrl-info") => Some(Command::CtrlInfo as fn(String) -> Command),
+        Some("--stats-json") => Some(Command::StatsJson as fn(String) -> Command),
+        Some("--dot") => Some(Command::Dot as fn(String) -> Command),
+        Some("--canonical") => Some(Command::Canonical as fn(String) -> Command),
+        Some("--raw-ctrl") => Some(Command::RawCtrl as fn(String) -> Command),
+        #[cfg(feature = "follow")]
+        Some("--follow") => Some(Command::Follow as fn(String) -> Command),
+        _ => None,
+    };
+    if let Some(command) = single_patch_mode {
+        if args.len() != 3 {
+            return None;
+        }
+        return Some(command(args[2].clone()));
+    }
+    let mut verbose = false;
+    let mut summary = false;
+    let mut limit = None;
+    let mut source_path = None;
+    let mut output_path = None;
+    let mut dry_run = false;
+    let mut raw_bytes = false;
+    let mut format = OutputFormat::Text;
+    let mut filter = SizeFilter::default();
+    let mut trace_decompress = false;
+    let mut lenient = false;
+    let mut path = None;
+    let mut iter = args[1..].iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "-v" | "--verbose" => verbose = true,
+            "--summary" => summary = true,
+            "--limit" => limit = Some(iter.next()?.parse().ok()?),
+            "--min-diff" => filter.min_diff = iter.next()?.parse().ok()?,
+            "--min-extra" => filter.min_extra = iter.next()?.parse().ok()?,
+            "--apply" => source_path = Some(iter.next()?.clone()),
+            "--output" => output_path = Some(iter.next()?.clone()),
+            "--dry-run" => dry_run = true,
+            "--bytes" => raw_bytes = true,
+            "--trace-decompress" => trace_decompress = true,
+            "--lenient" => lenient = true,
+            "--table" => format = OutputFormat::Table,
+            "--format" => {
+                format = match iter.next()?.as_str() {
+                    "text" => OutputFormat::Text,
+                    "json" => OutputFormat::Json,
+                    "csv" => OutputFormat::Csv,
+                    "table" => OutputFormat::Table,
+                    _ => return None,
+                }
+            }
+            _ if arg.starts_with('-') => return None,
+            _ if path.is_none() => path = Some(arg.clone()),
+            _ => return None,
+        }
+    }
+    if let Some(source_path) = source_path {
+        // Applying needs every entry, so a partial one can't be skipped.
+        if (output_path.is_none() && !dry_run) || lenient {
+            return None;
+        }
+        return Some(Command::Apply {
+            source_path,
+            output_path,
+            dry_run,
+            raw_bytes,
+            trace_decompress,
+            path: path?,
+        });
+    }
+    if output_path.is_some() || dry_run {
+        return None;
+    }
+    // The summary and the size filter only exist as text.
+    if (summary || filter.is_set()) && format != OutputFormat::Text {
+        return None;
+    }
+    return Some(Command::Dump {
+        verbose,
+        summary,
+        limit,
+        filter,
+        raw_bytes,
+        format,
+        trace_decompress,
+        lenient,
+        path: path?,
+    });
+}
+
+// Patches are mostly read front to back, so ask the kernel to read ahead
+// aggressively. This is only a hint, failures are ignored.
+#[cfg(unix)]
+fn advise_sequential(map: &memmap::Mmap) {
+    if map.is_empty() {
+        return;
+    }
+    let addr = map.as_ptr() as *mut libc::c_void;
+    unsafe {
+        libc::madvise(addr, map.len(), libc::MADV_SEQUENTIAL);
+        libc::madvise(addr, map.len(), libc::MADV_WILLNEED);
+    }
+}
+
+#[cfg(not(unix))]
+fn advise_sequential(_map: &memmap::Mmap) {}
+
+// The contents of a file opened by `map_file`. A zero length file can't be
+// mapped, but it is valid input, e.g. the source of a full image patch.
+enum MappedFile {
+    Mapped(memmap::Mmap),
+    Empty,
+}
+
+impl AsRef<[u8]> for MappedFile {
+    fn as_ref(&self) -> &[u8] {
+        return match self {
+            MappedFile::Mapped(map) => map.as_ref(),
+            MappedFile::Empty => &[],
+        };
     }
-    let path = &args[1];
+}
+
+fn map_file(path: &str) -> Result<MappedFile, ExitCode> {
     let path = std::path::Path::new(path);
     if !std::path::Path::exists(path) {
         println!("{} does not exists", path.display());
-        return Err(2);
+        return Err(ExitCode::FileNotFound);
+    }
+    let map = fs::File::open(path).and_then(|file| {
+        if file.metadata()?.len() == 0 {
+            return Ok(MappedFile::Empty);
+        }
+        let map = unsafe { memmap::Mmap::map(&file) }?;
+        advise_sequential(&map);
+        return Ok(MappedFile::Mapped(map));
+    });
+    return map.map_err(|e| {
+        eprintln!("Failed to open {}: {}", path.display(), e);
+        ExitCode::FileNotFound
+    });
+}
+
+fn parse_patch(payload: &[u8]) -> Result<BsdiffReader<'_>, ExitCode> {
+    return parse_patch_with_options(payload, BsdiffReaderOptions::default());
+}
+
+fn parse_patch_with_options(
+    payload: &[u8],
+    options: BsdiffReaderOptions,
+) -> Result<BsdiffReader<'_>, ExitCode> {
+    return BsdiffReader::with_options(payload, options).map_err(|e| {
+        eprintln!("Failed to parse bsdiff header: {}", e);
+        ExitCode::from(&e)
+    });
+}
+
+// Options that print a line to stderr for every stream decompressed, if
+// `trace` is set, and the mask and diff stream statistics to stdout, if
+// `verbose` is set.
+fn trace_options(options: BsdiffReaderOptions, trace: bool, verbose: bool) -> BsdiffReaderOptions {
+    if !trace && !verbose {
+        return options;
+    }
+    // Streams are decompressed again when needed, the statistics only
+    // need printing once per stream.
+    let mut described = Vec::new();
+    return options.trace_decompress(move |stream| {
+        if trace {
+            let outcome = match &stream.error {
+                Some(e) => format!("failed after {} bytes: {}", stream.output_len, e),
+                None => format!("{} bytes", stream.output_len),
+            };
+            eprintln!(
+                "trace: {} stream ({:?}): {} bytes -> {} in {:?}",
+                stream.stream, stream.compressor, stream.input_len, outcome, stream.elapsed
+            );
+        }
+        if !verbose || stream.error.is_some() || described.contains(&stream.stream) {
+            return;
+        }
+        described.push(stream.stream);
+        match stream.stream {
+            "mask" => println!(
+                "Mask data: {}/{} = {}",
+                stream.input_len,
+                stream.output_len,
+                stream.input_len as f32 / stream.output_len as f32,
+            ),
+            "diff" => println!(
+                "Diff stream has {}/{} = {}% zeros",
+                stream.zero_bytes,
+                stream.output_len,
+                stream.zero_bytes as f64 / stream.output_len as f64 * 100.0
+            ),
+            _ => {}
+        }
+    });
+}
+
+fn print_summary(reader: &BsdiffReader, human: bool) {
+    println!("control entries: {}", reader.num_control_entries());
+    let stats = reader.entry_size_stats();
+    println!(
+        "diff size: avg {}, max {}",
+        format_size(stats.avg_diff.round() as u64, human),
+        format_size(stats.max_diff, human)
+    );
+    println!(
+        "extra size: avg {}, max {}",
+        format_size(stats.avg_extra.round() as u64, human),
+        format_size(stats.max_extra, human)
+    );
+    println!(
+        "new file size: {}",
+        format_size(reader.get_new_file_size(), human)
+    );
+    println!("new data: {:.1}%", reader.new_data_fraction() * 100.0);
+    let fragmentation = reader.source_fragmentation();
+    println!(
+        "source reads: {} of avg {}, {} seeks over {}",
+        fragmentation.num_reads,
+        format_size(fragmentation.avg_read_len.round() as u64, human),
+        fragmentation.num_seeks,
+        format_size(fragmentation.seek_distance_sum, human)
+    );
+    let duplicate_runs = reader.duplicate_entry_runs();
+    println!(
+        "duplicate entry runs: {} ({} redundant entries)",
+        duplicate_runs.len(),
+        duplicate_runs
+            .iter()
+            .map(|run| run.len() - 1)
+            .sum::<usize>()
+    );
+    match reader.operations() {
+        Ok(operations) => println!(
+            "pure copies: {} (entries with all zero diff bytes)",
+            operations.iter().filter(|op| op.is_pure_copy).count()
+        ),
+        Err(e) => eprintln!("Failed to scan diff bytes: {}", e),
+    }
+    let streams = [
+        ("ctrl", reader.compressed_ctrl_stream()),
+        ("diff", reader.compressed_diff_stream()),
+        ("extra", reader.compressed_extra_stream()),
+    ];
+    for (name, stream) in streams {
+        println!(
+            "compressed {} stream: {}",
+            name,
+            format_size(stream.len() as u64, human)
+        );
+    }
+    match reader.expanded_streams() {
+        Ok(expanded) => {
+            for stream in &expanded {
+                eprintln!("warning: {}", stream);
+            }
+            println!("expanded streams: {}", expanded.len());
+        }
+        Err(e) => eprintln!("Failed to measure stream compression: {}", e),
     }
-    let file = fs::File::open(&path).unwrap();
-    let mmap = unsafe { memmap::Mmap::map(&file).unwrap() };
-    let data = mmap.as_ref();
+    if reader.has_mask() {
+        println!("mask blocks: {}", reader.mask_entries().count());
+        println!(
+            "masked bytes: {}",
+            format_size(reader.masked_bytes(), human)
+        );
+    }
+}
 
-    dump_bspatch(data);
+fn dump_bspatch(
+    payload: &[u8],
+    options: BsdiffReaderOptions,
+    verbose: bool,
+    summary: bool,
+    filter: SizeFilter,
+    human: bool,
+    format: OutputFormat,
+) -> Result<(), ExitCode> {
+    let reader = parse_patch_with_options(payload, options)?;
+    if reader.trailing_ctrl_bytes() > 0 {
+        eprintln!(
+            "warning: ignoring {} bytes after the last whole control entry",
+            reader.trailing_ctrl_bytes()
+        );
+    }
+    let stdout = std::io::stdout();
+    let mut out = stdout.lock();
+    if format != OutputFormat::Text {
+        if let Err(e) = dump(&reader, &mut out, format) {
+            eprintln!("Failed to write dump: {}", e);
+        }
+        return Ok(());
+    }
+    let result = write_header(&reader, &mut out).and_then(|_| {
+        if verbose {
+            write_raw_magic(&reader.header, &mut out)?;
+        }
+        if summary {
+            print_summary(&reader, human);
+            return Ok(());
+        }
+        if filter.is_set() {
+            return write_filtered_entries(&reader, filter, &mut out);
+        }
+        return write_entries(&reader, &mut out);
+    });
+    if let Err(e) = result {
+        eprintln!("Failed to write dump: {}", e);
+    }
     return Ok(());
 }
+
+// Like `write_entries`, but only the entries passing `filter`, with their
+// index and offsets in the whole patch.
+fn write_filtered_entries<W: Write>(
+    reader: &BsdiffReader,
+    filter: SizeFilter,
+    out: &mut W,
+) -> std::io::Result<()> {
+    let mut entries = reader.control_entries_with_offsets();
+    let mut count = 0;
+    for entry in entries.by_ref() {
+        count += 1;
+        if filter.matches(&entry.entry) {
+            writeln!(out, "{}", format_entry(&entry))?;
+        }
+    }
+    if entries.is_truncated() {
+        writeln!(out, "... stopped after {} entries", count)?;
+    }
+    return Ok(());
+}
+
+fn format_entry(entry: &OffsetControlEntry) -> String {
+    return format!(
+        "#{} tgt {} src {}: diff_size {} extra_size {} offset_increment {}",
+        entry.index,
+        entry.target_offset,
+        entry.source_offset,
+        entry.entry.diff_size,
+        entry.entry.extra_size,
+        entry.entry.offset_increment
+    );
+}
+
+fn format_field_delta(name: &str, old: i128, new: i128) -> Option<String> {
+    if old == new {
+        return None;
+    }
+    return Some(format!("{} {} -> {} ({:+})", name, old, new, new - old));
+}
+
+fn diff_entries(old_payload: &[u8], new_payload: &[u8]) -> Result<(), ExitCode> {
+    let old = parse_patch(old_payload)?;
+    let new = parse_patch(new_payload)?;
+    for change in diff_control_entries(&old, &new) {
+        match change {
+            EntryChange::Added(entry) => println!("+ {}", format_entry(&entry)),
+            EntryChange::Removed(entry) => println!("- {}", format_entry(&entry)),
+            EntryChange::Changed { old, new } => {
+                let deltas: Vec<String> = [
+                    format_field_delta(
+                        "diff_size",
+                        old.entry.diff_size as i128,
+                        new.entry.diff_size as i128,
+                    ),
+                    format_field_delta(
+                        "extra_size",
+                        old.entry.extra_size as i128,
+                        new.entry.extra_size as i128,
+                    ),
+                    format_field_delta(
+                        "offset_increment",
+                        old.entry.offset_increment as i128,
+                        new.entry.offset_increment as i128,
+                    ),
+                ]
+                .iter()
+                .flatten()
+                .cloned()
+                .collect();
+                println!(
+                    "~ #{}/#{} tgt {}: {}",
+                    old.index,
+                    new.index,
+                    old.target_offset,
+                    deltas.join(", ")
+                );
+            }
+        }
+    }
+    return Ok(());
+}
+
+fn first_diff(old_payload: &[u8], new_payload: &[u8]) -> Result<(), ExitCode> {
+    let old = parse_patch(old_payload)?;
+    let new = parse_patch(new_payload)?;
+    let divergence = first_divergence(&old, &new).map_err(|e| {
+        eprintln!("Failed to compare patches: {}", e);
+        ExitCode::from(&e)
+    })?;
+    match divergence {
+        None => println!("patches are equivalent"),
+        Some(Divergence::Header { field, old, new }) => {
+            println!("header {} differs: {} vs {}", field, old, new)
+        }
+        Some(Divergence::ControlEntry { index, old, new }) => {
+            println!("control entry {} differs: {:?} vs {:?}", index, old, new)
+        }
+        Some(Divergence::Stream { name, offset }) => {
+            println!("{} stream differs at offset {}", name, offset)
+        }
+    }
+    return Ok(());
+}
+
+fn what_if(payload: &[u8]) -> Result<(), ExitCode> {
+    let reader = parse_patch(payload)?;
+    let streams = [
+        (
+            "ctrl",
+            reader.compressed_ctrl_stream().len(),
+            Ok(reader.decompressed_ctrl_stream().to_vec()),
+        ),
+        (
+            "diff",
+            reader.compressed_diff_stream().len(),
+            reader.decompressed_diff_stream(),
+        ),
+        (
+            "extra",
+            reader.compressed_extra_stream().len(),
+            reader.decompressed_extra_stream(),
+        ),
+    ];
+    print!("{:<8}{:>12}{:>12}", "stream", "raw", "current");
+    for compressor_type in &CompressorType::ALL {
+        print!("{:>12}", format!("{:?}", compressor_type));
+    }
+    println!();
+    for (name, compressed_size, decompressed) in streams {
+        let decompressed = decompressed.map_err(|e| {
+            eprintln!("Failed to decompress {} stream: {}", name, e);
+            ExitCode::from(&e)
+        })?;
+        print!(
+            "{:<8}{:>12}{:>12}",
+            name,
+            decompressed.len(),
+            compressed_size
+        );
+        for compressor_type in &CompressorType::ALL {
+            match compress(&decompressed, *compressor_type) {
+                Ok(compressed) => print!("{:>12}", compressed.len()),
+                Err(_) => print!("{:>12}", "error"),
+            }
+        }
+        println!();
+    }
+    return Ok(());
+}
+
+fn apply_patch(
+    payload: &[u8],
+    options: BsdiffReaderOptions,
+    source: &[u8],
+    output_path: Option<String>,
+    dry_run: bool,
+    human: bool,
+) -> Result<(), ExitCode> {
+    let reader = parse_patch_with_options(payload, options)?;
+    if dry_run {
+        return match reader.dry_apply(source.len() as u64) {
+            Ok(output_size) => {
+                println!(
+                    "patch applies, output size {}",
+                    format_size(output_size, human)
+                );
+                Ok(())
+            }
+            Err(e) => {
+                eprintln!("patch does not apply: {}", e);
+                Err(ExitCode::from(&e))
+            }
+        };
+    }
+    let output_path = output_path.expect("--output is required without --dry-run");
+    let file = fs::File::create(&output_path).map_err(|e| {
+        eprintln!("Failed to create {}: {}", output_path, e);
+        ExitCode::WriteFailed
+    })?;
+    let mut writer = std::io::BufWriter::new(OutputWriter {
+        inner: file,
+        failed: false,
+    });
+    let result = reader
+        .apply_to(source, &mut writer)
+        .and_then(|_| Ok(writer.flush()?));
+    return result.map_err(|e| {
+        eprintln!("Failed to apply patch: {}", e);
+        if writer.get_ref().failed {
+            ExitCode::WriteFailed
+        } else {
+            ExitCode::ApplyFailed
+        }
+    });
+}
+
+// Forwards to `inner` and remembers whether a write failed. Decompression
+// errors are `BsdumpError::Io` as well, this tells the two apart.
+struct OutputWriter<W: Write> {
+    inner: W,
+    failed: bool,
+}
+
+impl<W: Write> Write for OutputWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let result = self.inner.write(buf);
+        self.failed |= result.is_err();
+        return result;
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        let result = self.inner.flush();
+        self.failed |= result.is_err();
+        return result;
+    }
+}
+
+fn verify(payload: &[u8], source: &[u8], target: &[u8]) -> Result<(), ExitCode> {
+    let reader = parse_patch(payload)?;
+    let output = reader.apply(source).map_err(|e| {
+        eprintln!("Failed to apply patch: {}", e);
+        ExitCode::ApplyFailed
+    })?;
+    return match first_difference(&output, target) {
+        None => {
+            println!("patch produces the expected target");
+            Ok(())
+        }
+        Some(offset) => {
+            println!(
+                "output differs from the target at offset {} (output {} bytes, target {} bytes)",
+                offset,
+                output.len(),
+                target.len()
+            );
+            Err(ExitCode::ValidationFailed)
+        }
+    };
+}
+
+fn check_compat(payload: &[u8], applier: &str) -> Result<(), ExitCode> {
+    let profile = match find_applier(applier) {
+        Some(profile) => profile,
+        None => {
+            let names: Vec<&str> = APPLIERS.iter().map(|profile| profile.name).collect();
+            eprintln!(
+                "Unknown applier {}, known appliers: {}",
+                applier,
+                names.join(", ")
+            );
+            return Err(ExitCode::Usage);
+        }
+    };
+    // Only the header matters, so a patch whose codec isn't compiled in
+    // can still be checked.
+    let header = BsdiffFormat::parse_unchecked(payload)
+        .and_then(|header| header.validate_magic().map(|_| header))
+        .map_err(|e| {
+            eprintln!("Failed to parse bsdiff header: {}", e);
+            ExitCode::from(&e)
+        })?;
+    let problems = profile.check(&header);
+    if problems.is_empty() {
+        println!("compatible with {} ({})", profile.name, profile.description);
+        return Ok(());
+    }
+    println!(
+        "not compatible with {} ({}):",
+        profile.name, profile.description
+    );
+    for problem in &problems {
+        println!("  {}", problem);
+    }
+    return Err(ExitCode::ValidationFailed);
+}
+
+fn repair(payload: &[u8], output_path: Option<String>) -> Result<(), ExitCode> {
+    let size = match BsdiffReader::find_diff_size_repair(payload) {
+        Ok(None) => {
+            println!("patch is fine, nothing to repair");
+            return Ok(());
+        }
+        Ok(Some(size)) => size,
+        Err(e) => {
+            eprintln!("Cannot repair patch: {}", e);
+            return Err(ExitCode::from(&e));
+        }
+    };
+    let header = BsdiffFormat::parse_unchecked(payload).expect("repaired patch has a header");
+    println!(
+        "compressed_diff_size should be {}, header says {}",
+        size, header.compressed_diff_size
+    );
+    if let Some(output_path) = output_path {
+        let patch = BsdiffReader::with_compressed_diff_size(payload, size);
+        return fs::write(&output_path, patch).map_err(|e| {
+            eprintln!("Failed to write {}: {}", output_path, e);
+            ExitCode::WriteFailed
+        });
+    }
+    return Ok(());
+}
+
+fn convert_to_bsdf2(payload: &[u8], output_path: &str) -> Result<(), ExitCode> {
+    let reader = parse_patch(payload)?;
+    let patch = reader.to_bsdf2().map_err(|e| {
+        eprintln!("Failed to convert patch: {}", e);
+        ExitCode::from(&e)
+    })?;
+    return fs::write(output_path, patch).map_err(|e| {
+        eprintln!("Failed to write {}: {}", output_path, e);
+        ExitCode::WriteFailed
+    });
+}
+
+// Watches the patch file and prints its summary whenever its size or
+// modification time changes, until interrupted. The file is read rather
+// than mapped, since the generator may truncate it while we look. A file
+// caught halfway through a rewrite fails to parse; the error is printed
+// and the next change is waited for.
+#[cfg(feature = "follow")]
+fn follow(path: &str) -> Result<(), ExitCode> {
+    if !std::path::Path::new(path).exists() {
+        println!("{} does not exists", path);
+        return Err(ExitCode::FileNotFound);
+    }
+    let mut watcher = FileWatcher::new(path);
+    let mut last_seen = None;
+    loop {
+        let stamp = fs::metadata(path)
+            .ok()
+            .map(|metadata| (metadata.len(), metadata.modified().ok()));
+        if stamp.is_some() && stamp != last_seen {
+            last_seen = stamp;
+            println!("==> {} <==", path);
+            match fs::read(path) {
+                Ok(payload) => match BsdiffReader::new(&payload) {
+                    Ok(reader) => print_summary(&reader, true),
+                    Err(e) => println!("Failed to parse bsdiff header: {}", e),
+                },
+                Err(e) => println!("Failed to read {}: {}", path, e),
+            }
+        }
+        watcher.wait();
+    }
+}
+
+// Blocks until something may have happened to the followed file. On Linux
+// this is inotify on the parent directory, so generators that write a
+// temporary file and rename it over the patch are seen as well as ones
+// that rewrite it in place. Anywhere else, or if inotify can't be set up,
+// it polls twice a second. Spurious wakeups are fine, the caller compares
+// the file's size and modification time before printing anything.
+#[cfg(feature = "follow")]
+struct FileWatcher {
+    #[cfg(target_os = "linux")]
+    inotify_fd: Option<libc::c_int>,
+}
+
+#[cfg(feature = "follow")]
+impl FileWatcher {
+    const POLL_INTERVAL: std::time::Duration = std::time::Duration::from_millis(500);
+
+    #[cfg(target_os = "linux")]
+    fn new(path: &str) -> FileWatcher {
+        use std::os::unix::ffi::OsStrExt;
+        let parent = match std::path::Path::new(path).parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => std::path::Path::new("."),
+        };
+        let parent = match std::ffi::CString::new(parent.as_os_str().as_bytes()) {
+            Ok(parent) => parent,
+            Err(_) => return FileWatcher { inotify_fd: None },
+        };
+        let fd = unsafe { libc::inotify_init1(libc::IN_CLOEXEC) };
+        if fd < 0 {
+            return FileWatcher { inotify_fd: None };
+        }
+        let mask = libc::IN_CLOSE_WRITE | libc::IN_MOVED_TO | libc::IN_CREATE | libc::IN_MODIFY;
+        if unsafe { libc::inotify_add_watch(fd, parent.as_ptr(), mask) } < 0 {
+            unsafe { libc::close(fd) };
+            return FileWatcher { inotify_fd: None };
+        }
+        return FileWatcher {
+            inotify_fd: Some(fd),
+        };
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn new(_path: &str) -> FileWatcher {
+        return FileWatcher {};
+    }
+
+    #[cfg(target_os = "linux")]
+    fn wait(&mut self) {
+        let fd = match self.inotify_fd {
+            Some(fd) => fd,
+            None => return std::thread::sleep(Self::POLL_INTERVAL),
+        };
+        // The events themselves aren't looked at, any of them is a reason
+        // to look at the file again.
+        let mut events = [0u8; 4096];
+        let read =
+            unsafe { libc::read(fd, events.as_mut_ptr() as *mut libc::c_void, events.len()) };
+        if read < 0 && std::io::Error::last_os_error().kind() != std::io::ErrorKind::Interrupted {
+            unsafe { libc::close(fd) };
+            self.inotify_fd = None;
+        }
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn wait(&mut self) {
+        std::thread::sleep(Self::POLL_INTERVAL);
+    }
+}
+
+fn run() -> Result<(), ExitCode> {
+    let args: Vec<String> = std::env::args().collect();
+    let command = match parse_args(&args) {
+        Some(command) => command,
+        None => {
+            println!(
+                "Usage: {} [--verbose] [--summary] [--limit <n>] [--min-diff <n>] [--min-extra <n>] [--bytes] [--table] [--format text|json|csv|table] [--trace-decompress] [--lenient] <bsdiff patch>",
+                args[0]
+            );
+            println!(
+                "       {} --apply <source> (--output <new file> | --dry-run) [--bytes] [--trace-decompress] <bsdiff patch>",
+                args[0]
+            );
+            println!("       {} --what-if <bsdiff patch>", args[0]);
+            println!("       {} --dot <bsdiff patch>", args[0]);
+            println!("       {} --count <bsdiff patch>", args[0]);
+            println!("       {} --ctrl-info <bsdiff patch>", args[0]);
+            println!("       {} --stats-json <bsdiff patch>", args[0]);
+            println!(
+                "       {} --canonical <bsdiff patch> > <BSDIFF40 patch>",
+                args[0]
+            );
+            println!(
+                "       {} --raw-ctrl <bsdiff patch> > <ctrl stream>",
+                args[0]
+            );
+            #[cfg(feature = "follow")]
+            println!("       {} --follow <bsdiff patch>", args[0]);
+            println!(
+                "       {} --source-plan --source-len <n> <bsdiff patch>",
+                args[0]
+            );
+            println!("       {} diff-entries <old patch> <new patch>", args[0]);
+            println!("       {} --first-diff <old patch> <new patch>", args[0]);
+            println!(
+                "       {} verify --source <old file> --patch <bsdiff patch> --target <new file>",
+                args[0]
+            );
+            println!(
+                "       {} --repair <bsdiff patch> [--output <repaired patch>]",
+                args[0]
+            );
+            println!(
+                "       {} check-compat --applier <name> <bsdiff patch>",
+                args[0]
+            );
+            println!(
+                "       {} convert --to bsdf2 <bsdiff patch> <output patch>",
+                args[0]
+            );
+            return Err(ExitCode::Usage);
+        }
+    };
+    match command {
+        Command::Dump {
+            verbose,
+            summary,
+            limit,
+            filter,
+            raw_bytes,
+            format,
+            trace_decompress,
+            lenient,
+            path,
+        } => {
+            let mmap = map_file(&path)?;
+            let mut options = BsdiffReaderOptions::default().lenient_control(lenient);
+            if let Some(limit) = limit {
+                options = options.max_control_entries(limit);
+            }
+            dump_bspatch(
+                mmap.as_ref(),
+                trace_options(options, trace_decompress, verbose),
+                verbose,
+                summary,
+                filter,
+                !raw_bytes,
+                format,
+            )?;
+        }
+        Command::WhatIf(path) => {
+            let mmap = map_file(&path)?;
+            what_if(mmap.as_ref())?;
+        }
+        Command::Count(path) => {
+            let mmap = map_file(&path)?;
+            let reader = parse_patch(mmap.as_ref())?;
+            println!("{}", reader.num_control_entries());
+        }
+        Command::CtrlInfo(path) => {
+            let mmap = map_file(&path)?;
+            let reader = parse_patch(mmap.as_ref())?;
+            let compressed = reader.compressed_ctrl_stream().len();
+            let decompressed = reader.decompressed_ctrl_stream().len();
+            println!(
+                "ctrl stream is {} compressed -> {} decompressed ({:.2}x, {} entries)",
+                compressed,
+                decompressed,
+                decompressed as f64 / compressed as f64,
+                reader.num_control_entries()
+            );
+        }
+        Command::Canonical(path) => {
+            let mmap = map_file(&path)?;
+            let reader = parse_patch(mmap.as_ref())?;
+            let patch = reader.to_bsdiff40().map_err(|e| {
+                eprintln!("Failed to convert patch: {}", e);
+                ExitCode::from(&e)
+            })?;
+            if let Err(e) = std::io::stdout().lock().write_all(&patch) {
+                eprintln!("Failed to write patch: {}", e);
+            }
+        }
+        Command::RawCtrl(path) => {
+            let mmap = map_file(&path)?;
+            // Keep a trailing partial entry, the bytes are written unparsed.
+            let options = BsdiffReaderOptions::default().allow_partial_ctrl_entry(true);
+            let reader = parse_patch_with_options(mmap.as_ref(), options)?;
+            if let Err(e) = std::io::stdout()
+                .lock()
+                .write_all(reader.decompressed_ctrl_stream())
+            {
+                eprintln!("Failed to write ctrl stream: {}", e);
+            }
+        }
+        #[cfg(feature = "follow")]
+        Command::Follow(path) => follow(&path)?,
+        Command::StatsJson(path) => {
+            let mmap = map_file(&path)?;
+            let reader = parse_patch(mmap.as_ref())?;
+            let stdout = std::io::stdout();
+            if let Err(e) = reader.report().write_stats_json(&mut stdout.lock()) {
+                eprintln!("Failed to write stats: {}", e);
+            }
+        }
+        Command::Dot(path) => {
+            let mmap = map_file(&path)?;
+            let reader = parse_patch(mmap.as_ref())?;
+            let stdout = std::io::stdout();
+            if let Err(e) = write_dot(&reader, &mut stdout.lock()) {
+                eprintln!("Failed to write graph: {}", e);
+            }
+        }
+        Command::Verify {
+            source_path,
+            patch_path,
+            target_path,
+        } => {
+            let mmap = map_file(&patch_path)?;
+            let source_mmap = map_file(&source_path)?;
+            let target_mmap = map_file(&target_path)?;
+            verify(mmap.as_ref(), source_mmap.as_ref(), target_mmap.as_ref())?;
+        }
+        Command::Repair { path, output_path } => {
+            let mmap = map_file(&path)?;
+            repair(mmap.as_ref(), output_path)?;
+        }
+        Command::SourcePlan { source_len, path } => {
+            let mmap = map_file(&path)?;
+            let reader = parse_patch(mmap.as_ref())?;
+            let stdout = std::io::stdout();
+            if let Err(e) = write_source_plan(&reader, source_len, &mut stdout.lock()) {
+                eprintln!("Failed to write source plan: {}", e);
+            }
+        }
+        Command::CheckCompat { applier, path } => {
+            let mmap = map_file(&path)?;
+            check_compat(mmap.as_ref(), &applier)?;
+        }
+        Command::Convert { path, output_path } => {
+            let mmap = map_file(&path)?;
+            convert_to_bsdf2(mmap.as_ref(), &output_path)?;
+        }
+        Command::Apply {
+            source_path,
+            output_path,
+            dry_run,
+            raw_bytes,
+            trace_decompress,
+            path,
+        } => {
+            let mmap = map_file(&path)?;
+            let source_mmap = map_file(&source_path)?;
+            apply_patch(
+                mmap.as_ref(),
+                trace_options(BsdiffReaderOptions::default(), trace_decompress, false),
+                source_mmap.as_ref(),
+                output_path,
+                dry_run,
+                !raw_bytes,
+            )?;
+        }
+        Command::DiffEntries { old_path, new_path } => {
+            let old_mmap = map_file(&old_path)?;
+            let new_mmap = map_file(&new_path)?;
+            diff_entries(old_mmap.as_ref(), new_mmap.as_ref())?;
+        }
+        Command::FirstDiff { old_path, new_path } => {
+            let old_mmap = map_file(&old_path)?;
+            let new_mmap = map_file(&new_path)?;
+            first_diff(old_mmap.as_ref(), new_mmap.as_ref())?;
+        }
+    }
+    return Ok(());
+}
+
+fn main() {
+    if let Err(code) = run() {
+        std::process::exit(code as i32);
+    }
+}
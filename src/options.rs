@@ -0,0 +1,212 @@
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::bsdiff_format::{BsdiffReader, CompressorType};
+use crate::error::BsdumpError;
+use crate::sidecar::Sidecar;
+
+/// Called with `(done, total)` as a long running operation makes progress.
+pub type ProgressCallback = Box<dyn FnMut(u64, u64) + Send>;
+
+/// Called once for every stream the reader decompresses, see
+/// `BsdiffReaderOptions::trace_decompress`.
+pub type TraceCallback = Box<dyn FnMut(&DecompressTrace) + Send>;
+
+/// How decompressing one stream went.
+#[derive(Debug, Clone)]
+pub struct DecompressTrace {
+    // "ctrl", "diff", "extra" or "mask".
+    pub stream: &'static str,
+    pub compressor: CompressorType,
+    // Size of the compressed stream.
+    pub input_len: u64,
+    // Bytes decompressed, up to the failure if there was one.
+    pub output_len: u64,
+    // How many of those bytes are zero. Mostly zeros in the diff stream
+    // means the source matches the new file well.
+    pub zero_bytes: u64,
+    pub elapsed: Duration,
+    // The decoder error, `None` if the stream decompressed completely.
+    pub error: Option<String>,
+}
+
+/// The options double as a builder: chain the setters, then finish with
+/// `read`. `BsdiffReader::new` is the shortcut for the defaults.
+pub type BsdiffReaderBuilder = BsdiffReaderOptions;
+
+#[derive(Default)]
+pub struct BsdiffReaderOptions {
+    pub(crate) progress: Option<Mutex<ProgressCallback>>,
+    pub(crate) zstd_dictionary: Option<Vec<u8>>,
+    pub(crate) max_control_entries: Option<usize>,
+    pub(crate) allow_partial_ctrl_entry: bool,
+    pub(crate) footer_len: usize,
+    pub(crate) source_chunk_size: Option<usize>,
+    pub(crate) max_brotli_window: Option<u64>,
+    pub(crate) skip_prefix: usize,
+    #[cfg(feature = "crc32fast")]
+    pub(crate) source_crc32: Option<u32>,
+    #[cfg(feature = "crc32fast")]
+    pub(crate) target_crc32: Option<u32>,
+    pub(crate) max_output: Option<u64>,
+    pub(crate) trace: Option<Mutex<TraceCallback>>,
+    // Magic bytes past the standard 8, see `magic_len`.
+    pub(crate) extra_magic_len: usize,
+}
+
+impl BsdiffReaderOptions {
+    pub fn new() -> BsdiffReaderOptions {
+        return BsdiffReaderOptions::default();
+    }
+
+    /// Registers a callback that receives `(done, total)` updates. While a
+    /// stream decompresses the numbers are compressed bytes consumed, while
+    /// applying they are bytes of the new file written.
+    pub fn progress<F: FnMut(u64, u64) + Send + 'static>(mut self, callback: F) -> Self {
+        self.progress = Some(Mutex::new(Box::new(callback)));
+        return self;
+    }
+
+    /// Dictionary used to decompress zstd streams that were compressed
+    /// against one. Without it such streams fail with
+    /// `BsdumpError::MissingDictionary`.
+    pub fn zstd_dictionary(mut self, dictionary: Vec<u8>) -> Self {
+        self.zstd_dictionary = Some(dictionary);
+        return self;
+    }
+
+    /// Makes `control_entries` stop after `n` entries, to keep tools
+    /// responsive on patches with millions of them. Use
+    /// `ControlEntryIter::is_truncated` to tell whether entries were left
+    /// out. Applying and validating always look at every entry.
+    pub fn max_control_entries(mut self, n: usize) -> Self {
+        self.max_control_entries = Some(n);
+        return self;
+    }
+
+    /// Accepts a control stream that ends partway through an entry instead
+    /// of failing to parse, so the complete entries before it can still be
+    /// inspected. The partial entry is skipped everywhere except
+    /// `BsdiffReader::control_entries_best_effort`, which reports it, and
+    /// its length is `BsdiffReader::trailing_ctrl_bytes`. Strict by default.
+    pub fn allow_partial_ctrl_entry(mut self, allow: bool) -> Self {
+        self.allow_partial_ctrl_entry = allow;
+        return self;
+    }
+
+    /// Lenient control stream parsing for recovering slightly corrupt
+    /// patches: a ctrl stream whose length is not a multiple of 24 yields
+    /// its `floor(len / 24)` whole entries, and the leftover bytes are
+    /// reported by `BsdiffReader::trailing_ctrl_bytes` instead of failing
+    /// to parse. Same as `allow_partial_ctrl_entry`. Strict by default.
+    pub fn lenient_control(self, lenient: bool) -> Self {
+        return self.allow_partial_ctrl_entry(lenient);
+    }
+
+    /// Treats the last `n` bytes of the patch as a footer, such as a CRC or
+    /// hash some generators append, instead of part of the extra stream.
+    /// Parsing fails if the footer does not fit after the diff stream. See
+    /// `BsdiffReader::footer`.
+    pub fn footer_len(mut self, n: usize) -> Self {
+        self.footer_len = n;
+        return self;
+    }
+
+    /// How many source bytes `BsdiffReader::apply_from_source` reads at a
+    /// time, 64 KiB by default. Source memory stays within one chunk no
+    /// matter how large a control entry is.
+    pub fn source_chunk_size(mut self, n: usize) -> Self {
+        self.source_chunk_size = Some(n.max(1));
+        return self;
+    }
+
+    /// Rejects brotli streams whose header declares a window larger than
+    /// `bytes` with `BsdumpError::BrotliWindowTooLarge`, before decoding
+    /// them. The decoder allocates the whole window, so this bounds the
+    /// memory needed on constrained appliers.
+    pub fn max_brotli_window(mut self, bytes: u64) -> Self {
+        self.max_brotli_window = Some(bytes);
+        return self;
+    }
+
+    /// Skips the first `n` bytes of the input, such as the header of a
+    /// container that wraps the bsdiff payload, so wrapped payloads can be
+    /// parsed without slicing them first. All offsets the reader reports
+    /// are relative to the bsdiff magic; `BsdiffReader::base_offset` is `n`.
+    pub fn skip_prefix(mut self, n: usize) -> Self {
+        self.skip_prefix = n;
+        return self;
+    }
+
+    /// Reads patches whose magic is `len` bytes long instead of 8, as some
+    /// generators write to record a version and flags. The first 8 bytes
+    /// must still be a standard magic, which gives the format and, at bytes
+    /// 5 to 7, the compressors. The remaining `len - 8` bytes are not
+    /// interpreted, see `BsdiffReader::magic_extension`. The size fields
+    /// and the streams follow the whole magic. Lengths below 8 are treated
+    /// as 8.
+    pub fn magic_len(mut self, len: usize) -> Self {
+        self.extra_magic_len = len.saturating_sub(8);
+        return self;
+    }
+
+    /// Registers a callback that receives a `DecompressTrace` whenever a
+    /// stream finishes or fails to decompress, to see which codec broke on
+    /// a patch and how far it got. Streams are decompressed again each time
+    /// they are needed, so the same stream may be reported more than once.
+    pub fn trace_decompress<F: FnMut(&DecompressTrace) + Send + 'static>(
+        mut self,
+        callback: F,
+    ) -> Self {
+        self.trace = Some(Mutex::new(Box::new(callback)));
+        return self;
+    }
+
+    /// Makes applying fail with `BsdumpError::OutputTooLarge` when the new
+    /// file would be larger than `bytes`, before anything is allocated or
+    /// decompressed. Without it a bogus header size still fails once the
+    /// streams turn out too short for it, but only after decompressing
+    /// them.
+    pub fn max_output(mut self, bytes: u64) -> Self {
+        self.max_output = Some(bytes);
+        return self;
+    }
+
+    /// Takes the zstd dictionary and, with the `crc32fast` feature, the
+    /// expected CRC32s from a sidecar. The CRC32s are checked by
+    /// `BsdiffReader::apply_verified`.
+    pub fn sidecar(mut self, sidecar: Sidecar) -> Self {
+        if let Some(dictionary) = sidecar.zstd_dictionary {
+            self.zstd_dictionary = Some(dictionary);
+        }
+        #[cfg(feature = "crc32fast")]
+        {
+            self.source_crc32 = sidecar.source_crc32;
+            self.target_crc32 = sidecar.target_crc32;
+        }
+        return self;
+    }
+
+    /// Same as `sidecar`, reading it from `path` with `Sidecar::load`.
+    pub fn load_sidecar<P: AsRef<std::path::Path>>(self, path: P) -> Result<Self, BsdumpError> {
+        return Ok(self.sidecar(Sidecar::load(path)?));
+    }
+
+    /// Parses `data` with these options, same as
+    /// `BsdiffReader::with_options`.
+    pub fn read(self, data: &[u8]) -> Result<BsdiffReader<'_>, BsdumpError> {
+        return BsdiffReader::with_options(data, self);
+    }
+
+    pub(crate) fn report_trace(&self, trace: &DecompressTrace) {
+        if let Some(callback) = &self.trace {
+            (callback.lock().unwrap())(trace);
+        }
+    }
+
+    pub(crate) fn report_progress(&self, done: u64, total: u64) {
+        if let Some(callback) = &self.progress {
+            (callback.lock().unwrap())(done, total);
+        }
+    }
+}
@@ -0,0 +1,201 @@
+use crate::bsdiff_format::{BsdiffFormat, BsdiffReader, ControlEntry};
+use crate::error::BsdumpError;
+
+// Protobuf encoding of the header and control entries, following the
+// schema in `proto/bsdump.proto`. The wire format is written by hand, like
+// the JSON dump, so no protobuf runtime is needed.
+const WIRE_VARINT: u64 = 0;
+const WIRE_FIXED64: u64 = 1;
+const WIRE_LEN: u64 = 2;
+const WIRE_FIXED32: u64 = 5;
+
+/// A decoded `bsdump.Patch` message.
+#[derive(Debug, Clone)]
+pub struct PatchMessage {
+    pub header: BsdiffFormat,
+    pub control_entries: Vec<ControlEntry>,
+    pub truncated: bool,
+}
+
+fn put_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+// Like protobuf, fields holding the default value of zero are left out.
+fn put_varint_field(out: &mut Vec<u8>, field: u64, value: u64) {
+    if value != 0 {
+        put_varint(out, field << 3 | WIRE_VARINT);
+        put_varint(out, value);
+    }
+}
+
+fn put_message_field(out: &mut Vec<u8>, field: u64, message: &[u8]) {
+    put_varint(out, field << 3 | WIRE_LEN);
+    put_varint(out, message.len() as u64);
+    out.extend_from_slice(message);
+}
+
+fn zigzag(value: i64) -> u64 {
+    return ((value << 1) ^ (value >> 63)) as u64;
+}
+
+fn unzigzag(value: u64) -> i64 {
+    return (value >> 1) as i64 ^ -((value & 1) as i64);
+}
+
+/// Encodes the header and control entries as a `bsdump.Patch` message.
+/// Entries stop at `BsdiffReaderOptions::max_control_entries` if set, and
+/// `truncated` tells whether any were left out.
+pub fn encode(reader: &BsdiffReader) -> Vec<u8> {
+    let header = &reader.header;
+    let mut header_message = Vec::new();
+    put_varint_field(&mut header_message, 1, header.magic);
+    put_varint_field(&mut header_message, 2, header.compressed_ctrl_size);
+    put_varint_field(&mut header_message, 3, header.compressed_diff_size);
+    put_varint_field(&mut header_message, 4, header.new_file_size);
+    let mut out = Vec::new();
+    put_message_field(&mut out, 1, &header_message);
+
+    let mut entries = reader.control_entries();
+    let mut entry_message = Vec::new();
+    for entry in entries.by_ref() {
+        entry_message.clear();
+        put_varint_field(&mut entry_message, 1, entry.diff_size);
+        put_varint_field(&mut entry_message, 2, entry.extra_size);
+        put_varint_field(&mut entry_message, 3, zigzag(entry.offset_increment));
+        put_message_field(&mut out, 2, &entry_message);
+    }
+    put_varint_field(&mut out, 3, entries.is_truncated() as u64);
+    return out;
+}
+
+// Reads fields of a message one at a time.
+struct FieldReader<'a> {
+    data: &'a [u8],
+}
+
+// The value of a field, for the wire types this schema uses.
+enum Value<'a> {
+    Varint(u64),
+    Message(&'a [u8]),
+    Other,
+}
+
+fn malformed(what: &str) -> BsdumpError {
+    return BsdumpError::InvalidProto(what.to_string());
+}
+
+impl<'a> FieldReader<'a> {
+    fn varint(&mut self) -> Result<u64, BsdumpError> {
+        let mut value = 0_u64;
+        for (i, byte) in self.data.iter().enumerate().take(10) {
+            value |= ((byte & 0x7f) as u64) << (7 * i);
+            if byte & 0x80 == 0 {
+                self.data = &self.data[i + 1..];
+                return Ok(value);
+            }
+        }
+        return Err(malformed("truncated or overlong varint"));
+    }
+
+    fn bytes(&mut self, len: u64) -> Result<&'a [u8], BsdumpError> {
+        if len > self.data.len() as u64 {
+            return Err(malformed("field runs past the end"));
+        }
+        let (bytes, rest) = self.data.split_at(len as usize);
+        self.data = rest;
+        return Ok(bytes);
+    }
+
+    // Returns the next field number and its value, skipping over the
+    // payload of wire types the schema doesn't use.
+    fn next_field(&mut self) -> Result<Option<(u64, Value<'a>)>, BsdumpError> {
+        if self.data.is_empty() {
+            return Ok(None);
+        }
+        let key = self.varint()?;
+        let value = match key & 7 {
+            WIRE_VARINT => Value::Varint(self.varint()?),
+            WIRE_LEN => {
+                let len = self.varint()?;
+                Value::Message(self.bytes(len)?)
+            }
+            WIRE_FIXED64 => {
+                self.bytes(8)?;
+                Value::Other
+            }
+            WIRE_FIXED32 => {
+                self.bytes(4)?;
+                Value::Other
+            }
+            wire_type => return Err(malformed(&format!("wire type {}", wire_type))),
+        };
+        return Ok(Some((key >> 3, value)));
+    }
+}
+
+fn decode_header(data: &[u8]) -> Result<BsdiffFormat, BsdumpError> {
+    let mut header = BsdiffFormat {
+        magic: 0,
+        compressed_ctrl_size: 0,
+        compressed_diff_size: 0,
+        new_file_size: 0,
+    };
+    let mut fields = FieldReader { data };
+    while let Some((field, value)) = fields.next_field()? {
+        if let Value::Varint(value) = value {
+            match field {
+                1 => header.magic = value,
+                2 => header.compressed_ctrl_size = value,
+                3 => header.compressed_diff_size = value,
+                4 => header.new_file_size = value,
+                _ => {}
+            }
+        }
+    }
+    return Ok(header);
+}
+
+fn decode_entry(data: &[u8]) -> Result<ControlEntry, BsdumpError> {
+    let mut entry = ControlEntry {
+        diff_size: 0,
+        extra_size: 0,
+        offset_increment: 0,
+    };
+    let mut fields = FieldReader { data };
+    while let Some((field, value)) = fields.next_field()? {
+        if let Value::Varint(value) = value {
+            match field {
+                1 => entry.diff_size = value,
+                2 => entry.extra_size = value,
+                3 => entry.offset_increment = unzigzag(value),
+                _ => {}
+            }
+        }
+    }
+    return Ok(entry);
+}
+
+/// Decodes a `bsdump.Patch` message written by `encode`. Unknown fields
+/// are skipped, as protobuf requires.
+pub fn decode(data: &[u8]) -> Result<PatchMessage, BsdumpError> {
+    let mut message = PatchMessage {
+        header: decode_header(&[])?,
+        control_entries: Vec::new(),
+        truncated: false,
+    };
+    let mut fields = FieldReader { data };
+    while let Some((field, value)) = fields.next_field()? {
+        match (field, value) {
+            (1, Value::Message(data)) => message.header = decode_header(data)?,
+            (2, Value::Message(data)) => message.control_entries.push(decode_entry(data)?),
+            (3, Value::Varint(value)) => message.truncated = value != 0,
+            _ => {}
+        }
+    }
+    return Ok(message);
+}
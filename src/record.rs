@@ -0,0 +1,98 @@
+use std::convert::TryInto;
+
+use crate::bsdiff_format::{
+    from_compressor_type, to_compressor_type, BsdiffReader, CompressorType,
+};
+use crate::error::BsdumpError;
+
+/// Length of an encoded `SummaryRecord`.
+pub const SUMMARY_RECORD_LEN: usize = 36;
+
+// Names of the formats, indexed by their byte in the record.
+const FORMATS: [&str; 3] = ["BSDIFF40", "BSDF2", "BDF3"];
+
+/// A fixed size digest of a patch for bulk indexing, see
+/// `BsdiffReader::summary_record`. Encoded as little endian:
+///
+/// | bytes  | field                                            |
+/// |--------|--------------------------------------------------|
+/// | 0      | format: 0 BSDIFF40, 1 BSDF2, 2 BDF3              |
+/// | 1..4   | ctrl, diff and extra compressor, as in the magic |
+/// | 4..12  | new file size                                    |
+/// | 12..20 | number of control entries                        |
+/// | 20..28 | bytes written from the diff stream               |
+/// | 28..36 | bytes written from the extra stream              |
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SummaryRecord {
+    // "BSDIFF40", "BSDF2" or "BDF3", see `BsdiffFormat::format_name`.
+    pub format: &'static str,
+    pub compressors: [CompressorType; 3],
+    pub new_file_size: u64,
+    pub num_control_entries: u64,
+    pub diff_bytes: u64,
+    pub extra_bytes: u64,
+}
+
+impl SummaryRecord {
+    pub fn new(reader: &BsdiffReader) -> SummaryRecord {
+        let (diff_bytes, extra_bytes) =
+            reader
+                .all_control_entries()
+                .fold((0_u64, 0_u64), |(diff, extra), entry| {
+                    (
+                        diff.saturating_add(entry.diff_size),
+                        extra.saturating_add(entry.extra_size),
+                    )
+                });
+        return SummaryRecord {
+            format: reader.header.format_name(),
+            compressors: reader.header.validated_compressors(),
+            new_file_size: reader.header.new_file_size,
+            num_control_entries: reader.num_control_entries() as u64,
+            diff_bytes,
+            extra_bytes,
+        };
+    }
+
+    pub fn encode(&self) -> [u8; SUMMARY_RECORD_LEN] {
+        let mut record = [0_u8; SUMMARY_RECORD_LEN];
+        record[0] = FORMATS.iter().position(|f| *f == self.format).unwrap() as u8;
+        for (i, compressor_type) in self.compressors.iter().enumerate() {
+            record[1 + i] = from_compressor_type(*compressor_type);
+        }
+        let fields = [
+            self.new_file_size,
+            self.num_control_entries,
+            self.diff_bytes,
+            self.extra_bytes,
+        ];
+        for (i, value) in fields.iter().enumerate() {
+            record[4 + 8 * i..][..8].copy_from_slice(&value.to_le_bytes());
+        }
+        return record;
+    }
+
+    /// Decodes a record written by `encode`. Fails on an unknown format
+    /// or compressor byte.
+    pub fn decode(record: &[u8; SUMMARY_RECORD_LEN]) -> Result<SummaryRecord, BsdumpError> {
+        let format = *FORMATS.get(record[0] as usize).ok_or_else(|| {
+            BsdumpError::CorruptPatch(format!("unknown format {} in summary record", record[0]))
+        })?;
+        let mut compressors = [CompressorType::Bz2; 3];
+        for (i, compressor_type) in compressors.iter_mut().enumerate() {
+            let value = record[1 + i];
+            *compressor_type = to_compressor_type(value).ok_or_else(|| {
+                BsdumpError::CorruptPatch(format!("unknown compressor {} in summary record", value))
+            })?;
+        }
+        let field = |i: usize| u64::from_le_bytes(record[4 + 8 * i..][..8].try_into().unwrap());
+        return Ok(SummaryRecord {
+            format,
+            compressors,
+            new_file_size: field(0),
+            num_control_entries: field(1),
+            diff_bytes: field(2),
+            extra_bytes: field(3),
+        });
+    }
+}
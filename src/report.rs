@@ -0,0 +1,318 @@
+use std::io::{self, Write};
+
+use crate::bsdiff_format::{BsdiffReader, CompressorType, EntrySizeStats};
+
+/// Everything known about a patch without its source, gathered by
+/// `BsdiffReader::report` so it can be stored in one go.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PatchReport {
+    pub format: &'static str,
+    pub magic: u64,
+    // Compressors of the ctrl, diff and extra streams, in that order.
+    pub compressors: [CompressorType; 3],
+    pub compressed_ctrl_size: u64,
+    pub compressed_diff_size: u64,
+    pub compressed_extra_size: u64,
+    // The whole patch, headers and any footer included.
+    pub patch_size: u64,
+    pub new_file_size: u64,
+    pub num_control_entries: usize,
+    // Bytes of the new file written from the diff and extra streams.
+    pub diff_bytes: u64,
+    pub extra_bytes: u64,
+    // Entries that only move the source offset, see
+    // `ControlEntry::is_pure_seek`.
+    pub pure_seeks: usize,
+    pub operations: OperationCounts,
+    // Entries whose diff bytes are all zero, see `BsdiffReader::operations`.
+    // `None` if the diff stream could not be decompressed.
+    pub pure_copies: Option<usize>,
+    // See `BsdiffReader::new_data_fraction`.
+    pub new_data_fraction: f64,
+    pub has_backward_seeks: bool,
+    pub entry_sizes: EntrySizeStats,
+    // Statistics of the decompressed diff stream, `None` if it could not
+    // be decompressed.
+    pub diff_stream: Option<DiffStreamStats>,
+    // Decompressed length of the extra stream, `None` if it could not be
+    // decompressed.
+    pub extra_stream_len: Option<u64>,
+    // Every problem `self_check` found, as messages.
+    pub problems: Vec<String>,
+}
+
+/// How many control entries write from which streams.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct OperationCounts {
+    pub diff_only: usize,
+    pub extra_only: usize,
+    pub diff_and_extra: usize,
+    // Entries that write nothing, including pure seeks.
+    pub no_output: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DiffStreamStats {
+    pub len: u64,
+    pub zero_bytes: u64,
+    // Shannon entropy in bits per byte, from 0 to 8.
+    pub entropy: f64,
+}
+
+impl DiffStreamStats {
+    pub fn new(stream: &[u8]) -> DiffStreamStats {
+        let mut counts = [0_u64; 256];
+        for byte in stream {
+            counts[*byte as usize] += 1;
+        }
+        let len = stream.len() as f64;
+        let entropy = counts
+            .iter()
+            .filter(|count| **count > 0)
+            .map(|count| {
+                let p = *count as f64 / len;
+                return -p * p.log2();
+            })
+            .sum::<f64>();
+        return DiffStreamStats {
+            len: stream.len() as u64,
+            zero_bytes: counts[0],
+            // Avoid a negative zero for streams of a single byte value.
+            entropy: entropy.max(0.0),
+        };
+    }
+
+    /// Share of zero bytes, from 0 to 100. Zero for an empty stream.
+    pub fn zero_percentage(&self) -> f64 {
+        if self.len == 0 {
+            return 0.0;
+        }
+        return self.zero_bytes as f64 / self.len as f64 * 100.0;
+    }
+}
+
+impl PatchReport {
+    pub fn new(reader: &BsdiffReader) -> PatchReport {
+        let header = &reader.header;
+        let (diff_bytes, extra_bytes) =
+            reader
+                .all_control_entries()
+                .fold((0_u64, 0_u64), |(diff, extra), entry| {
+                    (
+                        diff.saturating_add(entry.diff_size),
+                        extra.saturating_add(entry.extra_size),
+                    )
+                });
+        let pure_seeks = reader
+            .all_control_entries()
+            .filter(|entry| entry.is_pure_seek())
+            .count();
+        let mut operations = OperationCounts::default();
+        for entry in reader.all_control_entries() {
+            let count = match (entry.diff_size > 0, entry.extra_size > 0) {
+                (true, false) => &mut operations.diff_only,
+                (false, true) => &mut operations.extra_only,
+                (true, true) => &mut operations.diff_and_extra,
+                (false, false) => &mut operations.no_output,
+            };
+            *count += 1;
+        }
+        let problems = match reader.self_check() {
+            Ok(()) => Vec::new(),
+            Err(problems) => problems.iter().map(|e| e.to_string()).collect(),
+        };
+        return PatchReport {
+            format: header.format_name(),
+            magic: header.magic,
+            compressors: header.validated_compressors(),
+            compressed_ctrl_size: header.compressed_ctrl_size,
+            compressed_diff_size: header.compressed_diff_size,
+            compressed_extra_size: reader.compressed_extra_stream().len() as u64,
+            patch_size: reader.patch_size(),
+            new_file_size: header.new_file_size,
+            num_control_entries: reader.num_control_entries(),
+            diff_bytes,
+            extra_bytes,
+            pure_seeks,
+            operations,
+            pure_copies: reader
+                .operations()
+                .ok()
+                .map(|ops| ops.iter().filter(|op| op.is_pure_copy).count()),
+            new_data_fraction: reader.new_data_fraction(),
+            has_backward_seeks: reader.has_backward_seeks(),
+            entry_sizes: reader.entry_size_stats(),
+            diff_stream: reader
+                .decompressed_diff_stream()
+                .ok()
+                .map(|stream| DiffStreamStats::new(&stream)),
+            extra_stream_len: reader
+                .decompressed_extra_stream()
+                .ok()
+                .map(|stream| stream.len() as u64),
+            problems,
+        };
+    }
+
+    /// Writes the report as a single JSON object.
+    pub fn write_json<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        let compressors: Vec<String> = self
+            .compressors
+            .iter()
+            .map(|c| format!("\"{:?}\"", c))
+            .collect();
+        writeln!(w, "{{")?;
+        writeln!(w, "  \"format\": \"{}\",", self.format)?;
+        // The magic is not valid UTF-8 in general, so it goes out as hex.
+        writeln!(w, "  \"magic\": \"{:016x}\",", self.magic)?;
+        writeln!(w, "  \"compressors\": [{}],", compressors.join(", "))?;
+        for (name, value) in [
+            ("compressed_ctrl_size", self.compressed_ctrl_size),
+            ("compressed_diff_size", self.compressed_diff_size),
+            ("compressed_extra_size", self.compressed_extra_size),
+            ("new_file_size", self.new_file_size),
+            ("num_control_entries", self.num_control_entries as u64),
+            ("diff_bytes", self.diff_bytes),
+            ("extra_bytes", self.extra_bytes),
+            ("pure_seeks", self.pure_seeks as u64),
+        ] {
+            writeln!(w, "  \"{}\": {},", name, value)?;
+        }
+        writeln!(w, "  \"has_backward_seeks\": {},", self.has_backward_seeks)?;
+        let sizes = &self.entry_sizes;
+        writeln!(
+            w,
+            "  \"entry_sizes\": {{\"avg_diff\": {}, \"max_diff\": {}, \"avg_extra\": {}, \"max_extra\": {}}},",
+            sizes.avg_diff, sizes.max_diff, sizes.avg_extra, sizes.max_extra
+        )?;
+        match &self.diff_stream {
+            Some(stats) => writeln!(
+                w,
+                "  \"diff_stream\": {{\"len\": {}, \"zero_bytes\": {}, \"zero_percentage\": {}, \"entropy\": {}}},",
+                stats.len,
+                stats.zero_bytes,
+                stats.zero_percentage(),
+                stats.entropy
+            )?,
+            None => writeln!(w, "  \"diff_stream\": null,")?,
+        }
+        writeln!(w, "  \"pure_copies\": {},", json_count(self.pure_copies))?;
+        write!(w, "  \"problems\": [")?;
+        for (i, problem) in self.problems.iter().enumerate() {
+            let separator = if i == 0 { "" } else { "," };
+            write!(w, "{}\n    {}", separator, json_string(problem))?;
+        }
+        if !self.problems.is_empty() {
+            writeln!(w)?;
+            write!(w, "  ")?;
+        }
+        writeln!(w, "]")?;
+        writeln!(w, "}}")?;
+        return Ok(());
+    }
+}
+
+impl PatchReport {
+    /// Share of the new file's size the patch saves, e.g. 0.9 for a patch a
+    /// tenth the size of the new file. Negative if the patch is larger;
+    /// `None` for an empty new file.
+    pub fn space_savings(&self) -> Option<f64> {
+        return ratio(self.patch_size, self.new_file_size).map(|r| 1.0 - r);
+    }
+
+    /// Compressed size over decompressed size of the ctrl, diff and extra
+    /// streams. `None` for a stream that is empty or failed to decompress.
+    pub fn stream_ratios(&self) -> [Option<f64>; 3] {
+        let ctrl_len = (self.num_control_entries * 24) as u64;
+        return [
+            ratio(self.compressed_ctrl_size, ctrl_len),
+            self.diff_stream
+                .as_ref()
+                .and_then(|stats| ratio(self.compressed_diff_size, stats.len)),
+            self.extra_stream_len
+                .and_then(|len| ratio(self.compressed_extra_size, len)),
+        ];
+    }
+
+    /// Writes only the aggregate numbers as one JSON object, for tracking
+    /// delta quality over time. Numbers that can't be computed are null.
+    pub fn write_stats_json<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        let ops = &self.operations;
+        let [ctrl_ratio, diff_ratio, extra_ratio] = self.stream_ratios();
+        writeln!(w, "{{")?;
+        writeln!(
+            w,
+            "  \"num_control_entries\": {},",
+            self.num_control_entries
+        )?;
+        writeln!(
+            w,
+            "  \"operations\": {{\"diff_only\": {}, \"extra_only\": {}, \"diff_and_extra\": {}, \"no_output\": {}, \"pure_seeks\": {}}},",
+            ops.diff_only, ops.extra_only, ops.diff_and_extra, ops.no_output, self.pure_seeks
+        )?;
+        writeln!(w, "  \"pure_copies\": {},", json_count(self.pure_copies))?;
+        writeln!(w, "  \"new_data_fraction\": {},", self.new_data_fraction)?;
+        writeln!(
+            w,
+            "  \"space_savings\": {},",
+            json_number(self.space_savings())
+        )?;
+        let diff_stream = self.diff_stream.as_ref();
+        writeln!(
+            w,
+            "  \"zero_percentage\": {},",
+            json_number(diff_stream.map(|stats| stats.zero_percentage()))
+        )?;
+        writeln!(
+            w,
+            "  \"entropy\": {},",
+            json_number(diff_stream.map(|stats| stats.entropy))
+        )?;
+        writeln!(
+            w,
+            "  \"stream_ratios\": {{\"ctrl\": {}, \"diff\": {}, \"extra\": {}}}",
+            json_number(ctrl_ratio),
+            json_number(diff_ratio),
+            json_number(extra_ratio)
+        )?;
+        writeln!(w, "}}")?;
+        return Ok(());
+    }
+}
+
+fn ratio(numerator: u64, denominator: u64) -> Option<f64> {
+    if denominator == 0 {
+        return None;
+    }
+    return Some(numerator as f64 / denominator as f64);
+}
+
+fn json_number(value: Option<f64>) -> String {
+    return match value {
+        Some(value) => value.to_string(),
+        None => "null".to_string(),
+    };
+}
+
+fn json_count(value: Option<usize>) -> String {
+    return match value {
+        Some(value) => value.to_string(),
+        None => "null".to_string(),
+    };
+}
+
+// Quotes `s` as a JSON string. Error messages can quote patch bytes, so
+// control characters are escaped too.
+fn json_string(s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    return out;
+}
@@ -0,0 +1,84 @@
+use std::fs;
+use std::path::Path;
+
+use crate::error::BsdumpError;
+
+/// Decode parameters shipped next to a patch rather than inside it. A
+/// sidecar is a text file of `key = value` lines; blank lines and lines
+/// starting with `#` are ignored:
+///
+/// ```text
+/// zstd_dictionary = dict.bin
+/// source_crc32 = 0x1c291ca3
+/// target_crc32 = 0x8a9136aa
+/// ```
+///
+/// `zstd_dictionary` is a path, relative to the sidecar's directory. The
+/// expected source and target hashes are CRC32s, no other hash is
+/// supported. They are checked by `BsdiffReader::apply_verified`, so they
+/// need the `crc32fast` feature; without it a sidecar that sets them fails
+/// to parse rather than having them silently ignored. Pass the result to
+/// `BsdiffReaderOptions::sidecar`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Sidecar {
+    pub zstd_dictionary: Option<Vec<u8>>,
+    #[cfg(feature = "crc32fast")]
+    pub source_crc32: Option<u32>,
+    #[cfg(feature = "crc32fast")]
+    pub target_crc32: Option<u32>,
+}
+
+impl Sidecar {
+    /// Reads the sidecar at `path`, and the dictionary it names.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Sidecar, BsdumpError> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path)?;
+        return Sidecar::parse(&text, path.parent().unwrap_or_else(|| Path::new("")));
+    }
+
+    /// Parses sidecar `text`, resolving the dictionary path against `dir`.
+    pub fn parse(text: &str, dir: &Path) -> Result<Sidecar, BsdumpError> {
+        let mut sidecar = Sidecar::default();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let invalid = |what: &str| BsdumpError::InvalidSidecar {
+                line: number + 1,
+                message: what.to_string(),
+            };
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| invalid("expected key = value"))?;
+            let value = value.trim();
+            match key.trim() {
+                "zstd_dictionary" => sidecar.zstd_dictionary = Some(fs::read(dir.join(value))?),
+                #[cfg(feature = "crc32fast")]
+                "source_crc32" => {
+                    sidecar.source_crc32 = Some(parse_crc32(value).ok_or_else(|| invalid(value))?)
+                }
+                #[cfg(feature = "crc32fast")]
+                "target_crc32" => {
+                    sidecar.target_crc32 = Some(parse_crc32(value).ok_or_else(|| invalid(value))?)
+                }
+                #[cfg(not(feature = "crc32fast"))]
+                key @ ("source_crc32" | "target_crc32") => {
+                    return Err(invalid(&format!(
+                        "{} can't be checked without the crc32fast feature",
+                        key
+                    )))
+                }
+                key => return Err(invalid(&format!("unknown key {}", key))),
+            }
+        }
+        return Ok(sidecar);
+    }
+}
+
+// Hex, with or without a 0x prefix.
+#[cfg(feature = "crc32fast")]
+fn parse_crc32(value: &str) -> Option<u32> {
+    let digits = value.strip_prefix("0x").unwrap_or(value);
+    return u32::from_str_radix(digits, 16).ok();
+}
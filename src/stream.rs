@@ -0,0 +1,200 @@
+use std::io::{self, Cursor, Read, Take};
+
+use binread::BinRead;
+
+use crate::bsdiff_format::{
+    BsdiffFormat, CompressorType, ControlEntry, BSDIFF3_MASK_SIZE_LEN, CONTROL_ENTRY_SIZE,
+    HEADER_SIZE,
+};
+use crate::compression::not_compiled;
+use crate::error::BsdumpError;
+use crate::options::BsdiffReaderOptions;
+
+// A decoder that can hand back the reader it decodes from.
+trait SourceDecoder<R>: Read {
+    fn into_source(self: Box<Self>) -> Take<R>;
+}
+
+#[cfg(feature = "bz2")]
+impl<R: Read> SourceDecoder<R> for bzip2::read::BzDecoder<Take<R>> {
+    fn into_source(self: Box<Self>) -> Take<R> {
+        return self.into_inner();
+    }
+}
+
+#[cfg(feature = "brotli")]
+impl<R: Read> SourceDecoder<R> for brotli::Decompressor<Take<R>> {
+    fn into_source(self: Box<Self>) -> Take<R> {
+        return self.into_inner();
+    }
+}
+
+#[cfg(feature = "zstd")]
+impl<R: Read> SourceDecoder<R> for zstd::Decoder<'static, io::BufReader<Take<R>>> {
+    // Bytes left in the buffer belong to the ctrl stream, so dropping them
+    // is fine.
+    fn into_source(self: Box<Self>) -> Take<R> {
+        return self.finish().into_inner();
+    }
+}
+
+#[cfg(feature = "gzip")]
+impl<R: Read> SourceDecoder<R> for flate2::read::GzDecoder<Take<R>> {
+    fn into_source(self: Box<Self>) -> Take<R> {
+        return self.into_inner();
+    }
+}
+
+#[cfg_attr(
+    not(any(
+        feature = "bz2",
+        feature = "brotli",
+        feature = "zstd",
+        feature = "gzip"
+    )),
+    allow(unused_variables)
+)]
+fn source_decoder<'r, R: Read + 'r>(
+    data: Take<R>,
+    compressor_type: CompressorType,
+    options: &BsdiffReaderOptions,
+) -> Result<Box<dyn SourceDecoder<R> + 'r>, BsdumpError> {
+    compressor_type.require_compiled()?;
+    return match compressor_type {
+        #[cfg(feature = "bz2")]
+        CompressorType::Bz2 => Ok(Box::new(bzip2::read::BzDecoder::new(data))),
+        #[cfg(feature = "brotli")]
+        CompressorType::Brotli => Ok(Box::new(brotli::Decompressor::new(
+            data, 4096, /* buffer size */
+        ))),
+        #[cfg(feature = "zstd")]
+        CompressorType::Zstd => {
+            let dictionary = options.zstd_dictionary.as_deref().unwrap_or(&[]);
+            Ok(Box::new(zstd::Decoder::with_dictionary(
+                io::BufReader::new(data),
+                dictionary,
+            )?))
+        }
+        #[cfg(feature = "gzip")]
+        CompressorType::Gzip => Ok(Box::new(flate2::read::GzDecoder::new(data))),
+        #[allow(unreachable_patterns)]
+        _ => Err(not_compiled(compressor_type).into()),
+    };
+}
+
+// Reads until `buf` is full or the input ends, returning how much was read.
+fn read_up_to<R: Read>(reader: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    return Ok(filled);
+}
+
+/// Control entries decoded straight from a `Read`, see
+/// `BsdiffReader::stream_from`. Only the header and the decoder state are
+/// held in memory. Yields an error and stops if the ctrl stream fails to
+/// decompress or ends partway through an entry.
+pub struct ControlEntryStream<'r, R: Read + 'r> {
+    pub header: BsdiffFormat,
+    // BDF3 only, zero for other formats.
+    pub compressed_mask_size: u64,
+    decoder: Box<dyn SourceDecoder<R> + 'r>,
+    index: usize,
+    done: bool,
+    // From `BsdiffReaderOptions::max_control_entries`.
+    limit: Option<usize>,
+    // From `BsdiffReaderOptions::allow_partial_ctrl_entry`.
+    allow_partial_ctrl_entry: bool,
+}
+
+impl<'r, R: Read + 'r> ControlEntryStream<'r, R> {
+    pub(crate) fn new(
+        mut reader: R,
+        options: &BsdiffReaderOptions,
+    ) -> Result<ControlEntryStream<'r, R>, BsdumpError> {
+        io::copy(
+            &mut reader.by_ref().take(options.skip_prefix as u64),
+            &mut io::sink(),
+        )?;
+        // The magic, then any bytes `magic_len` added to it, then the
+        // size fields.
+        let mut buf = vec![0_u8; HEADER_SIZE as usize + options.extra_magic_len];
+        let len = read_up_to(&mut reader, &mut buf)?;
+        buf.truncate(len);
+        if len > 8 {
+            buf.drain(8..(8 + options.extra_magic_len).min(len));
+        }
+        let header = BsdiffFormat::read_validated(&mut Cursor::new(&buf[..]))?;
+        let mut compressed_mask_size = 0;
+        if header.is_bsdiff3_format() {
+            let mut buf = [0_u8; BSDIFF3_MASK_SIZE_LEN as usize];
+            reader.read_exact(&mut buf)?;
+            compressed_mask_size = u64::from_le_bytes(buf);
+        }
+        let ctrl = reader.take(header.compressed_ctrl_size);
+        return Ok(ControlEntryStream {
+            decoder: source_decoder(ctrl, header.get_ctrl_compressor(), options)?,
+            header,
+            compressed_mask_size,
+            index: 0,
+            done: false,
+            limit: options.max_control_entries,
+            allow_partial_ctrl_entry: options.allow_partial_ctrl_entry,
+        });
+    }
+
+    /// Skips whatever is left of the ctrl stream and returns the reader,
+    /// positioned at the start of the diff stream.
+    pub fn into_inner(self) -> Result<R, BsdumpError> {
+        let mut ctrl = self.decoder.into_source();
+        io::copy(&mut ctrl, &mut io::sink())?;
+        if ctrl.limit() > 0 {
+            return Err(BsdumpError::CorruptPatch(format!(
+                "input ends {} bytes before the end of the ctrl stream",
+                ctrl.limit()
+            )));
+        }
+        return Ok(ctrl.into_inner());
+    }
+}
+
+impl<'r, R: Read + 'r> Iterator for ControlEntryStream<'r, R> {
+    type Item = Result<ControlEntry, BsdumpError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done || self.limit == Some(self.index) {
+            return None;
+        }
+        let mut buf = [0_u8; CONTROL_ENTRY_SIZE];
+        let result = match read_up_to(&mut self.decoder, &mut buf) {
+            Ok(0) => {
+                self.done = true;
+                return None;
+            }
+            Ok(CONTROL_ENTRY_SIZE) => ControlEntry::read_args(
+                &mut Cursor::new(&buf[..]),
+                (self.header.is_legacy_bsdiff_format(),),
+            )
+            .map_err(BsdumpError::from),
+            Ok(_) if self.allow_partial_ctrl_entry => {
+                self.done = true;
+                return None;
+            }
+            Ok(available) => Err(BsdumpError::TruncatedControlEntry {
+                index: self.index,
+                offset: (self.index * CONTROL_ENTRY_SIZE) as u64,
+                available,
+            }),
+            Err(e) => Err(e.into()),
+        };
+        self.done = result.is_err();
+        self.index += 1;
+        return Some(result);
+    }
+}
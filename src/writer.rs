@@ -0,0 +1,235 @@
+use std::io::{self, Cursor, Read, Seek, SeekFrom, Write};
+
+use crate::bsdiff_format::{encode_bsdiff_int, from_compressor_type, CompressorType, HEADER_SIZE};
+use crate::compression::{compress, Encoder};
+
+// The 24 byte control entry for an entry with the given streams.
+fn encode_entry(diff_len: usize, extra_len: usize, offset_increment: i64) -> [u8; 24] {
+    let mut entry = [0_u8; 24];
+    entry[..8].copy_from_slice(&(diff_len as u64).to_le_bytes());
+    entry[8..16].copy_from_slice(&(extra_len as u64).to_le_bytes());
+    entry[16..].copy_from_slice(&encode_bsdiff_int(offset_increment).to_le_bytes());
+    return entry;
+}
+
+fn bsdf2_magic(compressors: [CompressorType; 3]) -> [u8; 8] {
+    let mut magic = *b"BSDF2\x00\x00\x00";
+    for (i, compressor_type) in compressors.iter().enumerate() {
+        magic[5 + i] = from_compressor_type(*compressor_type);
+    }
+    return magic;
+}
+
+// The sizes are written as plain little endian integers. Since they are
+// never negative, that is also their BSDIFF40 sign bit encoding.
+fn write_header<W: Write>(
+    out: &mut W,
+    magic: [u8; 8],
+    ctrl_len: u64,
+    diff_len: u64,
+    new_file_size: u64,
+) -> io::Result<()> {
+    out.write_all(&magic)?;
+    out.write_all(&ctrl_len.to_le_bytes())?;
+    out.write_all(&diff_len.to_le_bytes())?;
+    out.write_all(&new_file_size.to_le_bytes())?;
+    return Ok(());
+}
+
+/// Builds a BSDF2 patch, or a BSDIFF40 one with `bsdiff40`, one control
+/// entry at a time. The streams are kept uncompressed in memory and
+/// compressed when the patch is written.
+pub struct BsdiffWriter {
+    // Compressors for the ctrl, diff and extra streams, in that order.
+    compressors: [CompressorType; 3],
+    // Write a BSDIFF40 header instead of a BSDF2 one, see `bsdiff40`.
+    legacy: bool,
+    ctrl_stream: Vec<u8>,
+    diff_stream: Vec<u8>,
+    extra_stream: Vec<u8>,
+    new_file_size: u64,
+}
+
+impl BsdiffWriter {
+    pub fn new(compressors: [CompressorType; 3]) -> BsdiffWriter {
+        return BsdiffWriter {
+            compressors,
+            legacy: false,
+            ctrl_stream: Vec::new(),
+            diff_stream: Vec::new(),
+            extra_stream: Vec::new(),
+            new_file_size: 0,
+        };
+    }
+
+    /// A writer for the classic BSDIFF40 layout of upstream bsdiff 4.x:
+    /// bzip2 for every stream and a "BSDIFF40" magic, so the result can be
+    /// applied by the stock `bspatch`.
+    pub fn bsdiff40() -> BsdiffWriter {
+        let mut writer = BsdiffWriter::new([CompressorType::Bz2; 3]);
+        writer.legacy = true;
+        return writer;
+    }
+
+    /// Appends a control entry. `diff` is added bytewise to the source at
+    /// the current source offset, `extra` is copied to the target as is,
+    /// then the source offset moves by `offset_increment`.
+    pub fn add_entry(&mut self, diff: &[u8], extra: &[u8], offset_increment: i64) {
+        self.ctrl_stream.extend_from_slice(&encode_entry(
+            diff.len(),
+            extra.len(),
+            offset_increment,
+        ));
+        self.diff_stream.extend_from_slice(diff);
+        self.extra_stream.extend_from_slice(extra);
+        self.new_file_size += (diff.len() + extra.len()) as u64;
+    }
+
+    /// Size of the new file the entries added so far produce.
+    pub fn new_file_size(&self) -> u64 {
+        return self.new_file_size;
+    }
+
+    /// Compresses the streams and writes the complete patch to `out`.
+    pub fn write_to<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        let [ctrl_compressor, diff_compressor, extra_compressor] = self.compressors;
+        let ctrl = compress(&self.ctrl_stream, ctrl_compressor)?;
+        let diff = compress(&self.diff_stream, diff_compressor)?;
+        let extra = compress(&self.extra_stream, extra_compressor)?;
+        let magic = if self.legacy {
+            *b"BSDIFF40"
+        } else {
+            bsdf2_magic(self.compressors)
+        };
+        write_header(
+            out,
+            magic,
+            ctrl.len() as u64,
+            diff.len() as u64,
+            self.new_file_size,
+        )?;
+        out.write_all(&ctrl)?;
+        out.write_all(&diff)?;
+        out.write_all(&extra)?;
+        return Ok(());
+    }
+
+    /// Same as `write_to`, but returns the patch as a buffer.
+    pub fn finish(&self) -> io::Result<Vec<u8>> {
+        let mut patch = Vec::new();
+        self.write_to(&mut patch)?;
+        return Ok(patch);
+    }
+}
+
+/// Builds a BSDF2 patch like `BsdiffWriter`, but compresses the streams as
+/// entries are added instead of keeping them uncompressed in memory.
+///
+/// Since the header and the ctrl stream come before the diff and extra
+/// streams, only the ctrl stream is compressed straight into `out`. The
+/// diff and extra streams are compressed into two spools and copied after
+/// it by `finish`, which then seeks back to fill in the header sizes. So
+/// `out` must be `Write + Seek`, and the spools `Read + Write + Seek`,
+/// for example temporary files. `new` spools into memory, which holds the
+/// compressed streams only.
+pub struct StreamingBsdiffWriter<W: Write + Seek, S: Read + Write + Seek = Cursor<Vec<u8>>> {
+    compressors: [CompressorType; 3],
+    ctrl: Encoder<W>,
+    diff: Encoder<S>,
+    extra: Encoder<S>,
+    // Where the patch starts in `out`, and the spools start in theirs.
+    header_start: u64,
+    diff_spool_start: u64,
+    extra_spool_start: u64,
+    new_file_size: u64,
+}
+
+impl<W: Write + Seek> StreamingBsdiffWriter<W> {
+    pub fn new(out: W, compressors: [CompressorType; 3]) -> io::Result<StreamingBsdiffWriter<W>> {
+        return StreamingBsdiffWriter::with_spools(
+            out,
+            Cursor::new(Vec::new()),
+            Cursor::new(Vec::new()),
+            compressors,
+        );
+    }
+}
+
+impl<W: Write + Seek, S: Read + Write + Seek> StreamingBsdiffWriter<W, S> {
+    /// Writes the patch to `out` from its current position, spooling the
+    /// diff and extra streams to the given writers from theirs.
+    pub fn with_spools(
+        mut out: W,
+        mut diff_spool: S,
+        mut extra_spool: S,
+        compressors: [CompressorType; 3],
+    ) -> io::Result<StreamingBsdiffWriter<W, S>> {
+        let header_start = out.stream_position()?;
+        // Placeholder, filled in by `finish`.
+        out.write_all(&[0; HEADER_SIZE as usize])?;
+        let diff_spool_start = diff_spool.stream_position()?;
+        let extra_spool_start = extra_spool.stream_position()?;
+        let [ctrl_compressor, diff_compressor, extra_compressor] = compressors;
+        return Ok(StreamingBsdiffWriter {
+            compressors,
+            ctrl: Encoder::new(out, ctrl_compressor)?,
+            diff: Encoder::new(diff_spool, diff_compressor)?,
+            extra: Encoder::new(extra_spool, extra_compressor)?,
+            header_start,
+            diff_spool_start,
+            extra_spool_start,
+            new_file_size: 0,
+        });
+    }
+
+    /// See `BsdiffWriter::add_entry`.
+    pub fn add_entry(
+        &mut self,
+        diff: &[u8],
+        extra: &[u8],
+        offset_increment: i64,
+    ) -> io::Result<()> {
+        self.ctrl
+            .write_all(&encode_entry(diff.len(), extra.len(), offset_increment))?;
+        self.diff.write_all(diff)?;
+        self.extra.write_all(extra)?;
+        self.new_file_size += (diff.len() + extra.len()) as u64;
+        return Ok(());
+    }
+
+    /// Size of the new file the entries added so far produce.
+    pub fn new_file_size(&self) -> u64 {
+        return self.new_file_size;
+    }
+
+    /// Ends the streams, appends the spooled ones and writes the header.
+    /// Returns `out` positioned at the end of the patch.
+    pub fn finish(self) -> io::Result<W> {
+        let mut out = self.ctrl.finish()?;
+        let diff_start = out.stream_position()?;
+        let ctrl_len = diff_start - self.header_start - HEADER_SIZE;
+        append_spool(&mut out, self.diff.finish()?, self.diff_spool_start)?;
+        let extra_start = out.stream_position()?;
+        append_spool(&mut out, self.extra.finish()?, self.extra_spool_start)?;
+        let end = out.stream_position()?;
+
+        out.seek(SeekFrom::Start(self.header_start))?;
+        write_header(
+            &mut out,
+            bsdf2_magic(self.compressors),
+            ctrl_len,
+            extra_start - diff_start,
+            self.new_file_size,
+        )?;
+        out.seek(SeekFrom::Start(end))?;
+        return Ok(out);
+    }
+}
+
+// Copies everything written to `spool` after `start` to `out`.
+fn append_spool<W: Write, S: Read + Seek>(out: &mut W, mut spool: S, start: u64) -> io::Result<()> {
+    let end = spool.stream_position()?;
+    spool.seek(SeekFrom::Start(start))?;
+    io::copy(&mut spool.take(end - start), out)?;
+    return Ok(());
+}
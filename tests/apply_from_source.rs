@@ -0,0 +1,40 @@
+#![cfg(feature = "bz2")]
+
+mod common;
+
+use std::io::Cursor;
+
+use bsdump::bsdiff_format::{BsdiffReader, CompressorType};
+use bsdump::options::BsdiffReaderOptions;
+use bsdump::writer::BsdiffWriter;
+
+#[test]
+fn matches_apply_for_every_chunk_size() {
+    let fixture = common::generate(21, 8192, [CompressorType::Bz2; 3]);
+    for chunk_size in [1, 7, 64, 1 << 20] {
+        let options = BsdiffReaderOptions::new().source_chunk_size(chunk_size);
+        let reader = BsdiffReader::with_options(&fixture.patch, options).unwrap();
+        let mut out = Vec::new();
+        reader
+            .apply_from_source(Cursor::new(&fixture.source), &mut out)
+            .unwrap();
+        assert_eq!(out, fixture.target, "chunk size {}", chunk_size);
+    }
+}
+
+#[test]
+fn reads_outside_the_source_are_zero() {
+    // Reads two bytes before the source and past its end.
+    let mut writer = BsdiffWriter::new([CompressorType::Bz2; 3]);
+    writer.add_entry(&[], b"", -2);
+    writer.add_entry(&[1; 8], b"", 0);
+    let patch = writer.finish().unwrap();
+    let options = BsdiffReaderOptions::new().source_chunk_size(3);
+    let reader = BsdiffReader::with_options(&patch, options).unwrap();
+    let mut out = Vec::new();
+    reader
+        .apply_from_source(Cursor::new(b"abcd"), &mut out)
+        .unwrap();
+    assert_eq!(out, reader.apply(b"abcd").unwrap());
+    assert_eq!(out, b"\x01\x01bcde\x01\x01");
+}
@@ -0,0 +1,28 @@
+#![cfg(feature = "bz2")]
+
+mod common;
+
+use bsdump::bsdiff_format::BsdiffReader;
+use bsdump::error::BsdumpError;
+
+#[test]
+fn forward_reads_apply_in_place() {
+    let patch = common::bsdf2_patch(&[(2, 1, 2), (2, 0, 0)], &[0, 1, 0, 1], b"X", 5);
+    let reader = BsdiffReader::new(&patch).unwrap();
+    let mut buf = b"abcdef".to_vec();
+    reader.apply_in_place(&mut buf).unwrap();
+    assert_eq!(buf, reader.apply(b"abcdef").unwrap());
+    assert_eq!(buf, b"acXeg");
+}
+
+#[test]
+fn backward_read_is_rejected_and_buffer_untouched() {
+    // The extra bytes overwrite [0, 3) before the second entry reads [0, 2).
+    let patch = common::bsdf2_patch(&[(0, 3, 0), (2, 0, 0)], &[0, 0], b"XYZ", 5);
+    let reader = BsdiffReader::new(&patch).unwrap();
+    let mut buf = b"abcdef".to_vec();
+    let err = reader.apply_in_place(&mut buf).unwrap_err();
+    assert!(matches!(err, BsdumpError::InPlaceUnsafe { index: 1 }));
+    assert_eq!(buf, b"abcdef");
+    assert_eq!(reader.apply(b"abcdef").unwrap(), b"XYZab");
+}
@@ -0,0 +1,89 @@
+#![cfg(feature = "bz2")]
+
+mod common;
+
+use std::fs;
+
+use bsdump::bsdiff_format::{BsdiffReader, CompressorType};
+use bsdump::error::BsdumpError;
+use bsdump::options::BsdiffReaderOptions;
+use bsdump::writer::BsdiffWriter;
+
+#[test]
+fn new_file_is_written_through_a_mapping() {
+    let dir = std::env::temp_dir().join(format!("bsdump-mmap-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let fixture = common::generate(8, 16384, [CompressorType::Bz2; 3]);
+    let reader = BsdiffReader::new(&fixture.patch).unwrap();
+    let out_path = dir.join("target");
+    // Left over content is truncated away.
+    fs::write(&out_path, vec![0xaa; 100_000]).unwrap();
+    reader.apply_to_mmap(&fixture.source, &out_path).unwrap();
+    assert_eq!(fs::read(&out_path).unwrap(), fixture.target);
+
+    // An empty new file can't be mapped and is written directly.
+    let empty = BsdiffWriter::new([CompressorType::Bz2; 3])
+        .finish()
+        .unwrap();
+    let empty_path = dir.join("empty");
+    BsdiffReader::new(&empty)
+        .unwrap()
+        .apply_to_mmap(b"source", &empty_path)
+        .unwrap();
+    let empty_len = fs::metadata(&empty_path).unwrap().len();
+
+    let options = BsdiffReaderOptions::new().max_output(10);
+    let limited = BsdiffReader::with_options(&fixture.patch, options).unwrap();
+    let result = limited.apply_to_mmap(&fixture.source, &dir.join("limited"));
+    let limited_created = dir.join("limited").exists();
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(empty_len, 0);
+    match result {
+        Err(BsdumpError::OutputTooLarge { .. }) => {}
+        Err(e) => panic!("unexpected error {}", e),
+        Ok(()) => panic!("output limit was ignored"),
+    }
+    // The limit is checked before the file is created.
+    assert!(!limited_created);
+}
+
+#[test]
+fn failed_apply_leaves_no_file() {
+    let dir = std::env::temp_dir().join(format!("bsdump-mmap-fail-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+
+    // The header claims far more than the empty extra stream holds.
+    let size = 1 << 60;
+    let unbacked = common::bsdf2_patch(&[(0, size, 0)], b"", b"", size);
+    let unbacked_path = dir.join("unbacked");
+    let unbacked_result = BsdiffReader::new(&unbacked)
+        .unwrap()
+        .apply_to_mmap(b"", &unbacked_path);
+    let unbacked_created = unbacked_path.exists();
+
+    // Sizes add up, but the source pointer runs past i64 after the first
+    // entry, which only applying finds.
+    let overflowing = common::bsdf2_patch(&[(1, 0, i64::MAX), (1, 0, 0)], &[0, 0], b"", 2);
+    let overflowing_path = dir.join("overflowing");
+    let overflowing_result = BsdiffReader::new(&overflowing)
+        .unwrap()
+        .apply_to_mmap(b"ab", &overflowing_path);
+    let overflowing_left = overflowing_path.exists();
+    fs::remove_dir_all(&dir).unwrap();
+
+    match unbacked_result {
+        Err(BsdumpError::ExtraLengthMismatch { expected, found }) => {
+            assert_eq!((expected, found), (size, 0))
+        }
+        Err(e) => panic!("unexpected error {}", e),
+        Ok(()) => panic!("unbacked header size was applied"),
+    }
+    assert!(!unbacked_created);
+    match overflowing_result {
+        Err(BsdumpError::EntryOutOfBounds { entry, .. }) => assert_eq!(entry.index, 0),
+        Err(e) => panic!("unexpected error {}", e),
+        Ok(()) => panic!("overflowing source offset was applied"),
+    }
+    assert!(!overflowing_left);
+}
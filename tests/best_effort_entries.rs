@@ -0,0 +1,92 @@
+#![cfg(feature = "bz2")]
+
+use bsdump::bsdiff_format::{BsdiffReader, CompressorType};
+use bsdump::compression::compress;
+use bsdump::error::BsdumpError;
+use bsdump::options::BsdiffReaderOptions;
+use bsdump::writer::BsdiffWriter;
+
+// A BSDF2 patch with two whole control entries and 10 bytes of a third.
+fn truncated_ctrl_patch() -> Vec<u8> {
+    let mut ctrl = Vec::new();
+    for (diff_size, extra_size) in [(2_u64, 1_u64), (1, 0)] {
+        ctrl.extend_from_slice(&diff_size.to_le_bytes());
+        ctrl.extend_from_slice(&extra_size.to_le_bytes());
+        ctrl.extend_from_slice(&0_u64.to_le_bytes());
+    }
+    ctrl.extend_from_slice(&[7; 10]);
+    let ctrl = compress(&ctrl, CompressorType::Bz2).unwrap();
+    let diff = compress(&[0, 0, 0], CompressorType::Bz2).unwrap();
+    let extra = compress(b"x", CompressorType::Bz2).unwrap();
+    let mut patch = b"BSDF2\x01\x01\x01".to_vec();
+    patch.extend_from_slice(&(ctrl.len() as u64).to_le_bytes());
+    patch.extend_from_slice(&(diff.len() as u64).to_le_bytes());
+    patch.extend_from_slice(&4_u64.to_le_bytes());
+    patch.extend(ctrl);
+    patch.extend(diff);
+    patch.extend(extra);
+    return patch;
+}
+
+#[test]
+fn partial_entry_is_rejected_by_default() {
+    let patch = truncated_ctrl_patch();
+    assert!(BsdiffReader::new(&patch).is_err());
+}
+
+#[test]
+fn best_effort_yields_whole_entries_then_the_truncation() {
+    let patch = truncated_ctrl_patch();
+    let options = BsdiffReaderOptions::new().allow_partial_ctrl_entry(true);
+    let reader = BsdiffReader::with_options(&patch, options).unwrap();
+    let results: Vec<_> = reader.control_entries_best_effort().collect();
+    assert_eq!(results.len(), 3);
+    assert_eq!(results[0].as_ref().unwrap().diff_size, 2);
+    assert_eq!(results[1].as_ref().unwrap().diff_size, 1);
+    assert!(matches!(
+        results[2],
+        Err(BsdumpError::TruncatedControlEntry {
+            index: 2,
+            offset: 48,
+            available: 10
+        })
+    ));
+
+    // Everything else only sees the whole entries.
+    assert_eq!(reader.num_control_entries(), 2);
+    assert_eq!(reader.control_entries().count(), 2);
+    assert_eq!(reader.apply(b"abc").unwrap(), b"abxc");
+}
+
+#[test]
+fn best_effort_on_a_clean_patch_has_no_error() {
+    let mut writer = BsdiffWriter::new([CompressorType::Bz2; 3]);
+    writer.add_entry(&[0, 0], b"x", 0);
+    let patch = writer.finish().unwrap();
+    let reader = BsdiffReader::new(&patch).unwrap();
+    let results: Vec<_> = reader.control_entries_best_effort().collect();
+    assert_eq!(results.len(), 1);
+    assert!(results[0].is_ok());
+}
+
+#[test]
+fn lenient_control_keeps_whole_entries_and_counts_the_rest() {
+    let patch = truncated_ctrl_patch();
+    let reader = BsdiffReaderOptions::new()
+        .lenient_control(true)
+        .read(&patch)
+        .unwrap();
+    assert_eq!(reader.num_control_entries(), 2);
+    assert_eq!(reader.trailing_ctrl_bytes(), 10);
+    // The partial entry stays in the stream as decompressed.
+    assert_eq!(reader.decompressed_ctrl_stream().len(), 2 * 24 + 10);
+
+    assert!(BsdiffReaderOptions::new()
+        .lenient_control(false)
+        .read(&patch)
+        .is_err());
+    let whole = BsdiffWriter::new([CompressorType::Bz2; 3])
+        .finish()
+        .unwrap();
+    assert_eq!(BsdiffReader::new(&whole).unwrap().trailing_ctrl_bytes(), 0);
+}
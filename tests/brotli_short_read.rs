@@ -0,0 +1,70 @@
+#![cfg(feature = "brotli")]
+
+mod common;
+
+use bsdump::bsdiff_format::{BsdiffReader, CompressorType};
+use bsdump::compression::compress;
+use bsdump::error::BsdumpError;
+use std::convert::TryInto;
+
+// A BSDF2 brotli patch with a single entry reading `diff_size` and
+// `extra_size` bytes, whose streams hold `diff` and `extra`. Each stream is
+// a complete brotli stream, which is what a decoder that stops early on a
+// truncated stream hands back.
+fn brotli_patch(diff_size: u64, extra_size: u64, diff: &[u8], extra: &[u8]) -> Vec<u8> {
+    let ctrl = common::ctrl_stream(&[(diff_size, extra_size, 0)]);
+    return common::raw_patch(
+        CompressorType::Brotli,
+        &ctrl,
+        diff,
+        extra,
+        diff_size + extra_size,
+    );
+}
+
+fn assert_truncated(result: Result<impl Sized, BsdumpError>, stream: &str, expected: (u64, u64)) {
+    match result {
+        Err(BsdumpError::TruncatedStream {
+            name,
+            len,
+            available,
+        }) => {
+            assert_eq!(name, stream);
+            assert_eq!((len, available), expected);
+        }
+        Err(e) => panic!("unexpected error {}", e),
+        Ok(_) => panic!("short {} stream was accepted", stream),
+    }
+}
+
+#[test]
+fn short_brotli_diff_stream_is_reported() {
+    let patch = brotli_patch(8, 0, &[0; 5], &[]);
+    assert_truncated(BsdiffReader::new(&patch), "diff", (8, 5));
+}
+
+#[test]
+fn short_brotli_extra_stream_is_reported() {
+    let patch = brotli_patch(0, 4, &[], b"ab");
+    let reader = BsdiffReader::new(&patch).unwrap();
+    assert_truncated(reader.decompressed_extra_stream(), "extra", (4, 2));
+    assert_truncated(reader.apply(&[]), "extra", (4, 2));
+}
+
+#[test]
+fn short_brotli_ctrl_stream_is_reported() {
+    let mut patch = brotli_patch(0, 0, &[], &[]);
+    // Swap in a ctrl stream that ends partway through its entry.
+    let ctrl = compress(&[0; 20], CompressorType::Brotli).unwrap();
+    let old_ctrl_len = u64::from_le_bytes(patch[8..16].try_into().unwrap()) as usize;
+    patch.splice(32..32 + old_ctrl_len, ctrl.iter().copied());
+    patch[8..16].copy_from_slice(&(ctrl.len() as u64).to_le_bytes());
+    assert_truncated(BsdiffReader::new(&patch), "ctrl", (24, 20));
+}
+
+#[test]
+fn complete_brotli_streams_still_parse() {
+    let patch = brotli_patch(3, 2, &[0; 3], b"xy");
+    let reader = BsdiffReader::new(&patch).unwrap();
+    assert_eq!(reader.apply(&[1, 2, 3]).unwrap(), vec![1, 2, 3, b'x', b'y']);
+}
@@ -0,0 +1,42 @@
+#![cfg(feature = "brotli")]
+
+mod common;
+
+use bsdump::bsdiff_format::{BsdiffReader, CompressorType};
+use bsdump::compression::{brotli_window_bits, compress};
+use bsdump::error::BsdumpError;
+
+const BROTLI: [CompressorType; 3] = [CompressorType::Brotli; 3];
+
+#[test]
+fn window_bits_are_read_from_the_stream_header() {
+    let compressed = compress(b"hello hello hello", CompressorType::Brotli).unwrap();
+    assert_eq!(brotli_window_bits(&compressed), Some(22));
+    // A single zero bit means the default 16 bit window.
+    assert_eq!(brotli_window_bits(&[0b0]), Some(16));
+    // 1, then 000, then 010: a 10 bit window.
+    assert_eq!(brotli_window_bits(&[0b0010_0001]), Some(10));
+    assert_eq!(brotli_window_bits(&[]), None);
+}
+
+#[test]
+fn streams_over_the_limit_are_rejected() {
+    let fixture = common::generate(7, 2048, BROTLI);
+    match BsdiffReader::builder()
+        .max_brotli_window(1 << 21)
+        .read(&fixture.patch)
+    {
+        Err(BsdumpError::BrotliWindowTooLarge { window_size, max }) => {
+            assert_eq!(window_size, 1 << 22);
+            assert_eq!(max, 1 << 21);
+        }
+        Err(e) => panic!("unexpected error {}", e),
+        Ok(_) => panic!("large window was accepted"),
+    }
+
+    let reader = BsdiffReader::builder()
+        .max_brotli_window(1 << 22)
+        .read(&fixture.patch)
+        .unwrap();
+    assert_eq!(reader.apply(&fixture.source).unwrap(), fixture.target);
+}
@@ -0,0 +1,24 @@
+#![cfg(feature = "bz2")]
+
+use bsdump::bsdiff_format::{BsdiffReader, CompressorType};
+use bsdump::options::BsdiffReaderBuilder;
+use bsdump::writer::BsdiffWriter;
+
+#[test]
+fn builder_applies_every_setter() {
+    let mut writer = BsdiffWriter::new([CompressorType::Bz2; 3]);
+    writer.add_entry(&[0], b"x", 0);
+    writer.add_entry(&[0], b"y", 0);
+    let mut patch = writer.finish().unwrap();
+    patch.extend_from_slice(b"crc!");
+
+    let builder: BsdiffReaderBuilder = BsdiffReader::builder();
+    let reader = builder
+        .max_control_entries(1)
+        .footer_len(4)
+        .read(&patch)
+        .unwrap();
+    assert_eq!(reader.control_entries().count(), 1);
+    assert_eq!(reader.footer(), b"crc!");
+    assert_eq!(reader.apply(b"ab").unwrap(), b"axby");
+}
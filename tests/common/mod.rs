@@ -0,0 +1,161 @@
+use bsdump::bsdiff_format::CompressorType;
+use bsdump::compression::compress;
+use bsdump::writer::BsdiffWriter;
+
+/// A synthetic source/target pair and a BSDF2 patch between them.
+// Not every test binary that includes this module reads every field.
+#[allow(dead_code)]
+pub struct Fixture {
+    pub source: Vec<u8>,
+    pub target: Vec<u8>,
+    pub patch: Vec<u8>,
+    pub num_entries: usize,
+}
+
+// xorshift64, so fixtures are reproducible without a rand dependency.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        return self.0;
+    }
+
+    fn below(&mut self, n: u64) -> u64 {
+        return self.next() % n;
+    }
+}
+
+/// Generates a fixture from `seed`. The target is built from the source by a
+/// random edit script of copies with a few changed bytes, insertions and
+/// skips, and the same script is fed to `BsdiffWriter`, so the patch is
+/// known to turn the source into the target.
+#[allow(dead_code)]
+pub fn generate(seed: u64, source_len: usize, compressors: [CompressorType; 3]) -> Fixture {
+    let mut rng = Rng(seed | 1);
+    // A small alphabet keeps the streams compressible like real binaries.
+    let source: Vec<u8> = (0..source_len)
+        .map(|_| b'a' + rng.below(16) as u8)
+        .collect();
+    return edit(&mut rng, source, compressors);
+}
+
+/// Same as `generate`, but edits the given source.
+// Not every test binary that includes this module uses it.
+#[allow(dead_code)]
+pub fn generate_from(seed: u64, source: Vec<u8>, compressors: [CompressorType; 3]) -> Fixture {
+    return edit(&mut Rng(seed | 1), source, compressors);
+}
+
+fn edit(rng: &mut Rng, source: Vec<u8>, compressors: [CompressorType; 3]) -> Fixture {
+    let mut target = Vec::new();
+    let mut writer = BsdiffWriter::new(compressors);
+    let mut num_entries = 0;
+    let mut old_pos = 0_i64;
+    while (old_pos as usize) < source.len() {
+        let remaining = source.len() - old_pos as usize;
+        let copy_len = (64 + rng.below(448) as usize).min(remaining);
+        let copied = &source[old_pos as usize..][..copy_len];
+        let diff: Vec<u8> = (0..copy_len)
+            .map(|_| {
+                if rng.below(16) == 0 {
+                    rng.next() as u8
+                } else {
+                    0
+                }
+            })
+            .collect();
+        target.extend(copied.iter().zip(&diff).map(|(s, d)| s.wrapping_add(*d)));
+        let extra: Vec<u8> = (0..rng.below(32)).map(|_| rng.next() as u8).collect();
+        target.extend_from_slice(&extra);
+        // Seek back up to 32 bytes or skip up to 63, staying inside the source.
+        let after_copy = old_pos + copy_len as i64;
+        let offset_increment = (rng.below(96) as i64 - 32).min(source.len() as i64 - after_copy);
+        writer.add_entry(&diff, &extra, offset_increment);
+        num_entries += 1;
+        old_pos = after_copy + offset_increment;
+    }
+    return Fixture {
+        source,
+        target,
+        patch: writer.finish().unwrap(),
+        num_entries,
+    };
+}
+
+/// A bz2 BSDF2 patch with one control entry per `(diff, extra,
+/// offset_increment)`, written by `BsdiffWriter`.
+#[allow(dead_code)]
+pub fn write_patch(entries: &[(&[u8], &[u8], i64)]) -> Vec<u8> {
+    let mut writer = BsdiffWriter::new([CompressorType::Bz2; 3]);
+    for (diff, extra, offset_increment) in entries {
+        writer.add_entry(diff, extra, *offset_increment);
+    }
+    return writer.finish().unwrap();
+}
+
+/// The raw control stream for `(diff_size, extra_size, offset_increment)`
+/// entries as BSDF2 stores it: little endian sizes, and the seek with its
+/// sign in the top bit.
+#[allow(dead_code)]
+pub fn ctrl_stream(entries: &[(u64, u64, i64)]) -> Vec<u8> {
+    let mut ctrl = Vec::new();
+    for (diff_size, extra_size, offset_increment) in entries {
+        let sign = if *offset_increment < 0 { 1 << 63 } else { 0 };
+        ctrl.extend_from_slice(&diff_size.to_le_bytes());
+        ctrl.extend_from_slice(&extra_size.to_le_bytes());
+        ctrl.extend_from_slice(&(offset_increment.unsigned_abs() | sign).to_le_bytes());
+    }
+    return ctrl;
+}
+
+/// A BSDF2 patch assembled by hand from a raw control stream and raw diff
+/// and extra streams, all compressed with `compressor`. Nothing is checked,
+/// so the header, the entries and the streams can disagree.
+#[allow(dead_code)]
+pub fn raw_patch(
+    compressor: CompressorType,
+    ctrl: &[u8],
+    diff: &[u8],
+    extra: &[u8],
+    new_file_size: u64,
+) -> Vec<u8> {
+    let id = match compressor {
+        CompressorType::Bz2 => 1,
+        CompressorType::Brotli => 2,
+        CompressorType::Zstd => 3,
+        CompressorType::Gzip => 4,
+    };
+    let ctrl = compress(ctrl, compressor).unwrap();
+    let diff = compress(diff, compressor).unwrap();
+    let extra = compress(extra, compressor).unwrap();
+    let mut patch = b"BSDF2".to_vec();
+    patch.extend_from_slice(&[id; 3]);
+    patch.extend_from_slice(&(ctrl.len() as u64).to_le_bytes());
+    patch.extend_from_slice(&(diff.len() as u64).to_le_bytes());
+    patch.extend_from_slice(&new_file_size.to_le_bytes());
+    patch.extend(ctrl);
+    patch.extend(diff);
+    patch.extend(extra);
+    return patch;
+}
+
+/// `raw_patch` with bz2 streams and the control stream built from
+/// `entries`, see `ctrl_stream`.
+#[allow(dead_code)]
+pub fn bsdf2_patch(
+    entries: &[(u64, u64, i64)],
+    diff: &[u8],
+    extra: &[u8],
+    new_file_size: u64,
+) -> Vec<u8> {
+    return raw_patch(
+        CompressorType::Bz2,
+        &ctrl_stream(entries),
+        diff,
+        extra,
+        new_file_size,
+    );
+}
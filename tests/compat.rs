@@ -0,0 +1,74 @@
+use bsdump::bsdiff_format::{BsdiffFormat, CompressorType};
+use bsdump::compat::{find_applier, ApplierProfile, Incompatibility, APPLIERS};
+
+// A header with the given magic and zero sizes.
+fn header(magic: &[u8; 8]) -> BsdiffFormat {
+    let mut data = magic.to_vec();
+    data.extend_from_slice(&[0; 24]);
+    return BsdiffFormat::parse_unchecked(&data).unwrap();
+}
+
+#[test]
+fn bspatch_rejects_brotli_bsdf2() {
+    let bspatch = find_applier("bspatch").unwrap();
+    assert_eq!(
+        bspatch.check(&header(b"BSDF2\x01\x02\x01")),
+        [
+            Incompatibility::Format("BSDF2"),
+            Incompatibility::Compressor {
+                stream: "diff",
+                compressor_type: CompressorType::Brotli
+            },
+        ]
+    );
+    assert!(bspatch.check(&header(b"BSDIFF40")).is_empty());
+}
+
+#[test]
+fn bdf3_needs_brotli_for_its_mask() {
+    let profile = ApplierProfile {
+        name: "bz2-only",
+        description: "",
+        formats: &["BDF3"],
+        compressors: &[CompressorType::Bz2],
+    };
+    assert_eq!(
+        profile.check(&header(b"BDF3\x00\x01\x01\x01")),
+        [Incompatibility::Compressor {
+            stream: "mask",
+            compressor_type: CompressorType::Brotli
+        }]
+    );
+}
+
+#[test]
+fn android_ota_takes_bz2_and_brotli_bsdf2() {
+    let android = find_applier("android-ota").unwrap();
+    assert!(android.check(&header(b"BSDF2\x02\x02\x01")).is_empty());
+    let problems = android.check(&header(b"BSDF2\x01\x01\x03"));
+    assert_eq!(
+        problems[0].to_string(),
+        "unsupported Zstd compression of the extra stream"
+    );
+    assert!(find_applier("unknown").is_none());
+    assert!(APPLIERS
+        .iter()
+        .all(|profile| find_applier(profile.name) == Some(profile)));
+}
+
+#[test]
+fn unknown_compressor_byte_is_reported() {
+    let bsdump = find_applier("bsdump").unwrap();
+    let problems = bsdump.check(&header(b"BSDF2\x01\x01\x09"));
+    assert_eq!(
+        problems,
+        [Incompatibility::UnknownCompressor {
+            stream: "extra",
+            value: 9
+        }]
+    );
+    assert_eq!(
+        problems[0].to_string(),
+        "unknown compressor type 9 for the extra stream"
+    );
+}
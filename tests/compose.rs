@@ -0,0 +1,66 @@
+#![cfg(all(feature = "bz2", feature = "zstd"))]
+
+mod common;
+
+use bsdump::bsdiff_format::{BsdiffReader, CompressorType};
+use bsdump::error::BsdumpError;
+use bsdump::writer::BsdiffWriter;
+
+#[test]
+fn composed_patch_turns_a_into_c() {
+    for seed in 1..5 {
+        let a_to_b = common::generate(seed, 4096, [CompressorType::Bz2; 3]);
+        let b_to_c =
+            common::generate_from(seed + 100, a_to_b.target.clone(), [CompressorType::Zstd; 3]);
+        let first = BsdiffReader::new(&a_to_b.patch).unwrap();
+        let second = BsdiffReader::new(&b_to_c.patch).unwrap();
+        assert_eq!(second.num_control_entries(), b_to_c.num_entries);
+
+        let composed = BsdiffReader::compose(&first, &second, &a_to_b.source).unwrap();
+        let reader = BsdiffReader::new(&composed).unwrap();
+        assert_eq!(
+            reader.header.compressors().unwrap(),
+            [CompressorType::Zstd; 3]
+        );
+        assert_eq!(reader.apply(&a_to_b.source).unwrap(), b_to_c.target);
+    }
+}
+
+#[test]
+fn bytes_from_either_patch_are_traced_back() {
+    // B = "ab" + "XY" + "cd" with "ab" and "cd" read from A = "abcd".
+    let mut writer = BsdiffWriter::new([CompressorType::Bz2; 3]);
+    writer.add_entry(&[0, 0], b"XY", 0);
+    writer.add_entry(&[0, 0], b"", 0);
+    let first_patch = writer.finish().unwrap();
+    // C reads B[1..5) = "bXYc" with +1 on every byte, then appends "!".
+    let mut writer = BsdiffWriter::new([CompressorType::Bz2; 3]);
+    writer.add_entry(&[], b"", 1);
+    writer.add_entry(&[1; 4], b"!", 0);
+    let second_patch = writer.finish().unwrap();
+
+    let first = BsdiffReader::new(&first_patch).unwrap();
+    let second = BsdiffReader::new(&second_patch).unwrap();
+    let composed = BsdiffReader::compose(&first, &second, b"abcd").unwrap();
+    let reader = BsdiffReader::new(&composed).unwrap();
+    assert_eq!(reader.apply(b"abcd").unwrap(), b"cYZd!");
+    let entries: Vec<_> = reader
+        .control_entries()
+        .map(|entry| (entry.diff_size, entry.extra_size, entry.offset_increment))
+        .collect();
+    // "b" is read from A, "YZ!" and nothing else is stored as extra.
+    assert_eq!(entries, [(0, 0, 1), (1, 2, 0), (1, 1, 0)]);
+}
+
+#[test]
+fn second_patch_must_fit_the_intermediate_file() {
+    let a_to_b = common::generate(7, 1024, [CompressorType::Bz2; 3]);
+    let mut writer = BsdiffWriter::new([CompressorType::Bz2; 3]);
+    writer.add_entry(&vec![0; a_to_b.target.len() + 1], b"", 0);
+    let second_patch = writer.finish().unwrap();
+
+    let first = BsdiffReader::new(&a_to_b.patch).unwrap();
+    let second = BsdiffReader::new(&second_patch).unwrap();
+    let result = BsdiffReader::compose(&first, &second, &a_to_b.source);
+    assert!(matches!(result, Err(BsdumpError::EntryOutOfBounds { .. })));
+}
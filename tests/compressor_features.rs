@@ -0,0 +1,32 @@
+use bsdump::bsdiff_format::CompressorType;
+
+#[test]
+fn is_compiled_follows_features() {
+    assert_eq!(CompressorType::Bz2.is_compiled(), cfg!(feature = "bz2"));
+    assert_eq!(
+        CompressorType::Brotli.is_compiled(),
+        cfg!(feature = "brotli")
+    );
+    assert_eq!(CompressorType::Zstd.is_compiled(), cfg!(feature = "zstd"));
+    assert_eq!(CompressorType::Gzip.is_compiled(), cfg!(feature = "gzip"));
+}
+
+#[test]
+#[cfg(not(feature = "zstd"))]
+fn disabled_codec_is_reported() {
+    use bsdump::bsdiff_format::BsdiffReader;
+    use bsdump::error::BsdumpError;
+
+    // The header is all that gets looked at before the codec check.
+    let mut patch = b"BSDF2\x03\x03\x03".to_vec();
+    patch.extend_from_slice(&8_u64.to_le_bytes());
+    patch.extend_from_slice(&8_u64.to_le_bytes());
+    patch.extend_from_slice(&0_u64.to_le_bytes());
+    patch.extend_from_slice(&[0; 24]);
+    match BsdiffReader::new(&patch) {
+        Err(BsdumpError::CompressorNotCompiled { compressor_type }) => {
+            assert_eq!(compressor_type, CompressorType::Zstd)
+        }
+        other => panic!("unexpected result {:?}", other.map(|r| r.header)),
+    }
+}
@@ -0,0 +1,87 @@
+#![cfg(all(feature = "bz2", feature = "brotli"))]
+
+use bsdump::bsdiff_format::{BsdiffReader, CompressorType};
+use bsdump::compression::compress;
+use bsdump::error::BsdumpError;
+use bsdump::options::BsdiffReaderOptions;
+use bsdump::writer::BsdiffWriter;
+
+fn bdf3_patch(ctrl: &[u8], new_size: u64) -> Vec<u8> {
+    let ctrl = compress(ctrl, CompressorType::Bz2).unwrap();
+    // The full diff stream is [0, 5, 0, 0]; only the second byte is stored.
+    let diff = compress(&[5], CompressorType::Bz2).unwrap();
+    let extra = compress(b"xy", CompressorType::Bz2).unwrap();
+    let mask = compress(&[0b10], CompressorType::Brotli).unwrap();
+    let mut patch = b"BDF3\x00\x01\x01\x01".to_vec();
+    patch.extend_from_slice(&(ctrl.len() as u64).to_le_bytes());
+    patch.extend_from_slice(&(diff.len() as u64).to_le_bytes());
+    patch.extend_from_slice(&new_size.to_le_bytes());
+    patch.extend_from_slice(&(mask.len() as u64).to_le_bytes());
+    patch.extend_from_slice(&ctrl);
+    patch.extend_from_slice(&diff);
+    patch.extend_from_slice(&extra);
+    patch.extend_from_slice(&mask);
+    return patch;
+}
+
+fn control_stream(entries: &[(u64, u64, u64)]) -> Vec<u8> {
+    let mut ctrl = Vec::new();
+    for (diff_size, extra_size, offset_increment) in entries {
+        ctrl.extend_from_slice(&diff_size.to_le_bytes());
+        ctrl.extend_from_slice(&extra_size.to_le_bytes());
+        ctrl.extend_from_slice(&offset_increment.to_le_bytes());
+    }
+    return ctrl;
+}
+
+#[test]
+fn bdf3_converts_to_an_equivalent_bsdf2_patch() {
+    // Read "ab", skip back one byte and read "bc" again.
+    let ctrl = control_stream(&[(2, 1, 1 << 63 | 1), (2, 1, 0)]);
+    let patch = bdf3_patch(&ctrl, 6);
+    let reader = BsdiffReader::new(&patch).unwrap();
+    assert!(reader.has_mask());
+
+    let converted = reader.to_bsdf2().unwrap();
+    let bsdf2 = BsdiffReader::new(&converted).unwrap();
+    assert!(converted.starts_with(b"BSDF2\x01\x01\x01"));
+    assert!(!bsdf2.has_mask());
+    assert_eq!(bsdf2.decompressed_diff_stream().unwrap(), [0, 5, 0, 0]);
+    assert!(bsdf2.control_entries().eq(reader.control_entries()));
+    assert_eq!(bsdf2.apply(b"abc").unwrap(), reader.apply(b"abc").unwrap());
+    assert_eq!(bsdf2.apply(b"abc").unwrap(), b"agxbcy");
+}
+
+#[test]
+fn brotli_patch_converts_to_canonical_bsdiff40() {
+    let mut writer = BsdiffWriter::new([CompressorType::Brotli; 3]);
+    writer.add_entry(&[0, 1], b"x", 1);
+    writer.add_entry(&[2], b"", -4);
+    writer.add_entry(&[0], b"yz", 0);
+    let patch = writer.finish().unwrap();
+    let reader = BsdiffReader::new(&patch).unwrap();
+
+    let canonical = reader.to_bsdiff40().unwrap();
+    assert!(canonical.starts_with(b"BSDIFF40"));
+    let bsdiff40 = BsdiffReader::new(&canonical).unwrap();
+    assert_eq!(bsdiff40.header.format_name(), "BSDIFF40");
+    assert_eq!(
+        bsdiff40.header.compressors().unwrap(),
+        [CompressorType::Bz2; 3]
+    );
+    assert!(bsdiff40.control_entries().eq(reader.control_entries()));
+    assert_eq!(bsdiff40.apply(b"abcd").unwrap(), b"acxfayz");
+}
+
+#[test]
+fn partial_control_entry_is_not_converted() {
+    let mut ctrl = control_stream(&[(2, 1, 0), (2, 1, 0)]);
+    ctrl.truncate(ctrl.len() - 4);
+    let patch = bdf3_patch(&ctrl, 6);
+    let options = BsdiffReaderOptions::new().allow_partial_ctrl_entry(true);
+    let reader = BsdiffReader::with_options(&patch, options).unwrap();
+    assert!(matches!(
+        reader.to_bsdf2(),
+        Err(BsdumpError::CorruptPatch(_))
+    ));
+}
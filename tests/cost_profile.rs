@@ -0,0 +1,44 @@
+#![cfg(feature = "bz2")]
+
+mod common;
+
+use bsdump::bsdiff_format::{BsdiffReader, CompressorType, SEEK_COST};
+use bsdump::writer::BsdiffWriter;
+
+#[test]
+fn entries_are_ranked_by_cost() {
+    let mut writer = BsdiffWriter::new([CompressorType::Bz2; 3]);
+    // Costs 2 * 10 + 5 = 25.
+    writer.add_entry(&[0; 10], &[1; 5], 0);
+    // Costs 2 * 3 + SEEK_COST.
+    writer.add_entry(&[0; 3], &[], -3);
+    // Costs 100.
+    writer.add_entry(&[], &[2; 100], 0);
+    // Costs 25 again, ranked after the first entry.
+    writer.add_entry(&[0; 5], &[3; 15], 0);
+    let patch = writer.finish().unwrap();
+    let reader = BsdiffReader::new(&patch).unwrap();
+
+    let ranked: Vec<_> = reader
+        .cost_profile(10)
+        .iter()
+        .map(|cost| (cost.entry.index, cost.cost))
+        .collect();
+    assert_eq!(ranked, vec![(1, 6 + SEEK_COST), (2, 100), (0, 25), (3, 25)]);
+
+    let top = reader.cost_profile(2);
+    assert_eq!(top.len(), 2);
+    assert_eq!(top[1].entry.target_offset, 18);
+    assert!(reader.cost_profile(0).is_empty());
+}
+
+#[test]
+fn costs_cover_every_byte_of_the_fixture() {
+    let fixture = common::generate(6, 4096, [CompressorType::Bz2; 3]);
+    let reader = BsdiffReader::new(&fixture.patch).unwrap();
+    let profile = reader.cost_profile(usize::MAX);
+    assert_eq!(profile.len(), fixture.num_entries);
+    assert!(profile.windows(2).all(|pair| pair[0].cost >= pair[1].cost));
+    let total: u64 = profile.iter().map(|cost| cost.cost).sum();
+    assert!(total >= fixture.target.len() as u64);
+}
@@ -0,0 +1,81 @@
+#![cfg(feature = "bz2")]
+
+mod common;
+
+use bsdump::bsdiff_format::{BsdiffReader, CompressorType};
+use bsdump::error::BsdumpError;
+use bsdump::writer::BsdiffWriter;
+
+fn out_of_bounds_index(result: Result<u64, BsdumpError>) -> usize {
+    return match result {
+        Err(BsdumpError::EntryOutOfBounds { entry, .. }) => entry.index,
+        Err(e) => panic!("unexpected error {}", e),
+        Ok(len) => panic!("dry apply produced {} bytes", len),
+    };
+}
+
+#[test]
+fn sound_patch_produces_the_new_file_size() {
+    let fixture = common::generate(8, 4096, [CompressorType::Bz2; 3]);
+    let reader = BsdiffReader::new(&fixture.patch).unwrap();
+    assert_eq!(
+        reader.dry_apply(fixture.source.len() as u64).unwrap(),
+        fixture.target.len() as u64
+    );
+}
+
+#[test]
+fn seek_before_the_source_is_caught_at_the_next_read() {
+    // Each entry alone is in bounds, but the seek of the first one moves
+    // the second read to -6.
+    let mut writer = BsdiffWriter::new([CompressorType::Bz2; 3]);
+    writer.add_entry(&[0; 4], b"", -10);
+    writer.add_entry(&[0; 2], b"", 0);
+    let patch = writer.finish().unwrap();
+    let reader = BsdiffReader::new(&patch).unwrap();
+    assert_eq!(out_of_bounds_index(reader.dry_apply(16)), 1);
+}
+
+#[test]
+fn reads_past_the_end_of_the_source_are_caught() {
+    let mut writer = BsdiffWriter::new([CompressorType::Bz2; 3]);
+    writer.add_entry(&[0; 3], b"x", 0);
+    writer.add_entry(&[0; 3], b"", 0);
+    let patch = writer.finish().unwrap();
+    let reader = BsdiffReader::new(&patch).unwrap();
+    assert_eq!(reader.dry_apply(6).unwrap(), 7);
+    assert_eq!(out_of_bounds_index(reader.dry_apply(5)), 1);
+}
+
+#[test]
+fn writes_past_the_new_file_size_are_caught() {
+    let patch = common::bsdf2_patch(&[(2, 1, 0), (2, 0, 0)], &[0; 4], b"x", 4);
+    let reader = BsdiffReader::new(&patch).unwrap();
+    assert_eq!(out_of_bounds_index(reader.dry_apply(8)), 1);
+}
+
+#[test]
+fn entries_running_out_of_extra_bytes_are_named() {
+    let patch = common::bsdf2_patch(&[(1, 1, 0), (1, 2, 0)], &[0; 2], b"xy", 5);
+    let reader = BsdiffReader::new(&patch).unwrap();
+    match reader.dry_apply(8) {
+        Err(BsdumpError::CorruptPatch(msg)) => {
+            assert!(msg.starts_with("control entry 1 reads extra stream bytes [1, 3)"))
+        }
+        Err(e) => panic!("unexpected error {}", e),
+        Ok(len) => panic!("dry apply produced {} bytes", len),
+    }
+}
+
+#[test]
+fn short_output_is_reported() {
+    let patch = common::bsdf2_patch(&[(2, 1, 0)], &[0; 2], b"x", 5);
+    let reader = BsdiffReader::new(&patch).unwrap();
+    match reader.dry_apply(2) {
+        Err(BsdumpError::CorruptPatch(msg)) => {
+            assert_eq!(msg, "control entries produce 3 bytes, expected 5")
+        }
+        Err(e) => panic!("unexpected error {}", e),
+        Ok(len) => panic!("dry apply produced {} bytes", len),
+    }
+}
@@ -0,0 +1,105 @@
+#![cfg(feature = "bz2")]
+
+mod common;
+
+use bsdump::bsdiff_format::{BsdiffReader, CompressorType};
+use bsdump::dump::{dump, OutputFormat};
+use bsdump::options::BsdiffReaderOptions;
+use bsdump::writer::BsdiffWriter;
+
+fn patch() -> Vec<u8> {
+    return common::write_patch(&[(&[0, 0], b"x", 4), (&[1], b"", -2)]);
+}
+
+fn render(reader: &BsdiffReader, format: OutputFormat) -> String {
+    let mut out = Vec::new();
+    dump(reader, &mut out, format).unwrap();
+    return String::from_utf8(out).unwrap();
+}
+
+#[test]
+fn text() {
+    let patch = patch();
+    let reader = BsdiffReader::new(&patch).unwrap();
+    let text = render(&reader, OutputFormat::Text);
+    let lines: Vec<&str> = text.lines().collect();
+    assert!(lines[0].starts_with("BsdiffFormat { magic: "));
+    assert_eq!(lines[1], "compressors: ctrl Bz2, diff Bz2, extra Bz2");
+    assert_eq!(
+        &lines[2..],
+        [
+            "ControlEntry { diff_size: 2, extra_size: 1, offset_increment: 4 }",
+            "ControlEntry { diff_size: 1, extra_size: 0, offset_increment: -2 }",
+        ]
+    );
+}
+
+#[test]
+fn text_notes_truncation() {
+    let patch = patch();
+    let options = BsdiffReaderOptions::new().max_control_entries(1);
+    let reader = BsdiffReader::with_options(&patch, options).unwrap();
+    let text = render(&reader, OutputFormat::Text);
+    assert!(text.ends_with("... stopped after 1 entries\n"));
+}
+
+#[test]
+fn json() {
+    let patch = patch();
+    let reader = BsdiffReader::new(&patch).unwrap();
+    let json = render(&reader, OutputFormat::Json);
+    assert!(json.contains("\"magic\": \"4253444632010101\",\n"));
+    assert!(json.contains("\"new_file_size\": 4,\n"));
+    assert!(json.contains("\"compressors\": [\"Bz2\", \"Bz2\", \"Bz2\"],\n"));
+    assert!(json.contains(concat!(
+        "  \"control_entries\": [\n",
+        "    {\"diff_size\": 2, \"extra_size\": 1, \"offset_increment\": 4, ",
+        "\"src_start\": 0, \"src_len\": 2, \"tgt_start\": 0, \"tgt_len\": 3},\n",
+        "    {\"diff_size\": 1, \"extra_size\": 0, \"offset_increment\": -2, ",
+        "\"src_start\": 6, \"src_len\": 1, \"tgt_start\": 3, \"tgt_len\": 1}\n",
+        "  ],\n",
+        "  \"truncated\": false\n}\n"
+    )));
+}
+
+#[test]
+fn json_source_start_is_signed() {
+    let mut writer = BsdiffWriter::new([CompressorType::Bz2; 3]);
+    writer.add_entry(&[0], b"", -5);
+    writer.add_entry(&[], b"x", 0);
+    let patch = writer.finish().unwrap();
+    let reader = BsdiffReader::new(&patch).unwrap();
+    let json = render(&reader, OutputFormat::Json);
+    assert!(json.contains("\"src_start\": -4, \"src_len\": 0, \"tgt_start\": 1, \"tgt_len\": 1}"));
+}
+
+#[test]
+fn csv() {
+    let patch = patch();
+    let reader = BsdiffReader::new(&patch).unwrap();
+    assert_eq!(
+        render(&reader, OutputFormat::Csv),
+        concat!(
+            "index,source_offset,target_offset,diff_size,extra_size,offset_increment\n",
+            "0,0,0,2,1,4\n",
+            "1,6,3,1,0,-2\n"
+        )
+    );
+}
+
+#[test]
+fn table() {
+    let patch = patch();
+    let reader = BsdiffReader::new(&patch).unwrap();
+    let table = render(&reader, OutputFormat::Table);
+    let lines: Vec<&str> = table.lines().collect();
+    assert!(lines[0].starts_with("BsdiffFormat { magic: "));
+    assert_eq!(
+        &lines[2..],
+        [
+            "idx  diff  extra  off  src  tgt",
+            "  0     2      1    4    0    0",
+            "  1     1      0   -2    6    3",
+        ]
+    );
+}
@@ -0,0 +1,29 @@
+#![cfg(feature = "bz2")]
+
+use bsdump::bsdiff_format::{BsdiffReader, CompressorType};
+use bsdump::writer::BsdiffWriter;
+
+#[test]
+fn runs_of_identical_entries_are_reported() {
+    let mut writer = BsdiffWriter::new([CompressorType::Bz2; 3]);
+    writer.add_entry(&[], b"", 1);
+    writer.add_entry(&[], b"", 1);
+    writer.add_entry(&[], b"", 1);
+    writer.add_entry(&[0], b"x", 0);
+    writer.add_entry(&[0, 0], b"y", 0);
+    writer.add_entry(&[0], b"", 0);
+    writer.add_entry(&[0], b"", 0);
+    let patch = writer.finish().unwrap();
+    let reader = BsdiffReader::new(&patch).unwrap();
+    assert_eq!(reader.duplicate_entry_runs(), [0..3, 5..7]);
+}
+
+#[test]
+fn distinct_entries_have_no_runs() {
+    let mut writer = BsdiffWriter::new([CompressorType::Bz2; 3]);
+    writer.add_entry(&[0], b"a", 0);
+    writer.add_entry(&[0], b"bc", 0);
+    let patch = writer.finish().unwrap();
+    let reader = BsdiffReader::new(&patch).unwrap();
+    assert!(reader.duplicate_entry_runs().is_empty());
+}
@@ -0,0 +1,74 @@
+#![cfg(feature = "bz2")]
+
+mod common;
+
+use std::path::PathBuf;
+use std::process::Command;
+
+// A scratch directory for one test's files.
+fn scratch_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("bsdump-{}-{}", name, std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    return dir;
+}
+
+#[test]
+fn dry_run_accepts_an_empty_source() {
+    let dir = scratch_dir("empty-source");
+    let source = dir.join("source");
+    let patch = dir.join("patch");
+    std::fs::write(&source, b"").unwrap();
+    // A full image patch: every byte comes from the extra stream.
+    std::fs::write(&patch, common::write_patch(&[(&[], b"new file", 0)])).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_bsdump"))
+        .arg("--apply")
+        .arg(&source)
+        .arg("--dry-run")
+        .arg("--bytes")
+        .arg(&patch)
+        .output()
+        .unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "patch applies, output size 8\n"
+    );
+}
+
+#[test]
+fn verify_accepts_an_empty_target() {
+    let dir = scratch_dir("empty-target");
+    let source = dir.join("source");
+    let patch = dir.join("patch");
+    let target = dir.join("target");
+    std::fs::write(&source, b"old file").unwrap();
+    std::fs::write(&patch, common::write_patch(&[])).unwrap();
+    std::fs::write(&target, b"").unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_bsdump"))
+        .arg("verify")
+        .arg("--source")
+        .arg(&source)
+        .arg("--patch")
+        .arg(&patch)
+        .arg("--target")
+        .arg(&target)
+        .output()
+        .unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "patch produces the expected target\n"
+    );
+}
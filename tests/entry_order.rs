@@ -0,0 +1,53 @@
+#![cfg(feature = "bz2")]
+
+mod common;
+
+use bsdump::bsdiff_format::{BsdiffReader, CompressorType};
+use bsdump::writer::BsdiffWriter;
+
+#[test]
+fn entries_come_in_file_order() {
+    let mut writer = BsdiffWriter::new([CompressorType::Bz2; 3]);
+    for i in 0..20_u8 {
+        writer.add_entry(&vec![0; i as usize], &[i], -(i as i64));
+    }
+    let patch = writer.finish().unwrap();
+    let reader = BsdiffReader::new(&patch).unwrap();
+    let sizes: Vec<_> = reader
+        .control_entries()
+        .map(|entry| (entry.diff_size, entry.offset_increment))
+        .collect();
+    let expected: Vec<_> = (0..20).map(|i| (i as u64, -i)).collect();
+    assert_eq!(sizes, expected);
+    let indices: Vec<_> = reader
+        .control_entries_with_offsets()
+        .map(|entry| entry.index)
+        .collect();
+    assert_eq!(indices, (0..20).collect::<Vec<_>>());
+}
+
+#[test]
+fn repeated_iterations_are_identical() {
+    let fixture = common::generate(31, 8192, [CompressorType::Bz2; 3]);
+    let reader = BsdiffReader::new(&fixture.patch).unwrap();
+    let first: Vec<_> = reader.control_entries().collect();
+    assert_eq!(first.len(), fixture.num_entries);
+    assert_eq!(reader.control_entries().collect::<Vec<_>>(), first);
+
+    // A half consumed iterator doesn't affect a new one.
+    let mut partial = reader.control_entries();
+    partial.nth(first.len() / 2);
+    assert!(reader.control_entries().eq(first.iter().cloned()));
+
+    // Nor do two iterators advanced in lockstep affect each other.
+    let interleaved: Vec<_> = reader
+        .control_entries()
+        .zip(reader.control_entries())
+        .collect();
+    assert!(interleaved.iter().all(|(a, b)| a == b));
+    assert_eq!(interleaved.len(), first.len());
+
+    // Streaming the entries gives the same order.
+    let streamed = BsdiffReader::stream_from(&fixture.patch[..]).unwrap();
+    assert!(streamed.map(Result::unwrap).eq(first.iter().cloned()));
+}
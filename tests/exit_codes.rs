@@ -0,0 +1,59 @@
+#![cfg(feature = "bz2")]
+
+mod common;
+
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+// A scratch directory for one test's files.
+fn scratch_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("bsdump-{}-{}", name, std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    return dir;
+}
+
+// Runs `bsdump --apply` and returns its exit code.
+fn apply(dir: &Path, patch: &[u8], output: &Path) -> Option<i32> {
+    let source = dir.join("source");
+    let patch_path = dir.join("patch");
+    std::fs::write(&source, b"old file").unwrap();
+    std::fs::write(&patch_path, patch).unwrap();
+    let status = Command::new(env!("CARGO_BIN_EXE_bsdump"))
+        .arg("--apply")
+        .arg(&source)
+        .arg("--output")
+        .arg(output)
+        .arg(&patch_path)
+        .output()
+        .unwrap()
+        .status;
+    return status.code();
+}
+
+#[test]
+fn unwritable_output_is_a_write_failure() {
+    let dir = scratch_dir("exit-unwritable");
+    let patch = common::write_patch(&[(&[0; 8], b"", 0)]);
+    let code = apply(&dir, &patch, &dir.join("missing").join("new"));
+    std::fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(code, Some(6));
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn full_disk_is_a_write_failure() {
+    let dir = scratch_dir("exit-full");
+    let patch = common::write_patch(&[(&[0; 8], b"", 0)]);
+    let code = apply(&dir, &patch, Path::new("/dev/full"));
+    std::fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(code, Some(6));
+}
+
+#[test]
+fn short_output_is_an_apply_failure() {
+    let dir = scratch_dir("exit-short");
+    let patch = common::bsdf2_patch(&[(8, 0, 0)], &[0; 8], b"", 16);
+    let code = apply(&dir, &patch, &dir.join("new"));
+    std::fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(code, Some(5));
+}
@@ -0,0 +1,49 @@
+#![cfg(feature = "bz2")]
+
+mod common;
+
+use bsdump::bsdiff_format::{BsdiffReader, CompressorType};
+use bsdump::writer::BsdiffWriter;
+
+#[test]
+fn tiny_streams_expand() {
+    let mut writer = BsdiffWriter::new([CompressorType::Bz2; 3]);
+    writer.add_entry(&[0, 1], b"x", 0);
+    let patch = writer.finish().unwrap();
+    let reader = BsdiffReader::new(&patch).unwrap();
+    let expanded = reader.expanded_streams().unwrap();
+    let names: Vec<_> = expanded.iter().map(|stream| stream.stream).collect();
+    assert_eq!(names, ["ctrl", "diff", "extra"]);
+    let extra = &expanded[2];
+    assert_eq!(extra.decompressed_len, 1);
+    assert_eq!(
+        extra.compressed_len,
+        reader.compressed_extra_stream().len() as u64
+    );
+    assert_eq!(
+        extra.to_string(),
+        format!(
+            "extra stream expanded under Bz2 ({:.2}x)",
+            extra.compressed_len as f64
+        )
+    );
+}
+
+#[test]
+fn compressible_and_empty_streams_are_not_reported() {
+    let mut writer = BsdiffWriter::new([CompressorType::Bz2; 3]);
+    writer.add_entry(&[0; 4096], b"", 0);
+    let patch = writer.finish().unwrap();
+    let reader = BsdiffReader::new(&patch).unwrap();
+    let expanded = reader.expanded_streams().unwrap();
+    // Only the single 24 byte control entry is too small to compress.
+    assert_eq!(expanded.len(), 1);
+    assert_eq!(expanded[0].stream, "ctrl");
+
+    let fixture = common::generate(17, 16384, [CompressorType::Bz2; 3]);
+    let reader = BsdiffReader::new(&fixture.patch).unwrap();
+    // The fixture inserts random bytes, so only its extra stream expands.
+    let expanded = reader.expanded_streams().unwrap();
+    let names: Vec<_> = expanded.iter().map(|stream| stream.stream).collect();
+    assert_eq!(names, ["extra"]);
+}
@@ -0,0 +1,85 @@
+#![cfg(feature = "bz2")]
+
+mod common;
+
+use bsdump::bsdiff_format::{BsdiffReader, ControlEntry};
+use bsdump::compare::{first_difference, first_divergence, Divergence};
+
+fn divergence(old: &[u8], new: &[u8]) -> Option<Divergence> {
+    let old = BsdiffReader::new(old).unwrap();
+    let new = BsdiffReader::new(new).unwrap();
+    return first_divergence(&old, &new).unwrap();
+}
+
+#[test]
+fn equivalent() {
+    let a = common::write_patch(&[(&[0, 1], b"xy", 0)]);
+    assert_eq!(divergence(&a, &a), None);
+}
+
+#[test]
+fn header_differs_first() {
+    let a = common::write_patch(&[(&[0, 1], b"xy", 0)]);
+    let b = common::write_patch(&[(&[0, 1], b"xyz", 0)]);
+    assert_eq!(
+        divergence(&a, &b),
+        Some(Divergence::Header {
+            field: "new_file_size",
+            old: 4,
+            new: 5
+        })
+    );
+}
+
+#[test]
+fn control_entry_differs() {
+    let a = common::write_patch(&[(&[0], b"x", 0), (&[0], b"", 3)]);
+    let b = common::write_patch(&[(&[0], b"x", 0), (&[0], b"", 4)]);
+    assert!(matches!(
+        divergence(&a, &b),
+        Some(Divergence::ControlEntry { index: 1, .. })
+    ));
+
+    let c = common::write_patch(&[(&[0], b"x", 0), (&[0], b"", 3), (&[], b"", 0)]);
+    assert_eq!(
+        divergence(&a, &c),
+        Some(Divergence::ControlEntry {
+            index: 2,
+            old: None,
+            new: Some(ControlEntry {
+                diff_size: 0,
+                extra_size: 0,
+                offset_increment: 0
+            })
+        })
+    );
+}
+
+#[test]
+fn stream_content_differs() {
+    let a = common::write_patch(&[(&[0, 1, 2], b"xyz", 0)]);
+    let b = common::write_patch(&[(&[0, 1, 2], b"xyw", 0)]);
+    assert_eq!(
+        divergence(&a, &b),
+        Some(Divergence::Stream {
+            name: "extra",
+            offset: 2
+        })
+    );
+    let c = common::write_patch(&[(&[0, 7, 2], b"xyz", 0)]);
+    assert_eq!(
+        divergence(&a, &c),
+        Some(Divergence::Stream {
+            name: "diff",
+            offset: 1
+        })
+    );
+}
+
+#[test]
+fn first_difference_of_buffers() {
+    assert_eq!(first_difference(b"abc", b"abc"), None);
+    assert_eq!(first_difference(b"abc", b"abd"), Some(2));
+    assert_eq!(first_difference(b"ab", b"abc"), Some(2));
+    assert_eq!(first_difference(b"", b""), None);
+}
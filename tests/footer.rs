@@ -0,0 +1,45 @@
+#![cfg(feature = "bz2")]
+
+mod common;
+
+use bsdump::bsdiff_format::BsdiffReader;
+use bsdump::error::BsdumpError;
+use bsdump::options::BsdiffReaderOptions;
+
+fn patch() -> Vec<u8> {
+    return common::write_patch(&[(&[0, 1], b"xyz", 0)]);
+}
+
+#[test]
+fn footer_is_not_part_of_the_extra_stream() {
+    let plain = patch();
+    let mut patch = plain.clone();
+    patch.extend_from_slice(b"\xde\xad\xbe\xef");
+    let options = BsdiffReaderOptions::new().footer_len(4);
+    let reader = BsdiffReader::with_options(&patch, options).unwrap();
+    assert_eq!(reader.footer(), b"\xde\xad\xbe\xef");
+    let expected = BsdiffReader::new(&plain).unwrap();
+    assert_eq!(
+        reader.compressed_extra_stream(),
+        expected.compressed_extra_stream()
+    );
+    assert_eq!(reader.apply(b"ab").unwrap(), b"acxyz");
+}
+
+#[test]
+fn no_footer_by_default() {
+    let patch = patch();
+    assert!(BsdiffReader::new(&patch).unwrap().footer().is_empty());
+}
+
+#[test]
+fn footer_must_fit_after_the_diff_stream() {
+    let patch = patch();
+    let reader = BsdiffReader::new(&patch).unwrap();
+    let footer_len = reader.compressed_extra_stream().len() + 1;
+    let options = BsdiffReaderOptions::new().footer_len(footer_len);
+    assert!(matches!(
+        BsdiffReader::with_options(&patch, options),
+        Err(BsdumpError::CorruptPatch(_))
+    ));
+}
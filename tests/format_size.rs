@@ -0,0 +1,18 @@
+use bsdump::format::format_size;
+
+#[test]
+fn raw_bytes_are_exact() {
+    assert_eq!(format_size(0, false), "0");
+    assert_eq!(format_size(1536, false), "1536");
+    assert_eq!(format_size(u64::MAX, false), "18446744073709551615");
+}
+
+#[test]
+fn human_sizes_use_binary_units() {
+    assert_eq!(format_size(1023, true), "1023 B");
+    assert_eq!(format_size(1024, true), "1.0 KiB");
+    assert_eq!(format_size(1536, true), "1.5 KiB");
+    assert_eq!(format_size(5 << 20, true), "5.0 MiB");
+    assert_eq!(format_size(3 << 30, true), "3.0 GiB");
+    assert_eq!(format_size(2048 << 40, true), "2048.0 TiB");
+}
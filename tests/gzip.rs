@@ -0,0 +1,42 @@
+#![cfg(all(feature = "gzip", feature = "bz2"))]
+
+mod common;
+
+use bsdump::bsdiff_format::{BsdiffReader, CompressorType};
+
+const GZIP_CTRL: [CompressorType; 3] = [
+    CompressorType::Gzip,
+    CompressorType::Bz2,
+    CompressorType::Bz2,
+];
+
+#[test]
+fn gzip_ctrl_with_bz2_diff() {
+    let fixture = common::generate(42, 4096, GZIP_CTRL);
+    assert_eq!(&fixture.patch[..8], b"BSDF2\x04\x01\x01");
+    let reader = BsdiffReader::new(&fixture.patch).unwrap();
+    assert_eq!(reader.header.compressors().unwrap(), GZIP_CTRL);
+    assert_eq!(reader.num_control_entries(), fixture.num_entries);
+    assert_eq!(reader.apply(&fixture.source).unwrap(), fixture.target);
+}
+
+#[test]
+fn gzip_extra_stream_length_is_found() {
+    let compressors = [
+        CompressorType::Bz2,
+        CompressorType::Bz2,
+        CompressorType::Gzip,
+    ];
+    let first = common::generate(1, 1024, compressors);
+    let second = common::generate(2, 2048, compressors);
+    let mut concatenated = first.patch.clone();
+    concatenated.extend_from_slice(&second.patch);
+
+    assert_eq!(
+        BsdiffReader::patch_len(&concatenated).unwrap(),
+        first.patch.len()
+    );
+    let readers = BsdiffReader::parse_all(&concatenated).unwrap();
+    assert_eq!(readers.len(), 2);
+    assert_eq!(readers[1].apply(&second.source).unwrap(), second.target);
+}
@@ -0,0 +1,133 @@
+use bsdump::bsdiff_format::{is_header_at, BsdiffFormat, BsdiffReader};
+use bsdump::error::BsdumpError;
+
+fn header(magic: &[u8; 8]) -> Vec<u8> {
+    let mut patch = magic.to_vec();
+    patch.extend_from_slice(&[0; 24]);
+    return patch;
+}
+
+#[test]
+fn unknown_magic() {
+    for magic in [b"NOTAPTCH", b"CSDF2\x01\x01\x01", b"BSDIFF41"] {
+        match BsdiffReader::new(&header(magic)) {
+            Err(BsdumpError::NotABsdiffPatch { magic: found }) => assert_eq!(&found, magic),
+            other => panic!("unexpected result for {:?}: {:?}", magic, other.err()),
+        }
+    }
+}
+
+#[test]
+fn unknown_magic_in_a_short_file() {
+    assert!(matches!(
+        BsdiffReader::new(b"PK\x03\x04\x14\x00\x00\x00"),
+        Err(BsdumpError::NotABsdiffPatch { .. })
+    ));
+}
+
+#[test]
+fn invalid_compressor_byte() {
+    let cases: [(&[u8; 8], usize, u8); 3] = [
+        (b"BSDF2\x00\x01\x01", 5, 0),
+        (b"BSDF2\x01\x01\x09", 7, 9),
+        (b"BDF3\x00\x01\x05\x01", 6, 5),
+    ];
+    for (magic, expected_index, expected_value) in cases {
+        match BsdiffReader::new(&header(magic)) {
+            Err(BsdumpError::InvalidCompressorType { index, value, .. }) => {
+                assert_eq!((index, value), (expected_index, expected_value));
+            }
+            other => panic!("unexpected result for {:?}: {:?}", magic, other.err()),
+        }
+    }
+}
+
+#[test]
+fn near_miss_magics() {
+    let not_patches: [&[u8; 8]; 5] = [
+        b"BSDF3\x01\x01\x01",
+        b"bSDF2\x01\x01\x01",
+        b"BSDF\x002\x01\x01",
+        b"BDF2\x00\x01\x01\x01",
+        b"BSDIFF4\x00",
+    ];
+    for magic in not_patches {
+        match BsdiffReader::new(&header(magic)) {
+            Err(BsdumpError::NotABsdiffPatch { magic: found }) => assert_eq!(&found, magic),
+            other => panic!("unexpected result for {:?}: {:?}", magic, other.err()),
+        }
+    }
+}
+
+#[test]
+fn bdf3_reserved_byte_must_be_zero() {
+    for value in [1_u8, b'3', 0xff] {
+        let mut magic = *b"BDF3\x00\x01\x01\x01";
+        magic[4] = value;
+        match BsdiffReader::new(&header(&magic)) {
+            Err(e @ BsdumpError::ReservedMagicByte { .. }) => {
+                assert!(matches!(
+                    e,
+                    BsdumpError::ReservedMagicByte { index: 4, value: v, .. } if v == value
+                ));
+                assert!(e.to_string().contains("reserved magic byte 4"));
+            }
+            other => panic!("unexpected result for {:?}: {:?}", magic, other.err()),
+        }
+        assert!(!is_header_at(&magic, 0));
+    }
+    assert!(is_header_at(b"BDF3\x00\x01\x01\x01", 0));
+}
+
+#[test]
+fn header_probe() {
+    let mut blob = b"junk".to_vec();
+    blob.extend_from_slice(b"BSDF2\x01\x02\x03");
+    blob.extend_from_slice(b"BDF3\xff\x01\x01\x05");
+    blob.extend_from_slice(b"BSDIFF40");
+    let hits: Vec<usize> = (0..blob.len() + 1)
+        .filter(|offset| is_header_at(&blob, *offset))
+        .collect();
+    // The BDF3 magic has a non-zero reserved byte and an invalid extra
+    // compressor byte.
+    assert_eq!(hits, [4, 20]);
+    assert!(!is_header_at(b"BSDF2\x01\x01", 0));
+}
+
+#[test]
+fn unchecked_header_keeps_a_bad_magic() {
+    let mut patch = header(b"BSDF2\x01\x07\x01");
+    patch[8..16].copy_from_slice(&123_u64.to_le_bytes());
+    let header = BsdiffFormat::parse_unchecked(&patch).unwrap();
+    assert_eq!(header.magic.to_be_bytes(), *b"BSDF2\x01\x07\x01");
+    assert_eq!(header.compressed_ctrl_size, 123);
+    assert!(matches!(
+        header.validate_magic(),
+        Err(BsdumpError::InvalidCompressorType { index: 6, .. })
+    ));
+    assert!(matches!(
+        header.compressors(),
+        Err(BsdumpError::InvalidCompressorType {
+            index: 6,
+            value: 7,
+            ..
+        })
+    ));
+    assert!(BsdiffFormat::parse_unchecked(&patch[..31]).is_err());
+}
+
+#[test]
+fn headers_work_as_map_keys() {
+    let mut patch = header(b"BSDF2\x01\x01\x01");
+    let first = BsdiffFormat::parse_unchecked(&patch).unwrap();
+    patch[24..32].copy_from_slice(&7_u64.to_le_bytes());
+    let second = BsdiffFormat::parse_unchecked(&patch).unwrap();
+    assert_ne!(first, second);
+
+    let mut seen = std::collections::HashMap::new();
+    for header in [first, second, first] {
+        *seen.entry(header).or_insert(0) += 1;
+    }
+    assert_eq!(seen[&first], 2);
+    assert_eq!(seen[&second], 1);
+}
@@ -0,0 +1,24 @@
+use bsdump::bsdiff_format::BsdiffReader;
+use bsdump::error::BsdumpError;
+
+#[test]
+fn valid_magic_with_garbage_sizes_is_implausible() {
+    let mut patch = b"BSDF2\x02\x02\x02".to_vec();
+    patch.extend_from_slice(&0x4141_4141_4141_4141_u64.to_le_bytes());
+    patch.extend_from_slice(&0x4242_4242_u64.to_le_bytes());
+    patch.extend_from_slice(&100_u64.to_le_bytes());
+    patch.extend_from_slice(&[0x55; 64]);
+    match BsdiffReader::new(&patch) {
+        Err(BsdumpError::ImplausibleHeader {
+            field,
+            value,
+            file_len,
+        }) => {
+            assert_eq!(field, "compressed_ctrl_size");
+            assert_eq!(value, 0x4141_4141_4141_4141);
+            assert_eq!(file_len, patch.len() as u64);
+        }
+        Err(e) => panic!("unexpected error {}", e),
+        Ok(_) => panic!("garbage header was accepted"),
+    }
+}
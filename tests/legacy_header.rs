@@ -0,0 +1,105 @@
+#![cfg(feature = "bz2")]
+
+use std::io::Write;
+
+use bsdump::bsdiff_format::BsdiffReader;
+use bsdump::error::BsdumpError;
+
+fn bz2(data: &[u8]) -> Vec<u8> {
+    let mut encoder = bzip2::write::BzEncoder::new(Vec::new(), bzip2::Compression::best());
+    encoder.write_all(data).unwrap();
+    return encoder.finish().unwrap();
+}
+
+// Encodes like upstream bsdiff's offtout().
+fn offtout(x: i64) -> [u8; 8] {
+    let raw = if x < 0 {
+        x.unsigned_abs() | 1 << 63
+    } else {
+        x as u64
+    };
+    return raw.to_le_bytes();
+}
+
+// Builds a classic BSDIFF40 patch from raw header length fields.
+fn legacy_patch(ctrl: &[u8], diff: &[u8], extra: &[u8], new_file_size: [u8; 8]) -> Vec<u8> {
+    let (ctrl, diff, extra) = (bz2(ctrl), bz2(diff), bz2(extra));
+    let mut patch = b"BSDIFF40".to_vec();
+    patch.extend_from_slice(&offtout(ctrl.len() as i64));
+    patch.extend_from_slice(&offtout(diff.len() as i64));
+    patch.extend_from_slice(&new_file_size);
+    patch.extend(ctrl);
+    patch.extend(diff);
+    patch.extend(extra);
+    return patch;
+}
+
+#[test]
+fn offtout_vectors() {
+    // Values from upstream bsdiff's offtin()/offtout().
+    assert_eq!(offtout(0), [0, 0, 0, 0, 0, 0, 0, 0]);
+    assert_eq!(offtout(1), [1, 0, 0, 0, 0, 0, 0, 0]);
+    assert_eq!(offtout(-1), [1, 0, 0, 0, 0, 0, 0, 0x80]);
+    assert_eq!(offtout(0x1234), [0x34, 0x12, 0, 0, 0, 0, 0, 0]);
+}
+
+#[test]
+fn legacy_patch_applies() {
+    let mut ctrl = Vec::new();
+    ctrl.extend_from_slice(&offtout(4));
+    ctrl.extend_from_slice(&offtout(2));
+    ctrl.extend_from_slice(&offtout(-2));
+    let patch = legacy_patch(&ctrl, &[1, 1, 1, 1], b"xy", offtout(6));
+    let reader = BsdiffReader::new(&patch).unwrap();
+    assert_eq!(reader.get_new_file_size(), 6);
+    assert_eq!(reader.apply(b"abcd").unwrap(), b"bcdexy");
+}
+
+#[test]
+fn legacy_negative_zero_length_is_zero() {
+    let patch = legacy_patch(&[], &[], &[], [0, 0, 0, 0, 0, 0, 0, 0x80]);
+    let reader = BsdiffReader::new(&patch).unwrap();
+    assert_eq!(reader.get_new_file_size(), 0);
+    assert_eq!(reader.apply(b"").unwrap(), b"");
+}
+
+#[test]
+fn legacy_negative_length_is_rejected() {
+    let patch = legacy_patch(&[], &[], &[], offtout(-6));
+    match BsdiffReader::new(&patch) {
+        Err(BsdumpError::CorruptPatch(_)) => {}
+        Err(e) => panic!("unexpected error {}", e),
+        Ok(_) => panic!("negative new_file_size was accepted"),
+    }
+}
+
+#[test]
+fn legacy_entry_sizes_use_the_sign_bit() {
+    // A diff size of -0 is zero to bspatch, not 2^63.
+    let mut ctrl = Vec::new();
+    ctrl.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0, 0x80]);
+    ctrl.extend_from_slice(&offtout(2));
+    ctrl.extend_from_slice(&offtout(0));
+    let patch = legacy_patch(&ctrl, &[], b"xy", offtout(2));
+    let reader = BsdiffReader::new(&patch).unwrap();
+    let entry = reader.control_entries().next().unwrap();
+    assert_eq!((entry.diff_size, entry.extra_size), (0, 2));
+    assert_eq!(reader.apply(b"").unwrap(), b"xy");
+
+    let mut stream = BsdiffReader::stream_from(&patch[..]).unwrap();
+    assert_eq!(stream.next().unwrap().unwrap(), entry);
+}
+
+#[test]
+fn legacy_negative_entry_size_is_rejected() {
+    let mut ctrl = Vec::new();
+    ctrl.extend_from_slice(&offtout(0));
+    ctrl.extend_from_slice(&offtout(-2));
+    ctrl.extend_from_slice(&offtout(0));
+    let patch = legacy_patch(&ctrl, &[], b"xy", offtout(2));
+    match BsdiffReader::new(&patch) {
+        Err(BsdumpError::CorruptPatch(msg)) => assert!(msg.contains("entry 0"), "{}", msg),
+        Err(e) => panic!("unexpected error {}", e),
+        Ok(_) => panic!("negative extra_size was accepted"),
+    }
+}
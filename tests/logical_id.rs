@@ -0,0 +1,49 @@
+#![cfg(all(feature = "bz2", feature = "brotli"))]
+
+mod common;
+
+use bsdump::bsdiff_format::{BsdiffReader, CompressorType};
+
+#[test]
+fn recompressed_copies_share_an_id() {
+    let bz2 = common::generate(12, 4096, [CompressorType::Bz2; 3]);
+    let brotli = common::generate(12, 4096, [CompressorType::Brotli; 3]);
+    assert_ne!(bz2.patch, brotli.patch);
+    let id = BsdiffReader::new(&bz2.patch).unwrap().logical_id().unwrap();
+    assert_eq!(
+        BsdiffReader::new(&brotli.patch)
+            .unwrap()
+            .logical_id()
+            .unwrap(),
+        id
+    );
+    let legacy = BsdiffReader::new(&bz2.patch)
+        .unwrap()
+        .to_bsdiff40()
+        .unwrap();
+    assert_eq!(
+        BsdiffReader::new(&legacy).unwrap().logical_id().unwrap(),
+        id
+    );
+}
+
+#[test]
+fn different_deltas_have_different_ids() {
+    let a = common::generate(12, 4096, [CompressorType::Bz2; 3]);
+    let b = common::generate(14, 4096, [CompressorType::Bz2; 3]);
+    assert_ne!(
+        BsdiffReader::new(&a.patch).unwrap().logical_id().unwrap(),
+        BsdiffReader::new(&b.patch).unwrap().logical_id().unwrap()
+    );
+}
+
+#[test]
+fn id_is_stable() {
+    // FNV-1a 128 of the v2 layout for these two entries, computed
+    // independently of this crate.
+    let patch = common::write_patch(&[(&[0, 1], b"xyz", 3), (&[2], b"", -1)]);
+    assert_eq!(
+        BsdiffReader::new(&patch).unwrap().logical_id().unwrap(),
+        0x58ffab9b10116ac8a593b51efbc6b751
+    );
+}
@@ -0,0 +1,62 @@
+#![cfg(feature = "bz2")]
+
+mod common;
+
+use bsdump::bsdiff_format::{BsdiffReader, CompressorType};
+use bsdump::error::BsdumpError;
+
+// The fixture patch with `extension` inserted after its 8 byte magic.
+fn with_long_magic(patch: &[u8], extension: &[u8]) -> Vec<u8> {
+    let mut long = patch[..8].to_vec();
+    long.extend_from_slice(extension);
+    long.extend_from_slice(&patch[8..]);
+    return long;
+}
+
+#[test]
+fn sixteen_byte_magic_parses_with_magic_len() {
+    let fixture = common::generate(9, 2048, [CompressorType::Bz2; 3]);
+    let patch = with_long_magic(&fixture.patch, b"\x02\x00\x00\x00\x01\x00\x00\x00");
+
+    assert!(BsdiffReader::new(&patch).is_err());
+    let reader = BsdiffReader::builder().magic_len(16).read(&patch).unwrap();
+    let plain = BsdiffReader::new(&fixture.patch).unwrap();
+    assert_eq!(reader.header, plain.header);
+    assert_eq!(
+        reader.magic_extension(),
+        b"\x02\x00\x00\x00\x01\x00\x00\x00"
+    );
+    assert!(plain.magic_extension().is_empty());
+
+    let ranges = reader.stream_ranges(patch.len() as u64);
+    let plain_ranges = plain.stream_ranges(fixture.patch.len() as u64);
+    assert_eq!(ranges.ctrl.start, plain_ranges.ctrl.start + 8);
+    assert_eq!(ranges.extra.end, plain_ranges.extra.end + 8);
+    assert_eq!(
+        reader.compressed_ctrl_stream(),
+        plain.compressed_ctrl_stream()
+    );
+    assert_eq!(reader.apply(&fixture.source).unwrap(), fixture.target);
+}
+
+#[test]
+fn long_magic_still_checks_the_standard_part() {
+    let fixture = common::generate(9, 512, [CompressorType::Bz2; 3]);
+    let mut patch = with_long_magic(&fixture.patch, &[0xff; 8]);
+    patch[7] = 9;
+    match BsdiffReader::builder().magic_len(16).read(&patch) {
+        Err(BsdumpError::InvalidCompressorType { index: 7, .. }) => {}
+        Err(e) => panic!("unexpected error {}", e),
+        Ok(_) => panic!("invalid compressor byte was accepted"),
+    }
+}
+
+#[test]
+fn magic_len_of_eight_is_the_default() {
+    let fixture = common::generate(4, 512, [CompressorType::Bz2; 3]);
+    let reader = BsdiffReader::builder()
+        .magic_len(8)
+        .read(&fixture.patch)
+        .unwrap();
+    assert_eq!(reader.apply(&fixture.source).unwrap(), fixture.target);
+}
@@ -0,0 +1,49 @@
+#![cfg(all(feature = "bz2", feature = "brotli"))]
+
+mod common;
+
+use bsdump::bsdiff_format::{BsdiffReader, CompressorType, MaskEntry};
+use bsdump::compression::compress;
+
+// A BDF3 patch with one entry producing 16 bytes from the diff stream
+// `0 0 1 2 3 0 0 0 0 0 4 0 0 0 0 0`, of which only the non-zero bytes are
+// stored.
+fn bdf3_patch() -> Vec<u8> {
+    let ctrl = compress(&common::ctrl_stream(&[(16, 0, 0)]), CompressorType::Bz2).unwrap();
+    let diff = compress(&[1, 2, 3, 4], CompressorType::Bz2).unwrap();
+    let extra = compress(&[], CompressorType::Bz2).unwrap();
+    let mask = compress(&[0b0001_1100, 0b0000_0100], CompressorType::Brotli).unwrap();
+    let mut patch = b"BDF3\x00\x01\x01\x01".to_vec();
+    patch.extend_from_slice(&(ctrl.len() as u64).to_le_bytes());
+    patch.extend_from_slice(&(diff.len() as u64).to_le_bytes());
+    patch.extend_from_slice(&16_u64.to_le_bytes());
+    patch.extend_from_slice(&(mask.len() as u64).to_le_bytes());
+    patch.extend(ctrl);
+    patch.extend(diff);
+    patch.extend(extra);
+    patch.extend(mask);
+    return patch;
+}
+
+#[test]
+fn mask_runs_and_masked_bytes() {
+    let patch = bdf3_patch();
+    let reader = BsdiffReader::new(&patch).unwrap();
+    assert!(reader.mask_entries().eq([
+        MaskEntry { offset: 2, len: 3 },
+        MaskEntry { offset: 10, len: 1 },
+    ]));
+    assert_eq!(reader.masked_bytes(), 12);
+    assert_eq!(
+        reader.apply(&[0; 16]).unwrap(),
+        [0, 0, 1, 2, 3, 0, 0, 0, 0, 0, 4, 0, 0, 0, 0, 0]
+    );
+}
+
+#[test]
+fn no_mask_strips_nothing() {
+    let fixture = common::generate(3, 512, [CompressorType::Bz2; 3]);
+    let reader = BsdiffReader::new(&fixture.patch).unwrap();
+    assert_eq!(reader.mask_entries().count(), 0);
+    assert_eq!(reader.masked_bytes(), 0);
+}
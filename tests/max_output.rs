@@ -0,0 +1,51 @@
+#![cfg(feature = "bz2")]
+
+mod common;
+
+use bsdump::bsdiff_format::{BsdiffReader, CompressorType};
+use bsdump::error::BsdumpError;
+use bsdump::writer::BsdiffWriter;
+
+fn assert_too_large<T>(result: Result<T, BsdumpError>) {
+    match result {
+        Err(BsdumpError::OutputTooLarge { size, max }) => assert_eq!((size, max), (5, 4)),
+        Err(e) => panic!("unexpected error {}", e),
+        Ok(_) => panic!("output over the limit was produced"),
+    }
+}
+
+#[test]
+fn every_apply_respects_max_output() {
+    let mut writer = BsdiffWriter::new([CompressorType::Bz2; 3]);
+    writer.add_entry(&[0, 0], b"xyz", 0);
+    let patch = writer.finish().unwrap();
+
+    let reader = BsdiffReader::builder().max_output(4).read(&patch).unwrap();
+    assert_too_large(reader.apply(b"ab"));
+    assert_too_large(reader.apply_to(b"ab", &mut Vec::new()));
+    assert_too_large(reader.apply_from_source(std::io::Cursor::new(b"ab"), &mut Vec::new()));
+    assert_too_large(reader.apply_in_place(&mut b"ab".to_vec()));
+    assert_too_large(reader.into_apply(b"ab"));
+
+    let reader = BsdiffReader::builder().max_output(5).read(&patch).unwrap();
+    assert_eq!(reader.apply(b"ab").unwrap(), b"abxyz");
+}
+
+#[test]
+fn header_size_is_not_trusted_without_max_output() {
+    // Claims an exabyte of new file that the empty extra stream can't back.
+    let size = 1 << 60;
+    let patch = common::bsdf2_patch(&[(0, size, 0)], b"", b"", size);
+    let reader = BsdiffReader::new(&patch).unwrap();
+    let assert_short = |result: Result<Vec<u8>, BsdumpError>| match result {
+        Err(BsdumpError::ExtraLengthMismatch { expected, found }) => {
+            assert_eq!((expected, found), (size, 0))
+        }
+        Err(e) => panic!("unexpected error {}", e),
+        Ok(_) => panic!("unbacked output was produced"),
+    };
+    assert_short(reader.apply(b""));
+    #[cfg(feature = "crc32fast")]
+    assert_short(reader.apply_with_crc32(b"").map(|(out, _)| out));
+    assert_short(reader.into_apply(b""));
+}
@@ -0,0 +1,31 @@
+#![cfg(feature = "bz2")]
+
+mod common;
+
+use bsdump::bsdiff_format::{BsdiffReader, CompressorType};
+use bsdump::compression::compress;
+
+#[test]
+fn estimate_picks_the_best_codec_per_stream() {
+    let fixture = common::generate(21, 8192, [CompressorType::Bz2; 3]);
+    let reader = BsdiffReader::new(&fixture.patch).unwrap();
+    let streams = [
+        reader.decompressed_ctrl_stream().to_vec(),
+        reader.decompressed_diff_stream().unwrap(),
+        reader.decompressed_extra_stream().unwrap(),
+    ];
+    let mut expected = 32;
+    for stream in &streams {
+        expected += CompressorType::ALL
+            .iter()
+            .filter(|compressor_type| compressor_type.is_compiled())
+            .map(|compressor_type| compress(stream, *compressor_type).unwrap().len() as u64)
+            .min()
+            .unwrap();
+    }
+    let estimate = reader.minimum_size_estimate().unwrap();
+    assert_eq!(estimate, expected);
+    // Recompressing with bzip2 alone reproduces the patch, so the best
+    // codec can't do worse.
+    assert!(estimate <= fixture.patch.len() as u64);
+}
@@ -0,0 +1,44 @@
+#![cfg(feature = "bz2")]
+
+use bsdump::bsdiff_format::{BsdiffReader, CompressorType};
+use bsdump::writer::BsdiffWriter;
+
+#[test]
+fn new_data_fraction_counts_extra_bytes() {
+    let mut writer = BsdiffWriter::new([CompressorType::Bz2; 3]);
+    writer.add_entry(&[0, 0, 0], b"x", 0);
+    writer.add_entry(&[], b"yz", -3);
+    writer.add_entry(&[1, 1], b"", 0);
+    let patch = writer.finish().unwrap();
+    let reader = BsdiffReader::new(&patch).unwrap();
+    // Three of the eight new bytes come from the extra stream.
+    assert_eq!(reader.new_data_fraction(), 0.375);
+    assert_eq!(reader.apply(b"abc").unwrap(), b"abcxyzbc");
+}
+
+#[test]
+fn empty_new_file_has_no_new_data() {
+    let patch = BsdiffWriter::new([CompressorType::Bz2; 3])
+        .finish()
+        .unwrap();
+    let reader = BsdiffReader::new(&patch).unwrap();
+    assert_eq!(reader.new_data_fraction(), 0.0);
+}
+
+#[test]
+fn all_extra_patch_is_a_full_image() {
+    let mut writer = BsdiffWriter::new([CompressorType::Bz2; 3]);
+    writer.add_entry(&[], b"new", 0);
+    writer.add_entry(&[], b"data", 7);
+    let patch = writer.finish().unwrap();
+    let reader = BsdiffReader::new(&patch).unwrap();
+    assert!(reader.is_full_image());
+    assert_eq!(reader.new_data_fraction(), 1.0);
+    assert_eq!(reader.apply(b"").unwrap(), b"newdata");
+
+    let mut writer = BsdiffWriter::new([CompressorType::Bz2; 3]);
+    writer.add_entry(&[], b"new", 0);
+    writer.add_entry(&[0], b"", 0);
+    let patch = writer.finish().unwrap();
+    assert!(!BsdiffReader::new(&patch).unwrap().is_full_image());
+}
@@ -0,0 +1,173 @@
+#![cfg(feature = "bz2")]
+
+mod common;
+
+use std::io::Cursor;
+
+use bsdump::bsdiff_format::BsdiffReader;
+use bsdump::dot::write_dot;
+use bsdump::dump::{dump, OutputFormat};
+use bsdump::error::BsdumpError;
+use bsdump::report::PatchReport;
+
+// Seeks and sizes whose running sums overflow both the source and the
+// target offset.
+fn patch() -> Vec<u8> {
+    return common::bsdf2_patch(
+        &[
+            (0, 0, 1 << 62),
+            (0, 0, 1 << 62),
+            (1 << 63, 1 << 63, 0),
+            (0, 1 << 63, 0),
+        ],
+        &[],
+        &[],
+        0,
+    );
+}
+
+#[test]
+fn offsets_saturate() {
+    let patch = patch();
+    let reader = BsdiffReader::new(&patch).unwrap();
+    let offsets: Vec<(i64, u64)> = reader
+        .control_entries_with_offsets()
+        .map(|entry| (entry.source_offset, entry.target_offset))
+        .collect();
+    assert_eq!(
+        offsets,
+        [(0, 0), (1 << 62, 0), (i64::MAX, 0), (i64::MAX, u64::MAX)]
+    );
+}
+
+#[test]
+fn every_dump_format_renders() {
+    let patch = patch();
+    let reader = BsdiffReader::new(&patch).unwrap();
+    for format in [
+        OutputFormat::Text,
+        OutputFormat::Json,
+        OutputFormat::Csv,
+        OutputFormat::Table,
+    ] {
+        let mut out = Vec::new();
+        dump(&reader, &mut out, format).unwrap();
+        assert!(!out.is_empty());
+    }
+}
+
+#[test]
+fn stats_render() {
+    let patch = patch();
+    let reader = BsdiffReader::new(&patch).unwrap();
+    let stats = reader.entry_size_stats();
+    assert_eq!(stats.max_extra, 1 << 63);
+    assert_eq!(stats.avg_extra, (1_u64 << 63) as f64 / 2.0);
+    let mut out = Vec::new();
+    PatchReport::new(&reader)
+        .write_stats_json(&mut out)
+        .unwrap();
+    assert!(!out.is_empty());
+}
+
+#[test]
+fn overflowing_write_is_out_of_bounds() {
+    let patch = patch();
+    let reader = BsdiffReader::new(&patch).unwrap();
+    match reader.validate_against_source(u64::MAX) {
+        Err(e @ BsdumpError::EntryOutOfBounds { .. }) => {
+            assert!(e.to_string().starts_with("control entry 2 reads source ["));
+        }
+        Err(e) => panic!("unexpected error {}", e),
+        Ok(()) => panic!("overflowing target write was accepted"),
+    }
+}
+
+fn assert_read_past_i64(result: Result<impl Sized, BsdumpError>) {
+    match result {
+        Err(BsdumpError::EntryOutOfBounds { entry, .. }) => {
+            assert_eq!((entry.index, entry.source_offset), (1, i64::MAX));
+        }
+        Err(e) => panic!("unexpected error {}", e),
+        Ok(_) => panic!("read past i64 was applied"),
+    }
+}
+
+#[test]
+fn apply_rejects_a_read_past_i64() {
+    let patch = common::bsdf2_patch(&[(0, 0, i64::MAX), (1, 0, 0)], &[0], &[], 1);
+    let reader = BsdiffReader::new(&patch).unwrap();
+    assert_read_past_i64(reader.apply(b"a"));
+    let mut out = Vec::new();
+    assert_read_past_i64(reader.apply_from_source(Cursor::new(b"a"), &mut out));
+    assert_read_past_i64(reader.into_apply(b"a"));
+}
+
+#[test]
+fn source_range_is_capped() {
+    let patch = common::bsdf2_patch(&[(0, 0, i64::MAX), (1, 0, 0)], &[0], &[], 1);
+    let reader = BsdiffReader::new(&patch).unwrap();
+    let ranges: Vec<_> = reader
+        .control_entries_with_offsets()
+        .map(|entry| entry.source_range())
+        .collect();
+    let end = i64::MAX as u64;
+    assert_eq!(ranges, [0..0, end..end]);
+}
+
+#[test]
+fn dot_labels_saturate() {
+    let patch = patch();
+    let reader = BsdiffReader::new(&patch).unwrap();
+    let mut out = Vec::new();
+    write_dot(&reader, &mut out).unwrap();
+    let dot = String::from_utf8(out).unwrap();
+    assert!(dot.contains(&format!("t3 [label=\"#3 [{}, {})", u64::MAX, u64::MAX)));
+    assert!(dot.contains(&format!("s2 [label=\"[{}, {})\"]", i64::MAX, i64::MAX)));
+}
+
+#[test]
+fn seek_to_target_past_u64() {
+    let patch = patch();
+    let reader = BsdiffReader::new(&patch).unwrap();
+    let mut entries = reader.control_entries();
+    assert_eq!(entries.seek_to_target(1 << 63), Some(0));
+    assert_eq!(entries.next().unwrap().extra_size, 1 << 63);
+}
+
+#[test]
+fn diff_offsets_saturate() {
+    let patch = common::bsdf2_patch(&[(1 << 63, 0, 0), (1 << 63, 0, 0)], &[], &[], 0);
+    let reader = BsdiffReader::new(&patch).unwrap();
+    assert_eq!(reader.diff_offset_for_entry(1), 1 << 63);
+    assert_eq!(reader.diff_offset_for_entry(2), u64::MAX);
+}
+
+#[test]
+fn new_data_fraction_saturates() {
+    let patch = common::bsdf2_patch(&[(0, 1 << 63, 0), (0, 1 << 63, 0)], &[], &[], 1);
+    let reader = BsdiffReader::new(&patch).unwrap();
+    assert_eq!(reader.new_data_fraction(), u64::MAX as f64);
+}
+
+#[test]
+fn source_fragmentation_saturates() {
+    // Three seeks of i64::MAX bytes each, and reads adding up to 2^64 + 2.
+    let patch = common::bsdf2_patch(
+        &[
+            (0, 0, -i64::MAX),
+            (1, 0, i64::MAX),
+            (1, 0, -i64::MAX),
+            (1 << 63, 0, 0),
+            (1 << 63, 0, 0),
+        ],
+        &[],
+        &[],
+        0,
+    );
+    let reader = BsdiffReader::new(&patch).unwrap();
+    let stats = reader.source_fragmentation();
+    assert_eq!((stats.num_reads, stats.num_seeks), (4, 3));
+    assert_eq!(stats.seek_distance_sum, u64::MAX);
+    assert_eq!(stats.avg_read_len, ((1_u128 << 64) + 2) as f64 / 4.0);
+}
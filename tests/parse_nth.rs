@@ -0,0 +1,47 @@
+#![cfg(feature = "bz2")]
+
+mod common;
+
+use bsdump::bsdiff_format::{BsdiffReader, CompressorType};
+use bsdump::error::BsdumpError;
+
+const BZ2: [CompressorType; 3] = [CompressorType::Bz2; 3];
+
+#[test]
+fn nth_patch_is_parsed_alone() {
+    let fixtures: Vec<_> = (0..3)
+        .map(|seed| common::generate(seed, 1024 * (seed as usize + 1), BZ2))
+        .collect();
+    let mut concatenated = Vec::new();
+    for fixture in &fixtures {
+        concatenated.extend_from_slice(&fixture.patch);
+    }
+    // Break the ctrl stream of the first patch. Skipping it only needs the
+    // header and the extra stream, so the others still parse.
+    concatenated[40] ^= 0xff;
+    assert!(BsdiffReader::parse_all(&concatenated).is_err());
+
+    for (n, fixture) in fixtures.iter().enumerate().skip(1) {
+        let reader = BsdiffReader::parse_nth(&concatenated, n).unwrap();
+        assert_eq!(reader.apply(&fixture.source).unwrap(), fixture.target);
+    }
+    let last = BsdiffReader::parse_nth(&concatenated, 2).unwrap();
+    assert_eq!(
+        last.base_offset(),
+        (fixtures[0].patch.len() + fixtures[1].patch.len()) as u64
+    );
+}
+
+#[test]
+fn index_past_the_last_patch_is_reported() {
+    let fixture = common::generate(4, 512, BZ2);
+    let mut concatenated = fixture.patch.clone();
+    concatenated.extend_from_slice(&fixture.patch);
+    match BsdiffReader::parse_nth(&concatenated, 2) {
+        Err(BsdumpError::CorruptPatch(msg)) => {
+            assert_eq!(msg, "asked for patch 2, but the buffer holds only 2")
+        }
+        Err(e) => panic!("unexpected error {}", e),
+        Ok(_) => panic!("found a third patch"),
+    }
+}
@@ -0,0 +1,58 @@
+#![cfg(feature = "bz2")]
+
+mod common;
+
+use bsdump::bsdiff_format::{BsdiffReader, CompressorType, Primitive};
+
+#[test]
+fn primitives_follow_entry_fields() {
+    let fixture = common::generate(3, 4096, [CompressorType::Bz2; 3]);
+    let reader = BsdiffReader::new(&fixture.patch).unwrap();
+    for entry in reader.control_entries() {
+        assert_eq!(
+            entry.primitives(),
+            [
+                Primitive::Add {
+                    len: entry.diff_size
+                },
+                Primitive::Insert {
+                    len: entry.extra_size
+                },
+                Primitive::Seek {
+                    delta: entry.offset_increment
+                },
+            ]
+        );
+    }
+}
+
+#[test]
+fn applying_primitives_matches_apply() {
+    let fixture = common::generate(11, 8192, [CompressorType::Bz2; 3]);
+    let reader = BsdiffReader::new(&fixture.patch).unwrap();
+    let diff = reader.decompressed_diff_stream().unwrap();
+    let extra = reader.decompressed_extra_stream().unwrap();
+    let (mut diff_pos, mut extra_pos, mut old_pos) = (0_usize, 0_usize, 0_i64);
+    let mut new_file = Vec::new();
+    for entry in reader.control_entries() {
+        for primitive in entry.primitives().iter() {
+            match *primitive {
+                Primitive::Add { len } => {
+                    for _ in 0..len {
+                        let old = fixture.source.get(old_pos as usize).copied();
+                        new_file.push(diff[diff_pos].wrapping_add(old.unwrap_or(0)));
+                        diff_pos += 1;
+                        old_pos += 1;
+                    }
+                }
+                Primitive::Insert { len } => {
+                    new_file.extend_from_slice(&extra[extra_pos..][..len as usize]);
+                    extra_pos += len as usize;
+                }
+                Primitive::Seek { delta } => old_pos += delta,
+            }
+        }
+    }
+    assert_eq!(new_file, fixture.target);
+    assert_eq!(new_file, reader.apply(&fixture.source).unwrap());
+}
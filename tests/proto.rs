@@ -0,0 +1,193 @@
+#![cfg(all(feature = "bz2", feature = "proto"))]
+
+mod common;
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use bsdump::bsdiff_format::{BsdiffReader, CompressorType};
+use bsdump::error::BsdumpError;
+use bsdump::options::BsdiffReaderOptions;
+use bsdump::proto::decode;
+use bsdump::writer::BsdiffWriter;
+
+fn patch() -> Vec<u8> {
+    return common::write_patch(&[
+        (&[0; 300], b"x", 4),
+        (&[1], b"", -2),
+        (&[], b"yz", i64::MIN + 1),
+    ]);
+}
+
+#[test]
+fn proto_round_trip() {
+    let patch = patch();
+    let reader = BsdiffReader::new(&patch).unwrap();
+    let message = decode(&reader.to_proto()).unwrap();
+    assert_eq!(message.header.magic, reader.header.magic);
+    assert_eq!(
+        message.header.compressed_ctrl_size,
+        reader.header.compressed_ctrl_size
+    );
+    assert_eq!(
+        message.header.compressed_diff_size,
+        reader.header.compressed_diff_size
+    );
+    assert_eq!(message.header.new_file_size, 304);
+    assert!(message
+        .control_entries
+        .into_iter()
+        .eq(reader.control_entries()));
+    assert!(!message.truncated);
+}
+
+#[test]
+fn proto_notes_truncation() {
+    let patch = patch();
+    let options = BsdiffReaderOptions::new().max_control_entries(1);
+    let reader = BsdiffReader::with_options(&patch, options).unwrap();
+    let message = decode(&reader.to_proto()).unwrap();
+    assert_eq!(message.control_entries.len(), 1);
+    assert!(message.truncated);
+}
+
+#[test]
+fn entry_encoding_matches_protobuf() {
+    let mut writer = BsdiffWriter::new([CompressorType::Bz2; 3]);
+    writer.add_entry(&[0; 300], b"", -1);
+    let patch = writer.finish().unwrap();
+    let encoded = BsdiffReader::new(&patch).unwrap().to_proto();
+    // Field 2, 5 bytes: diff_size 300 as a varint, offset_increment -1
+    // zigzag encoded as 1, and no extra_size since it is zero.
+    let entry = [0x12, 0x05, 0x08, 0xac, 0x02, 0x18, 0x01];
+    assert!(encoded.windows(entry.len()).any(|window| window == entry));
+    match decode(&[0x12, 0x05, 0x08]) {
+        Err(BsdumpError::InvalidProto(_)) => {}
+        Err(e) => panic!("unexpected error {}", e),
+        Ok(_) => panic!("truncated message was decoded"),
+    }
+}
+
+// Field number to type and name, for each message of proto/bsdump.proto.
+type Schema = HashMap<String, HashMap<u64, (String, String)>>;
+
+fn schema() -> Schema {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("proto/bsdump.proto");
+    let mut messages = Schema::new();
+    let mut current = None;
+    for line in fs::read_to_string(path).unwrap().lines() {
+        let line = line.split("//").next().unwrap();
+        let tokens: Vec<&str> = line
+            .split_whitespace()
+            .filter(|token| *token != "repeated")
+            .collect();
+        match (tokens.as_slice(), &current) {
+            (["message", name, "{"], _) => current = Some(name.to_string()),
+            (["}"], _) => current = None,
+            ([kind, name, "=", number], Some(message)) => {
+                let number = number.trim_end_matches(';').parse().unwrap();
+                messages
+                    .entry(message.clone())
+                    .or_default()
+                    .insert(number, (kind.to_string(), name.to_string()));
+            }
+            _ => {}
+        }
+    }
+    return messages;
+}
+
+#[derive(Debug, PartialEq)]
+enum Node {
+    Value(i128),
+    Message(Vec<(String, Node)>),
+}
+
+fn varint(data: &[u8], pos: &mut usize) -> u64 {
+    let mut value = 0;
+    for shift in (0..64).step_by(7) {
+        let byte = data[*pos];
+        *pos += 1;
+        value |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            break;
+        }
+    }
+    return value;
+}
+
+// Decodes `data` as `message` going only by the schema, independently of
+// `bsdump::proto::decode`. Panics on a field or wire type the schema
+// doesn't have.
+fn decode_as(schema: &Schema, message: &str, data: &[u8]) -> Vec<(String, Node)> {
+    let mut fields = Vec::new();
+    let mut pos = 0;
+    while pos < data.len() {
+        let key = varint(data, &mut pos);
+        let (kind, name) = &schema[message][&(key >> 3)];
+        let node = match (kind.as_str(), key & 7) {
+            ("uint64" | "bool", 0) => Node::Value(varint(data, &mut pos).into()),
+            ("sint64", 0) => {
+                let value = varint(data, &mut pos);
+                Node::Value(((value >> 1) as i64 ^ -((value & 1) as i64)).into())
+            }
+            (_, 2) if schema.contains_key(kind) => {
+                let len = varint(data, &mut pos) as usize;
+                let sub = &data[pos..pos + len];
+                pos += len;
+                Node::Message(decode_as(schema, kind, sub))
+            }
+            (_, wire_type) => panic!("{}.{} has wire type {}", message, name, wire_type),
+        };
+        fields.push((name.clone(), node));
+    }
+    return fields;
+}
+
+// A message with `fields`, leaving out the ones that are zero like
+// protobuf does.
+fn message(fields: &[(&str, i128)]) -> Node {
+    return Node::Message(
+        fields
+            .iter()
+            .filter(|(_, value)| *value != 0)
+            .map(|(name, value)| (name.to_string(), Node::Value(*value)))
+            .collect(),
+    );
+}
+
+#[test]
+fn encoding_follows_the_proto_schema() {
+    let patch = patch();
+    let reader = BsdiffReader::new(&patch).unwrap();
+    let header = &reader.header;
+    let mut expected = vec![(
+        "header".to_string(),
+        message(&[
+            ("magic", header.magic.into()),
+            ("compressed_ctrl_size", header.compressed_ctrl_size.into()),
+            ("compressed_diff_size", header.compressed_diff_size.into()),
+            ("new_file_size", header.new_file_size.into()),
+        ]),
+    )];
+    for entry in reader.control_entries() {
+        expected.push((
+            "control_entries".to_string(),
+            message(&[
+                ("diff_size", entry.diff_size.into()),
+                ("extra_size", entry.extra_size.into()),
+                ("offset_increment", entry.offset_increment.into()),
+            ]),
+        ));
+    }
+    assert_eq!(decode_as(&schema(), "Patch", &reader.to_proto()), expected);
+
+    let options = BsdiffReaderOptions::new().max_control_entries(1);
+    let reader = BsdiffReader::with_options(&patch, options).unwrap();
+    let fields = decode_as(&schema(), "Patch", &reader.to_proto());
+    assert_eq!(
+        fields.last().unwrap(),
+        &("truncated".to_string(), Node::Value(1))
+    );
+}
@@ -0,0 +1,48 @@
+#![cfg(feature = "bz2")]
+
+mod common;
+
+use bsdump::bsdiff_format::{BsdiffReader, CompressorType};
+
+fn patch() -> Vec<u8> {
+    return common::write_patch(&[
+        // A pure copy, even with extra bytes after it.
+        (&[0; 4], b"x", 0),
+        // One non-zero diff byte makes it a real add.
+        (&[0, 0, 3], b"", 0),
+        // Reads no source, so copies nothing.
+        (&[], b"yz", -7),
+        (&[0], b"", 0),
+    ]);
+}
+
+#[test]
+fn entries_with_all_zero_diff_bytes_are_pure_copies() {
+    let patch = patch();
+    let reader = BsdiffReader::new(&patch).unwrap();
+    let operations = reader.operations().unwrap();
+    let flags: Vec<_> = operations.iter().map(|op| op.is_pure_copy).collect();
+    assert_eq!(flags, vec![true, false, false, true]);
+    assert_eq!(operations[1].entry.target_offset, 5);
+    assert_eq!(operations[3].entry.index, 3);
+    assert_eq!(reader.apply(b"abcdefg").unwrap(), b"abcdxefjyza");
+}
+
+#[test]
+fn report_counts_pure_copies() {
+    let patch = patch();
+    let report = BsdiffReader::new(&patch).unwrap().report();
+    assert_eq!(report.pure_copies, Some(2));
+    let mut json = Vec::new();
+    report.write_stats_json(&mut json).unwrap();
+    assert!(String::from_utf8(json)
+        .unwrap()
+        .contains("  \"pure_copies\": 2,\n"));
+}
+
+#[test]
+fn operations_cover_every_entry() {
+    let fixture = common::generate(7, 4096, [CompressorType::Bz2; 3]);
+    let reader = BsdiffReader::new(&fixture.patch).unwrap();
+    assert_eq!(reader.operations().unwrap().len(), fixture.num_entries);
+}
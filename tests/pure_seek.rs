@@ -0,0 +1,39 @@
+#![cfg(feature = "bz2")]
+
+mod common;
+
+use bsdump::bsdiff_format::{BsdiffReader, CompressorType};
+use bsdump::writer::BsdiffWriter;
+
+// Copies "ab", seeks over "cd" with a pure-seek entry, then copies "ef".
+fn patch() -> Vec<u8> {
+    return common::write_patch(&[(&[0, 0], b"", 0), (&[], b"", 2), (&[0, 1], b"!", 0)]);
+}
+
+#[test]
+fn pure_seek_only_moves_the_source() {
+    let patch = patch();
+    let reader = BsdiffReader::new(&patch).unwrap();
+    let pure_seeks: Vec<bool> = reader
+        .control_entries()
+        .map(|entry| entry.is_pure_seek())
+        .collect();
+    assert_eq!(pure_seeks, [false, true, false]);
+    assert_eq!(reader.report().pure_seeks, 1);
+
+    assert_eq!(reader.apply(b"abcdef").unwrap(), b"abeg!");
+    let mut buf = b"abcdef".to_vec();
+    reader.apply_in_place(&mut buf).unwrap();
+    assert_eq!(buf, b"abeg!");
+    assert_eq!(reader.into_apply(b"abcdef").unwrap(), b"abeg!");
+}
+
+#[test]
+fn empty_entry_is_not_a_seek() {
+    let mut writer = BsdiffWriter::new([CompressorType::Bz2; 3]);
+    writer.add_entry(&[], b"", 0);
+    let patch = writer.finish().unwrap();
+    let reader = BsdiffReader::new(&patch).unwrap();
+    assert!(!reader.control_entries().next().unwrap().is_pure_seek());
+    assert_eq!(reader.apply(b"").unwrap(), b"");
+}
@@ -0,0 +1,52 @@
+#![cfg(feature = "bz2")]
+
+mod common;
+
+use bsdump::bsdiff_format::{BsdiffFormat, BsdiffReader, CompressorType};
+
+fn off_by(patch: &[u8], delta: i64) -> Vec<u8> {
+    let size = BsdiffFormat::parse_unchecked(patch)
+        .unwrap()
+        .compressed_diff_size;
+    return BsdiffReader::with_compressed_diff_size(patch, (size as i64 + delta) as u64);
+}
+
+#[test]
+fn off_by_one_diff_size_is_found() {
+    let fixture = common::generate(11, 2048, [CompressorType::Bz2; 3]);
+    let size = BsdiffReader::new(&fixture.patch)
+        .unwrap()
+        .header
+        .compressed_diff_size;
+    for delta in [-1, 1] {
+        let broken = off_by(&fixture.patch, delta);
+        assert!(BsdiffReader::new(&broken)
+            .and_then(|reader| reader.decompressed_extra_stream())
+            .is_err());
+        assert_eq!(
+            BsdiffReader::find_diff_size_repair(&broken).unwrap(),
+            Some(size)
+        );
+        let repaired = BsdiffReader::with_compressed_diff_size(&broken, size);
+        assert_eq!(repaired, fixture.patch);
+        let reader = BsdiffReader::new(&repaired).unwrap();
+        assert_eq!(reader.apply(&fixture.source).unwrap(), fixture.target);
+    }
+}
+
+#[test]
+fn intact_patch_needs_no_repair() {
+    let fixture = common::generate(12, 512, [CompressorType::Bz2; 3]);
+    assert_eq!(
+        BsdiffReader::find_diff_size_repair(&fixture.patch).unwrap(),
+        None
+    );
+}
+
+#[test]
+fn larger_errors_are_not_repaired() {
+    let fixture = common::generate(13, 512, [CompressorType::Bz2; 3]);
+    let broken = off_by(&fixture.patch, 5);
+    assert!(BsdiffReader::find_diff_size_repair(&broken).is_err());
+    assert!(BsdiffReader::find_diff_size_repair(&fixture.patch[..16]).is_err());
+}
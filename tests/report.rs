@@ -0,0 +1,106 @@
+#![cfg(feature = "bz2")]
+
+use bsdump::bsdiff_format::{BsdiffReader, CompressorType};
+use bsdump::report::DiffStreamStats;
+use bsdump::writer::BsdiffWriter;
+
+#[test]
+fn report_aggregates_the_patch() {
+    let mut writer = BsdiffWriter::new([CompressorType::Bz2; 3]);
+    writer.add_entry(&[0, 0, 0, 1], b"x", 4);
+    writer.add_entry(&[0, 0], b"", -2);
+    let patch = writer.finish().unwrap();
+    let reader = BsdiffReader::new(&patch).unwrap();
+    let report = reader.report();
+
+    assert_eq!(report.format, "BSDF2");
+    assert_eq!(report.compressors, [CompressorType::Bz2; 3]);
+    assert_eq!(report.new_file_size, 7);
+    assert_eq!(report.num_control_entries, 2);
+    assert_eq!((report.diff_bytes, report.extra_bytes), (6, 1));
+    assert!(report.has_backward_seeks);
+    assert_eq!(report.entry_sizes.max_diff, 4);
+    let diff_stream = report.diff_stream.as_ref().unwrap();
+    assert_eq!((diff_stream.len, diff_stream.zero_bytes), (6, 5));
+    assert_eq!(reader.diff_stream_zeros().unwrap(), (5, 6));
+    assert!(report.problems.is_empty());
+
+    let mut json = Vec::new();
+    report.write_json(&mut json).unwrap();
+    let json = String::from_utf8(json).unwrap();
+    assert!(json.contains("  \"format\": \"BSDF2\",\n"));
+    assert!(json.contains("  \"diff_bytes\": 6,\n"));
+    assert!(json.ends_with("  \"problems\": []\n}\n"));
+}
+
+#[test]
+fn report_lists_self_check_problems() {
+    let mut writer = BsdiffWriter::new([CompressorType::Bz2; 3]);
+    writer.add_entry(&[0], b"x", 0);
+    let mut patch = writer.finish().unwrap();
+    // Claim a larger new file than the entries produce.
+    patch[24..32].copy_from_slice(&5_u64.to_le_bytes());
+    let report = BsdiffReader::new(&patch).unwrap().report();
+    assert_eq!(report.problems.len(), 1);
+
+    let mut json = Vec::new();
+    report.write_json(&mut json).unwrap();
+    let json = String::from_utf8(json).unwrap();
+    assert!(json.ends_with(&format!(
+        "  \"problems\": [\n    \"{}\"\n  ]\n}}\n",
+        report.problems[0]
+    )));
+}
+
+#[test]
+fn diff_stream_entropy() {
+    assert_eq!(DiffStreamStats::new(&[0; 16]).entropy, 0.0);
+    assert_eq!(DiffStreamStats::new(&[0, 1, 2, 3]).entropy, 2.0);
+    let empty = DiffStreamStats::new(&[]);
+    assert_eq!((empty.entropy, empty.zero_percentage()), (0.0, 0.0));
+    assert_eq!(DiffStreamStats::new(&[0, 0, 0, 7]).zero_percentage(), 75.0);
+}
+
+#[test]
+fn stats_json_has_only_aggregates() {
+    let mut writer = BsdiffWriter::new([CompressorType::Bz2; 3]);
+    writer.add_entry(&[0, 0, 0, 1], b"x", 4);
+    writer.add_entry(&[], b"yz", 0);
+    writer.add_entry(&[], b"", -2);
+    let patch = writer.finish().unwrap();
+    let report = BsdiffReader::new(&patch).unwrap().report();
+
+    assert_eq!(report.operations.diff_and_extra, 1);
+    assert_eq!(report.operations.extra_only, 1);
+    assert_eq!(report.operations.no_output, 1);
+    assert_eq!(report.patch_size, patch.len() as u64);
+    assert_eq!(report.extra_stream_len, Some(3));
+    let savings = report.space_savings().unwrap();
+    assert_eq!(savings, 1.0 - patch.len() as f64 / 7.0);
+
+    let mut json = Vec::new();
+    report.write_stats_json(&mut json).unwrap();
+    let json = String::from_utf8(json).unwrap();
+    assert!(json.contains("  \"num_control_entries\": 3,\n"));
+    assert!(json.contains(
+        "\"extra_only\": 1, \"diff_and_extra\": 1, \"no_output\": 1, \"pure_seeks\": 1}"
+    ));
+    assert!(json.contains(&format!("  \"new_data_fraction\": {},\n", 3.0 / 7.0)));
+    assert!(json.contains("  \"zero_percentage\": 75,\n"));
+    assert!(!json.contains("control_entries\": ["));
+    assert!(json.ends_with("}\n}\n"));
+}
+
+#[test]
+fn stats_json_nulls_undefined_ratios() {
+    let writer = BsdiffWriter::new([CompressorType::Bz2; 3]);
+    let patch = writer.finish().unwrap();
+    let report = BsdiffReader::new(&patch).unwrap().report();
+    assert_eq!(report.space_savings(), None);
+
+    let mut json = Vec::new();
+    report.write_stats_json(&mut json).unwrap();
+    let json = String::from_utf8(json).unwrap();
+    assert!(json.contains("  \"space_savings\": null,\n"));
+    assert!(json.contains("\"stream_ratios\": {\"ctrl\": null, \"diff\": null, \"extra\": null}"));
+}
@@ -0,0 +1,50 @@
+#![cfg(feature = "bz2")]
+
+mod common;
+
+use bsdump::bsdiff_format::BsdiffReader;
+use bsdump::options::BsdiffReaderOptions;
+
+// Entries write [0, 3), nothing at 3, then [3, 6).
+fn patch() -> Vec<u8> {
+    return common::write_patch(&[(&[0, 0], b"x", 0), (&[], b"", 5), (&[0], b"yz", 0)]);
+}
+
+#[test]
+fn seek_lands_on_the_covering_entry() {
+    let patch = patch();
+    let reader = BsdiffReader::new(&patch).unwrap();
+    let mut entries = reader.control_entries();
+
+    assert_eq!(entries.seek_to_target(4), Some(3));
+    assert_eq!(entries.next().unwrap().extra_size, 2);
+    assert!(entries.next().is_none());
+
+    // Seeking backwards works too, and the empty entry is skipped.
+    assert_eq!(entries.seek_to_target(3), Some(3));
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries.seek_to_target(2), Some(0));
+    assert_eq!(entries.len(), 3);
+    assert_eq!(entries.next().unwrap().diff_size, 2);
+}
+
+#[test]
+fn seek_past_the_end() {
+    let patch = patch();
+    let reader = BsdiffReader::new(&patch).unwrap();
+    let mut entries = reader.control_entries();
+    assert_eq!(entries.seek_to_target(6), None);
+    assert!(entries.next().is_none());
+    assert!(!entries.is_truncated());
+}
+
+#[test]
+fn seek_respects_max_control_entries() {
+    let patch = patch();
+    let options = BsdiffReaderOptions::new().max_control_entries(1);
+    let reader = BsdiffReader::with_options(&patch, options).unwrap();
+    let mut entries = reader.control_entries();
+    assert_eq!(entries.seek_to_target(5), Some(3));
+    assert!(entries.next().is_none());
+    assert!(entries.is_truncated());
+}
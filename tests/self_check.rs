@@ -0,0 +1,49 @@
+#![cfg(feature = "bz2")]
+
+mod common;
+
+use bsdump::bsdiff_format::{BsdiffReader, CompressorType};
+use bsdump::error::BsdumpError;
+use bsdump::options::BsdiffReaderOptions;
+use bsdump::writer::BsdiffWriter;
+
+fn messages(problems: Vec<BsdumpError>) -> Vec<String> {
+    return problems.iter().map(ToString::to_string).collect();
+}
+
+#[test]
+fn consistent_patch_passes() {
+    let mut writer = BsdiffWriter::new([CompressorType::Bz2; 3]);
+    writer.add_entry(&[0, 1], b"xyz", 3);
+    writer.add_entry(&[2], b"", -1);
+    let patch = writer.finish().unwrap();
+    assert!(BsdiffReader::new(&patch).unwrap().self_check().is_ok());
+}
+
+#[test]
+fn every_problem_is_reported() {
+    let mut ctrl = Vec::new();
+    // Needs 4 diff and 2 extra bytes, the streams have 1 and 0.
+    for value in [4_u64, 2, 0] {
+        ctrl.extend_from_slice(&value.to_le_bytes());
+    }
+    // extra_size with the high bit set, which bspatch reads as negative.
+    for value in [0_u64, 5 | 1 << 63, 0] {
+        ctrl.extend_from_slice(&value.to_le_bytes());
+    }
+    ctrl.extend_from_slice(&[0; 4]);
+    let patch = common::raw_patch(CompressorType::Bz2, &ctrl, &[0], b"", 6);
+    let options = BsdiffReaderOptions::new().allow_partial_ctrl_entry(true);
+    let reader = BsdiffReader::with_options(&patch, options).unwrap();
+    let problems = messages(reader.self_check().unwrap_err());
+    assert_eq!(
+        problems,
+        [
+            "control entry 2 at ctrl stream offset 48 is truncated, only 4 of 24 bytes present",
+            "corrupt patch: control entry 1 has extra_size 9223372036854775813, more than i64::MAX",
+            "control entries read 4 bytes from the diff stream, but it has 1",
+            "control entries read 9223372036854775815 bytes from the extra stream, but it has 0",
+            "corrupt patch: control entries produce 9223372036854775819 bytes, expected 6",
+        ]
+    );
+}
@@ -0,0 +1,110 @@
+#[cfg(all(feature = "crc32fast", feature = "bz2"))]
+mod common;
+
+use std::fs;
+use std::path::Path;
+
+use bsdump::error::BsdumpError;
+use bsdump::sidecar::Sidecar;
+
+#[test]
+fn dictionary_is_read_next_to_the_sidecar() {
+    let dir = std::env::temp_dir().join(format!("bsdump-sidecar-dict-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("dict.bin"), b"dictionary").unwrap();
+    let path = dir.join("patch.sidecar");
+    fs::write(&path, "zstd_dictionary = dict.bin\n").unwrap();
+    let sidecar = Sidecar::load(&path).unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(sidecar.zstd_dictionary.as_deref(), Some(&b"dictionary"[..]));
+}
+
+#[cfg(feature = "crc32fast")]
+#[test]
+fn sidecar_is_parsed() {
+    let dir = std::env::temp_dir().join(format!("bsdump-sidecar-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("dict.bin"), b"dictionary").unwrap();
+    let path = dir.join("patch.sidecar");
+    fs::write(
+        &path,
+        "# decode parameters\n\nzstd_dictionary = dict.bin\nsource_crc32 = 0x1c291ca3\ntarget_crc32 = 8A9136AA\n",
+    )
+    .unwrap();
+    let sidecar = Sidecar::load(&path).unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(
+        sidecar,
+        Sidecar {
+            zstd_dictionary: Some(b"dictionary".to_vec()),
+            source_crc32: Some(0x1c29_1ca3),
+            target_crc32: Some(0x8a91_36aa),
+        }
+    );
+}
+
+#[test]
+fn bad_lines_are_rejected() {
+    for text in ["zstd_dictionary", "target_crc32 = xyz", "hash = 1"] {
+        match Sidecar::parse(text, Path::new("")) {
+            Err(BsdumpError::InvalidSidecar { line, .. }) => assert_eq!(line, 1),
+            Err(e) => panic!("unexpected error {}", e),
+            Ok(_) => panic!("{:?} was accepted", text),
+        }
+    }
+}
+
+#[cfg(not(feature = "crc32fast"))]
+#[test]
+fn checksums_need_crc32fast() {
+    match Sidecar::parse("\nsource_crc32 = 0x1c291ca3", Path::new("")) {
+        Err(e @ BsdumpError::InvalidSidecar { .. }) => assert_eq!(
+            e.to_string(),
+            "invalid sidecar, line 2: source_crc32 can't be checked without the crc32fast feature"
+        ),
+        Err(e) => panic!("unexpected error {}", e),
+        Ok(_) => panic!("a checksum that can't be checked was accepted"),
+    }
+}
+
+#[cfg(all(feature = "crc32fast", feature = "bz2"))]
+mod verified {
+    use super::*;
+    use bsdump::bsdiff_format::BsdiffReader;
+
+    fn patch() -> Vec<u8> {
+        return common::write_patch(&[(&[0, 1], b"!", 0)]);
+    }
+
+    fn read(patch: &[u8], source_crc32: u32, target_crc32: u32) -> BsdiffReader<'_> {
+        let sidecar = Sidecar {
+            zstd_dictionary: None,
+            source_crc32: Some(source_crc32),
+            target_crc32: Some(target_crc32),
+        };
+        return BsdiffReader::builder()
+            .sidecar(sidecar)
+            .read(patch)
+            .unwrap();
+    }
+
+    #[test]
+    fn checksums_are_checked_when_applying() {
+        let patch = patch();
+        let (source_crc32, target_crc32) = (crc32fast::hash(b"ab"), crc32fast::hash(b"ac!"));
+        let reader = read(&patch, source_crc32, target_crc32);
+        assert_eq!(reader.apply_verified(b"ab").unwrap(), b"ac!");
+
+        match reader.apply_verified(b"ax") {
+            Err(BsdumpError::ChecksumMismatch { file, .. }) => assert_eq!(file, "source"),
+            Err(e) => panic!("unexpected error {}", e),
+            Ok(_) => panic!("wrong source was accepted"),
+        }
+        let reader = read(&patch, source_crc32, !target_crc32);
+        match reader.apply_verified(b"ab") {
+            Err(BsdumpError::ChecksumMismatch { file, .. }) => assert_eq!(file, "new file"),
+            Err(e) => panic!("unexpected error {}", e),
+            Ok(_) => panic!("wrong new file was accepted"),
+        }
+    }
+}
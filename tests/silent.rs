@@ -0,0 +1,62 @@
+#![cfg(feature = "bz2")]
+
+mod common;
+
+use std::process::Command;
+
+use bsdump::bsdiff_format::CompressorType;
+use bsdump::options::BsdiffReaderOptions;
+
+// Set when this test binary runs itself to parse with captured output.
+const CHILD_ENV: &str = "BSDUMP_SILENT_TEST_CHILD";
+
+// Parses a patch in a child process with `--nocapture`, and returns
+// everything it printed between the markers.
+fn output_of(test_name: &str) -> String {
+    let output = Command::new(std::env::current_exe().unwrap())
+        .args([test_name, "--exact", "--nocapture", "--test-threads=1"])
+        .env(CHILD_ENV, "1")
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    let start = stdout.find("<<<").unwrap() + 3;
+    let end = stdout.find(">>>").unwrap();
+    return format!("{}{}", &stdout[start..end], stderr);
+}
+
+fn parse(trace: bool) {
+    if std::env::var_os(CHILD_ENV).is_none() {
+        return;
+    }
+    let fixture = common::generate(1, 1024, [CompressorType::Bz2; 3]);
+    let mut options = BsdiffReaderOptions::new();
+    if trace {
+        options = options.trace_decompress(|_| {});
+    }
+    print!("<<<");
+    let reader = options.read(&fixture.patch).unwrap();
+    print!(">>>");
+    assert_eq!(reader.apply(&fixture.source).unwrap(), fixture.target);
+}
+
+#[test]
+fn child_default() {
+    parse(false);
+}
+
+#[test]
+fn child_traced() {
+    parse(true);
+}
+
+#[test]
+fn new_prints_nothing_by_default() {
+    assert_eq!(output_of("child_default"), "");
+}
+
+#[test]
+fn tracing_prints_nothing_either() {
+    assert_eq!(output_of("child_traced"), "");
+}
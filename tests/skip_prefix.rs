@@ -0,0 +1,40 @@
+#![cfg(feature = "bz2")]
+
+mod common;
+
+use bsdump::bsdiff_format::{BsdiffReader, CompressorType};
+use bsdump::error::BsdumpError;
+
+#[test]
+fn wrapped_payload_parses_after_the_prefix() {
+    let fixture = common::generate(5, 2048, [CompressorType::Bz2; 3]);
+    let mut wrapped = b"WRAP\x01\x00\x00\x00".to_vec();
+    wrapped.extend_from_slice(&fixture.patch);
+
+    assert!(BsdiffReader::new(&wrapped).is_err());
+    let reader = BsdiffReader::builder()
+        .skip_prefix(8)
+        .read(&wrapped)
+        .unwrap();
+    let plain = BsdiffReader::new(&fixture.patch).unwrap();
+    assert_eq!(reader.base_offset(), 8);
+    assert_eq!(reader.header.magic, plain.header.magic);
+    assert_eq!(
+        reader.compressed_ctrl_stream(),
+        plain.compressed_ctrl_stream()
+    );
+    assert_eq!(
+        reader.stream_ranges(fixture.patch.len() as u64),
+        plain.stream_ranges(fixture.patch.len() as u64)
+    );
+    assert_eq!(reader.apply(&fixture.source).unwrap(), fixture.target);
+}
+
+#[test]
+fn prefix_longer_than_input_is_rejected() {
+    match BsdiffReader::builder().skip_prefix(9).read(b"BSDF2") {
+        Err(BsdumpError::CorruptPatch(_)) => {}
+        Err(e) => panic!("unexpected error {}", e),
+        Ok(_) => panic!("prefix past the end was accepted"),
+    }
+}
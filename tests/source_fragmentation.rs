@@ -0,0 +1,41 @@
+#![cfg(feature = "bz2")]
+
+use bsdump::bsdiff_format::{BsdiffReader, CompressorType, SourceFragmentation};
+use bsdump::writer::BsdiffWriter;
+
+#[test]
+fn seeks_are_counted_between_reads() {
+    let mut writer = BsdiffWriter::new([CompressorType::Bz2; 3]);
+    // Reads [0, 4), then jumps ahead to 10.
+    writer.add_entry(&[0; 4], b"", 6);
+    // Reads [10, 12) and continues at 12, an extra-only entry in between
+    // does not read.
+    writer.add_entry(&[0; 2], b"", 0);
+    writer.add_entry(&[], b"xyz", 0);
+    // Reads [12, 15), then jumps back to 1.
+    writer.add_entry(&[0; 3], b"", -14);
+    writer.add_entry(&[0; 3], b"", 0);
+    let patch = writer.finish().unwrap();
+    let reader = BsdiffReader::new(&patch).unwrap();
+    assert_eq!(
+        reader.source_fragmentation(),
+        SourceFragmentation {
+            num_reads: 4,
+            avg_read_len: 3.0,
+            num_seeks: 2,
+            seek_distance_sum: 6 + 14,
+        }
+    );
+}
+
+#[test]
+fn full_image_has_no_reads() {
+    let mut writer = BsdiffWriter::new([CompressorType::Bz2; 3]);
+    writer.add_entry(&[], b"new", 100);
+    let patch = writer.finish().unwrap();
+    let reader = BsdiffReader::new(&patch).unwrap();
+    assert_eq!(
+        reader.source_fragmentation(),
+        SourceFragmentation::default()
+    );
+}
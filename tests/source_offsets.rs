@@ -0,0 +1,40 @@
+#![cfg(feature = "bz2")]
+
+use bsdump::bsdiff_format::{BsdiffReader, CompressorType};
+use bsdump::writer::BsdiffWriter;
+
+#[test]
+fn source_offsets_follow_diff_sizes_and_seeks() {
+    let mut writer = BsdiffWriter::new([CompressorType::Bz2; 3]);
+    writer.add_entry(&[0, 0], b"x", 3);
+    writer.add_entry(&[0], b"", -6);
+    writer.add_entry(&[], b"y", 0);
+    let patch = writer.finish().unwrap();
+    let reader = BsdiffReader::new(&patch).unwrap();
+    let offsets: Vec<Option<i64>> = (0..=3).map(|i| reader.source_offset_for_entry(i)).collect();
+    assert_eq!(offsets, [Some(0), Some(5), Some(0), Some(0)]);
+
+    // Seeking before the start of the source stays visible.
+    let mut writer = BsdiffWriter::new([CompressorType::Bz2; 3]);
+    writer.add_entry(&[0], b"", -4);
+    let patch = writer.finish().unwrap();
+    let reader = BsdiffReader::new(&patch).unwrap();
+    assert_eq!(reader.source_offset_for_entry(1), Some(-3));
+    let entries: Vec<Option<i64>> = reader
+        .control_entries_with_offsets()
+        .map(|entry| Some(entry.source_offset))
+        .collect();
+    assert_eq!(entries, [reader.source_offset_for_entry(0)]);
+}
+
+#[test]
+fn overflowing_source_offset_is_none() {
+    let mut writer = BsdiffWriter::new([CompressorType::Bz2; 3]);
+    writer.add_entry(&[], b"", i64::MAX);
+    writer.add_entry(&[0], b"", 0);
+    writer.add_entry(&[], b"", i64::MIN);
+    let patch = writer.finish().unwrap();
+    let reader = BsdiffReader::new(&patch).unwrap();
+    let offsets: Vec<Option<i64>> = (0..=3).map(|i| reader.source_offset_for_entry(i)).collect();
+    assert_eq!(offsets, [Some(0), Some(i64::MAX), None, None]);
+}
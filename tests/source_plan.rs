@@ -0,0 +1,78 @@
+#![cfg(feature = "bz2")]
+
+mod common;
+
+use bsdump::bsdiff_format::{BsdiffReader, CompressorType};
+use bsdump::dump::write_source_plan;
+
+fn as_tuples(ranges: Vec<std::ops::Range<u64>>) -> Vec<(u64, u64)> {
+    return ranges
+        .iter()
+        .map(|range| (range.start, range.end))
+        .collect();
+}
+
+fn patch() -> Vec<u8> {
+    return common::write_patch(&[
+        // Reads [0, 4), then seeks back to 2.
+        (&[0; 4], b"x", -2),
+        // Reads [2, 5), continuing at 5.
+        (&[0; 3], b"", 0),
+        // Reads [5, 7), merged with the read before.
+        (&[0; 2], b"", 10),
+        // Only inserts.
+        (&[], b"yz", 0),
+        // Reads [17, 21), partly past a 20 byte source.
+        (&[0; 4], b"", 0),
+    ]);
+}
+
+#[test]
+fn plan_keeps_read_order_and_merges_contiguous_reads() {
+    let patch = patch();
+    let reader = BsdiffReader::new(&patch).unwrap();
+    assert_eq!(
+        as_tuples(reader.source_plan(20)),
+        vec![(0, 4), (2, 7), (17, 20)]
+    );
+    assert_eq!(as_tuples(reader.source_plan(3)), vec![(0, 3), (2, 3)]);
+    assert!(reader.source_plan(0).is_empty());
+}
+
+#[test]
+fn plan_as_json() {
+    let patch = patch();
+    let reader = BsdiffReader::new(&patch).unwrap();
+    let mut out = Vec::new();
+    write_source_plan(&reader, 20, &mut out).unwrap();
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        concat!(
+            "[\n",
+            "  {\"start\": 0, \"len\": 4},\n",
+            "  {\"start\": 2, \"len\": 5},\n",
+            "  {\"start\": 17, \"len\": 3}\n",
+            "]\n"
+        )
+    );
+    let mut out = Vec::new();
+    write_source_plan(&reader, 0, &mut out).unwrap();
+    assert_eq!(out, b"[\n]\n");
+}
+
+#[test]
+fn plan_covers_the_fixture_reads() {
+    let fixture = common::generate(5, 4096, [CompressorType::Bz2; 3]);
+    let reader = BsdiffReader::new(&fixture.patch).unwrap();
+    let source_len = fixture.source.len() as u64;
+    let planned: u64 = reader
+        .source_plan(source_len)
+        .iter()
+        .map(|range| range.end - range.start)
+        .sum();
+    let read: u64 = reader
+        .source_read_ranges()
+        .map(|range| range.end.min(source_len) - range.start.min(source_len))
+        .sum();
+    assert_eq!(planned, read);
+}
@@ -0,0 +1,118 @@
+#![cfg(all(feature = "bz2", feature = "zstd"))]
+
+mod common;
+
+use bsdump::bsdiff_format::{BsdiffReader, CompressorType, ControlEntry};
+use bsdump::error::BsdumpError;
+use bsdump::options::BsdiffReaderOptions;
+
+#[test]
+fn streamed_entries_match_the_parsed_ones() {
+    for compressor_type in [CompressorType::Bz2, CompressorType::Zstd] {
+        let fixture = common::generate(3, 4096, [compressor_type; 3]);
+        let reader = BsdiffReader::new(&fixture.patch).unwrap();
+        assert_eq!(reader.apply(&fixture.source).unwrap(), fixture.target);
+        let mut stream = BsdiffReader::stream_from(&fixture.patch[..]).unwrap();
+        assert_eq!(stream.header.new_file_size, reader.get_new_file_size());
+
+        let entries: Vec<_> = stream.by_ref().map(Result::unwrap).collect();
+        assert_eq!(entries.len(), fixture.num_entries);
+        assert!(entries.into_iter().eq(reader.control_entries()));
+        // What is left starts with the diff stream.
+        let rest = stream.into_inner().unwrap();
+        assert!(rest.starts_with(reader.compressed_diff_stream()));
+    }
+}
+
+#[test]
+fn truncated_input_is_reported() {
+    let fixture = common::generate(5, 1024, [CompressorType::Bz2; 3]);
+    let reader = BsdiffReader::new(&fixture.patch).unwrap();
+    let ctrl_end = 32 + reader.compressed_ctrl_stream().len();
+    let input = &fixture.patch[..ctrl_end - 1];
+    let mut stream = BsdiffReader::stream_from(input).unwrap();
+    assert!(stream.by_ref().any(|entry| entry.is_err()));
+    assert!(stream.next().is_none());
+}
+
+#[test]
+fn not_a_patch() {
+    assert!(matches!(
+        BsdiffReader::stream_from(&b"NOTAPTCH and some more bytes"[..]),
+        Err(BsdumpError::NotABsdiffPatch { .. })
+    ));
+}
+
+#[test]
+fn prefix_long_magic_and_limit_are_honored() {
+    let fixture = common::generate(6, 2048, [CompressorType::Bz2; 3]);
+    let reader = BsdiffReader::new(&fixture.patch).unwrap();
+    let mut input = b"wrapper".to_vec();
+    input.extend_from_slice(&fixture.patch[..8]);
+    input.extend_from_slice(b"12345678");
+    input.extend_from_slice(&fixture.patch[8..]);
+    let options = BsdiffReaderOptions::new()
+        .skip_prefix(7)
+        .magic_len(16)
+        .max_control_entries(2);
+    let stream = BsdiffReader::stream_from_with_options(&input[..], options).unwrap();
+    assert_eq!(stream.header, reader.header);
+    let entries: Vec<_> = stream.map(Result::unwrap).collect();
+    assert!(entries.into_iter().eq(reader.control_entries().take(2)));
+}
+
+#[test]
+fn partial_entry_is_allowed_on_request() {
+    let mut ctrl = common::ctrl_stream(&[(2, 1, 0)]);
+    ctrl.extend_from_slice(&[0; 10]);
+    let patch = common::raw_patch(CompressorType::Bz2, &ctrl, &[0, 0], b"x", 3);
+    let strict: Vec<_> = BsdiffReader::stream_from(&patch[..]).unwrap().collect();
+    assert!(matches!(
+        strict[..],
+        [
+            Ok(_),
+            Err(BsdumpError::TruncatedControlEntry { index: 1, .. })
+        ]
+    ));
+    let options = BsdiffReaderOptions::new().allow_partial_ctrl_entry(true);
+    let lenient: Vec<_> = BsdiffReader::stream_from_with_options(&patch[..], options)
+        .unwrap()
+        .map(Result::unwrap)
+        .collect();
+    assert_eq!(lenient.len(), 1);
+}
+
+#[test]
+fn zstd_dictionary_is_used() {
+    let entries = [(2, 1, 0), (0, 3, 5)];
+    let raw_ctrl = common::ctrl_stream(&entries);
+    // The control stream itself as a raw dictionary, so the frame can't be
+    // decoded without it.
+    let dictionary = raw_ctrl.clone();
+    let ctrl = zstd::bulk::Compressor::with_dictionary(3, &dictionary)
+        .unwrap()
+        .compress(&raw_ctrl)
+        .unwrap();
+    let mut patch = b"BSDF2\x03\x03\x03".to_vec();
+    for value in [ctrl.len() as u64, 0, 6] {
+        patch.extend_from_slice(&value.to_le_bytes());
+    }
+    patch.extend_from_slice(&ctrl);
+
+    let expected: Vec<ControlEntry> = entries
+        .iter()
+        .map(|&(diff_size, extra_size, offset_increment)| ControlEntry {
+            diff_size,
+            extra_size,
+            offset_increment,
+        })
+        .collect();
+    let options = BsdiffReaderOptions::new().zstd_dictionary(dictionary);
+    let streamed: Vec<_> = BsdiffReader::stream_from_with_options(&patch[..], options)
+        .unwrap()
+        .map(Result::unwrap)
+        .collect();
+    assert_eq!(streamed, expected);
+    let without: Result<Vec<_>, _> = BsdiffReader::stream_from(&patch[..]).unwrap().collect();
+    assert!(!matches!(without, Ok(entries) if entries == expected));
+}
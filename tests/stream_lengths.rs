@@ -0,0 +1,86 @@
+#![cfg(feature = "bz2")]
+
+mod common;
+
+use std::io::Cursor;
+
+use bsdump::bsdiff_format::BsdiffReader;
+use bsdump::error::BsdumpError;
+
+#[test]
+fn short_diff_stream() {
+    let patch = common::bsdf2_patch(&[(4, 1, 0)], &[0, 0], b"x", 5);
+    let reader = BsdiffReader::new(&patch).unwrap();
+    assert!(matches!(
+        reader.check_stream_lengths(),
+        Err(BsdumpError::DiffLengthMismatch {
+            expected: 4,
+            found: 2
+        })
+    ));
+    assert!(matches!(
+        reader.apply(b"abcd"),
+        Err(BsdumpError::DiffLengthMismatch { .. })
+    ));
+}
+
+#[test]
+fn empty_diff_stream_with_diff_entries() {
+    let patch = common::bsdf2_patch(&[(3, 1, 0)], &[], b"x", 4);
+    let reader = BsdiffReader::new(&patch).unwrap();
+    let is_mismatch = |result: Result<(), BsdumpError>| {
+        return matches!(
+            result,
+            Err(BsdumpError::DiffLengthMismatch {
+                expected: 3,
+                found: 0
+            })
+        );
+    };
+    assert!(is_mismatch(reader.check_stream_lengths()));
+    assert!(reader
+        .self_check()
+        .unwrap_err()
+        .iter()
+        .any(|e| matches!(e, BsdumpError::DiffLengthMismatch { .. })));
+    // No way of applying reads past the empty diff buffer.
+    assert!(is_mismatch(reader.apply(b"abc").map(drop)));
+    assert!(is_mismatch(
+        reader
+            .apply_from_source(Cursor::new(b"abc"), &mut Vec::new())
+            .map(drop)
+    ));
+    let mut buf = b"abc".to_vec();
+    assert!(is_mismatch(reader.apply_in_place(&mut buf)));
+    assert_eq!(buf, b"abc");
+    let out_path = std::env::temp_dir().join(format!("bsdump-empty-diff-{}", std::process::id()));
+    assert!(is_mismatch(reader.apply_to_mmap(b"abc", &out_path)));
+    assert!(!out_path.exists());
+    let reader = BsdiffReader::new(&patch).unwrap();
+    assert!(is_mismatch(reader.into_apply(b"abc").map(drop)));
+}
+
+#[test]
+fn short_extra_stream() {
+    let patch = common::bsdf2_patch(&[(2, 3, 0)], &[0, 0], b"x", 5);
+    let reader = BsdiffReader::new(&patch).unwrap();
+    assert!(matches!(
+        reader.check_stream_lengths(),
+        Err(BsdumpError::ExtraLengthMismatch {
+            expected: 3,
+            found: 1
+        })
+    ));
+    assert!(matches!(
+        reader.into_apply(b"ab"),
+        Err(BsdumpError::ExtraLengthMismatch { .. })
+    ));
+}
+
+#[test]
+fn longer_streams_are_fine() {
+    let patch = common::bsdf2_patch(&[(2, 1, 0)], &[0, 0, 9], b"xy", 3);
+    let reader = BsdiffReader::new(&patch).unwrap();
+    assert!(reader.check_stream_lengths().is_ok());
+    assert_eq!(reader.apply(b"ab").unwrap(), b"abx");
+}
@@ -0,0 +1,101 @@
+#![cfg(all(feature = "bz2", feature = "brotli"))]
+
+use bsdump::bsdiff_format::{BsdiffReader, CompressorType, MemoryEstimate};
+use bsdump::compression::compress;
+
+fn control_stream(entries: &[(u64, u64, u64)]) -> Vec<u8> {
+    let mut ctrl = Vec::new();
+    for (diff_size, extra_size, offset_increment) in entries {
+        ctrl.extend_from_slice(&diff_size.to_le_bytes());
+        ctrl.extend_from_slice(&extra_size.to_le_bytes());
+        ctrl.extend_from_slice(&offset_increment.to_le_bytes());
+    }
+    return ctrl;
+}
+
+#[test]
+fn bsdf2_stream_slices() {
+    let ctrl = compress(&control_stream(&[(4, 3, 0)]), CompressorType::Bz2).unwrap();
+    let diff = compress(&[0, 1, 0, 1], CompressorType::Brotli).unwrap();
+    let extra = compress(b"new", CompressorType::Bz2).unwrap();
+    let mut patch = b"BSDF2\x01\x02\x01".to_vec();
+    patch.extend_from_slice(&(ctrl.len() as u64).to_le_bytes());
+    patch.extend_from_slice(&(diff.len() as u64).to_le_bytes());
+    patch.extend_from_slice(&7_u64.to_le_bytes());
+    patch.extend_from_slice(&ctrl);
+    patch.extend_from_slice(&diff);
+    patch.extend_from_slice(&extra);
+
+    let reader = BsdiffReader::new(&patch).unwrap();
+    assert_eq!(reader.compressed_ctrl_stream(), &ctrl[..]);
+    assert_eq!(reader.compressed_diff_stream(), &diff[..]);
+    assert_eq!(reader.compressed_extra_stream(), &extra[..]);
+    assert_eq!(reader.decompressed_extra_stream().unwrap(), b"new");
+    assert_eq!(reader.apply(b"abcd").unwrap(), b"accenew");
+    let estimate = reader.apply_memory_estimate().unwrap();
+    assert_eq!(
+        estimate,
+        MemoryEstimate {
+            ctrl_stream: 24,
+            mask_stream: 0,
+            diff_stream: 4,
+            extra_stream: 3,
+            output: 7,
+        }
+    );
+    assert_eq!(estimate.total(), 38);
+}
+
+#[test]
+fn bdf3_extra_slice_excludes_mask() {
+    let ctrl = compress(&control_stream(&[(2, 1, 0)]), CompressorType::Bz2).unwrap();
+    // The full diff stream is [0, 5]; only the second byte is stored.
+    let diff = compress(&[5], CompressorType::Bz2).unwrap();
+    let extra = compress(b"x", CompressorType::Bz2).unwrap();
+    let mask = compress(&[0b10], CompressorType::Brotli).unwrap();
+    let mut patch = b"BDF3\x00\x01\x01\x01".to_vec();
+    patch.extend_from_slice(&(ctrl.len() as u64).to_le_bytes());
+    patch.extend_from_slice(&(diff.len() as u64).to_le_bytes());
+    patch.extend_from_slice(&3_u64.to_le_bytes());
+    patch.extend_from_slice(&(mask.len() as u64).to_le_bytes());
+    patch.extend_from_slice(&ctrl);
+    patch.extend_from_slice(&diff);
+    patch.extend_from_slice(&extra);
+    patch.extend_from_slice(&mask);
+
+    let reader = BsdiffReader::new(&patch).unwrap();
+    assert_eq!(reader.compressed_ctrl_stream(), &ctrl[..]);
+    assert_eq!(reader.compressed_diff_stream(), &diff[..]);
+    assert_eq!(reader.compressed_extra_stream(), &extra[..]);
+    assert_eq!(reader.apply(b"ab").unwrap(), b"agx");
+    let estimate = reader.apply_memory_estimate().unwrap();
+    // The one byte mask expands the diff stream to eight bytes.
+    assert_eq!((estimate.mask_stream, estimate.diff_stream), (1, 8));
+    assert_eq!(reader.into_apply(b"ab").unwrap(), b"agx");
+}
+
+#[test]
+fn diff_offsets_slice_each_entry() {
+    let ctrl = compress(
+        &control_stream(&[(2, 1, 0), (0, 2, 0), (3, 0, 0)]),
+        CompressorType::Bz2,
+    )
+    .unwrap();
+    let diff = compress(&[1, 2, 3, 4, 5], CompressorType::Bz2).unwrap();
+    let extra = compress(b"xyz", CompressorType::Bz2).unwrap();
+    let mut patch = b"BSDF2\x01\x01\x01".to_vec();
+    patch.extend_from_slice(&(ctrl.len() as u64).to_le_bytes());
+    patch.extend_from_slice(&(diff.len() as u64).to_le_bytes());
+    patch.extend_from_slice(&8_u64.to_le_bytes());
+    patch.extend_from_slice(&ctrl);
+    patch.extend_from_slice(&diff);
+    patch.extend_from_slice(&extra);
+
+    let reader = BsdiffReader::new(&patch).unwrap();
+    let offsets: Vec<u64> = (0..=3).map(|i| reader.diff_offset_for_entry(i)).collect();
+    assert_eq!(offsets, [0, 2, 2, 5]);
+    let diff_stream = reader.decompressed_diff_stream().unwrap();
+    let last = reader.control_entries().nth(2).unwrap();
+    let start = reader.diff_offset_for_entry(2) as usize;
+    assert_eq!(&diff_stream[start..][..last.diff_size as usize], [3, 4, 5]);
+}
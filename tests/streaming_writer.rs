@@ -0,0 +1,61 @@
+#![cfg(all(
+    feature = "bz2",
+    feature = "brotli",
+    feature = "zstd",
+    feature = "gzip"
+))]
+
+mod common;
+
+use std::io::{Cursor, Seek};
+
+use bsdump::bsdiff_format::{BsdiffReader, CompressorType};
+use bsdump::writer::StreamingBsdiffWriter;
+
+// Re-encodes `patch` through the streaming writer, entry
+// by entry, into `out` after whatever it already holds.
+fn restream(patch: &[u8], out: &mut Cursor<Vec<u8>>, compressors: [CompressorType; 3]) {
+    let reader = BsdiffReader::new(patch).unwrap();
+    let diff = reader.decompressed_diff_stream().unwrap();
+    let extra = reader.decompressed_extra_stream().unwrap();
+    let mut writer = StreamingBsdiffWriter::new(out, compressors).unwrap();
+    let (mut diff_pos, mut extra_pos) = (0, 0);
+    for entry in reader.control_entries() {
+        let diff_end = diff_pos + entry.diff_size as usize;
+        let extra_end = extra_pos + entry.extra_size as usize;
+        writer
+            .add_entry(
+                &diff[diff_pos..diff_end],
+                &extra[extra_pos..extra_end],
+                entry.offset_increment,
+            )
+            .unwrap();
+        (diff_pos, extra_pos) = (diff_end, extra_end);
+    }
+    assert_eq!(writer.new_file_size(), reader.get_new_file_size());
+    let out = writer.finish().unwrap();
+    assert_eq!(out.stream_position().unwrap(), out.get_ref().len() as u64);
+}
+
+#[test]
+fn streamed_patches_apply_with_every_compressor() {
+    for (seed, compressor_type) in CompressorType::ALL.iter().copied().enumerate() {
+        let fixture = common::generate(seed as u64, 8192, [compressor_type; 3]);
+        let mut out = Cursor::new(Vec::new());
+        restream(&fixture.patch, &mut out, [compressor_type; 3]);
+        let reader = BsdiffReader::new(out.get_ref()).unwrap();
+        assert_eq!(reader.header.compressors().unwrap(), [compressor_type; 3]);
+        assert_eq!(reader.apply(&fixture.source).unwrap(), fixture.target);
+    }
+}
+
+#[test]
+fn streamed_bz2_patch_matches_the_buffered_writer() {
+    let fixture = common::generate(3, 4096, [CompressorType::Bz2; 3]);
+    // Starting partway into `out` leaves what came before alone.
+    let mut out = Cursor::new(b"prefix".to_vec());
+    out.seek(std::io::SeekFrom::End(0)).unwrap();
+    restream(&fixture.patch, &mut out, [CompressorType::Bz2; 3]);
+    assert_eq!(&out.get_ref()[..6], b"prefix");
+    assert_eq!(&out.get_ref()[6..], fixture.patch);
+}
@@ -0,0 +1,48 @@
+#![cfg(all(feature = "bz2", feature = "zstd"))]
+
+mod common;
+
+use bsdump::bsdiff_format::{BsdiffReader, CompressorType};
+use bsdump::error::BsdumpError;
+use bsdump::record::{SummaryRecord, SUMMARY_RECORD_LEN};
+
+const COMPRESSORS: [CompressorType; 3] = [
+    CompressorType::Bz2,
+    CompressorType::Zstd,
+    CompressorType::Bz2,
+];
+
+#[test]
+fn record_round_trips() {
+    let fixture = common::generate(11, 4096, COMPRESSORS);
+    let reader = BsdiffReader::new(&fixture.patch).unwrap();
+    let record = reader.summary_record();
+    assert_eq!(&record[..4], [1, 1, 3, 1]);
+
+    let decoded = SummaryRecord::decode(&record).unwrap();
+    assert_eq!(decoded, SummaryRecord::new(&reader));
+    assert_eq!(decoded.format, "BSDF2");
+    assert_eq!(decoded.compressors, COMPRESSORS);
+    assert_eq!(decoded.new_file_size, fixture.target.len() as u64);
+    assert_eq!(decoded.num_control_entries, fixture.num_entries as u64);
+    assert_eq!(
+        decoded.diff_bytes + decoded.extra_bytes,
+        decoded.new_file_size
+    );
+    assert_eq!(decoded.encode(), record);
+}
+
+#[test]
+fn unknown_bytes_are_rejected() {
+    let mut record = [0_u8; SUMMARY_RECORD_LEN];
+    record[1..4].copy_from_slice(&[1, 1, 1]);
+    assert!(SummaryRecord::decode(&record).is_ok());
+    for (index, value) in [(0, 3), (2, 9)] {
+        let mut bad = record;
+        bad[index] = value;
+        assert!(matches!(
+            SummaryRecord::decode(&bad),
+            Err(BsdumpError::CorruptPatch(_))
+        ));
+    }
+}
@@ -0,0 +1,40 @@
+//! Applies the synthetic patches under `tests/data/synthetic` and checks
+//! the output against the target stored next to each. See the README there
+//! for the layout and where the files come from.
+#![cfg(feature = "bz2")]
+
+use std::fs;
+use std::path::Path;
+
+use bsdump::bsdiff_format::BsdiffReader;
+
+#[test]
+fn synthetic_patches_produce_their_targets() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/data/synthetic");
+    let mut fixtures: Vec<_> = fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.is_dir())
+        .collect();
+    fixtures.sort();
+    assert!(!fixtures.is_empty(), "no fixtures in {}", dir.display());
+    for fixture in fixtures {
+        let read = |name: &str| {
+            fs::read(fixture.join(name))
+                .unwrap_or_else(|e| panic!("{}/{}: {}", fixture.display(), name, e))
+        };
+        let source = read("source");
+        let patch = read("patch");
+        let expected = read("target");
+        let reader =
+            BsdiffReader::new(&patch).unwrap_or_else(|e| panic!("{}: {}", fixture.display(), e));
+        let target = reader
+            .apply(&source)
+            .unwrap_or_else(|e| panic!("{}: {}", fixture.display(), e));
+        assert!(
+            target == expected,
+            "{} does not produce its stored target",
+            fixture.display()
+        );
+    }
+}
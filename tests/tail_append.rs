@@ -0,0 +1,52 @@
+#![cfg(feature = "bz2")]
+
+use bsdump::bsdiff_format::{BsdiffReader, CompressorType};
+use bsdump::error::BsdumpError;
+use bsdump::writer::BsdiffWriter;
+
+#[test]
+fn trailing_extra_bytes_are_the_tail() {
+    let mut writer = BsdiffWriter::new([CompressorType::Bz2; 3]);
+    writer.add_entry(&[0; 3], b"a", 0);
+    writer.add_entry(&[1], b"bc", 0);
+    writer.add_entry(&[], b"def", 0);
+    writer.add_entry(&[], b"g", 0);
+    let patch = writer.finish().unwrap();
+    let reader = BsdiffReader::new(&patch).unwrap();
+    assert_eq!(reader.tail_append_len(), 6);
+    assert_eq!(reader.apply(b"wxyz").unwrap(), b"wxya{bcdefg");
+}
+
+#[test]
+fn tail_of_full_image_and_of_patch_ending_in_a_read() {
+    let mut writer = BsdiffWriter::new([CompressorType::Bz2; 3]);
+    writer.add_entry(&[], b"abc", 0);
+    writer.add_entry(&[], b"d", 0);
+    let patch = writer.finish().unwrap();
+    let reader = BsdiffReader::new(&patch).unwrap();
+    assert_eq!(reader.tail_append_len(), 4);
+    assert_eq!(reader.apply(b"").unwrap(), b"abcd");
+
+    let mut writer = BsdiffWriter::new([CompressorType::Bz2; 3]);
+    writer.add_entry(&[], b"abc", 0);
+    writer.add_entry(&[0], b"", 0);
+    let patch = writer.finish().unwrap();
+    let reader = BsdiffReader::new(&patch).unwrap();
+    assert_eq!(reader.tail_append_len(), 0);
+    assert_eq!(reader.apply(b"z").unwrap(), b"abcz");
+}
+
+#[test]
+fn tail_past_the_new_file_size_is_rejected() {
+    let mut writer = BsdiffWriter::new([CompressorType::Bz2; 3]);
+    writer.add_entry(&[0], b"", 0);
+    writer.add_entry(&[], b"xyz", 0);
+    let mut patch = writer.finish().unwrap();
+    patch[24..32].copy_from_slice(&2_u64.to_le_bytes());
+    let reader = BsdiffReader::new(&patch).unwrap();
+    match reader.apply(b"a") {
+        Err(BsdumpError::CorruptPatch(msg)) => assert!(msg.contains("from 1 on")),
+        Err(e) => panic!("unexpected error {}", e),
+        Ok(_) => panic!("oversized tail was applied"),
+    }
+}
@@ -0,0 +1,14 @@
+use bsdump::bsdiff_format::{BsdiffReader, ControlEntryIter, ControlEntryOffsetIter};
+use bsdump::error::BsdumpError;
+use bsdump::options::BsdiffReaderOptions;
+
+fn assert_send_sync<T: Send + Sync>() {}
+
+#[test]
+fn reader_types_are_send_and_sync() {
+    assert_send_sync::<BsdiffReader<'static>>();
+    assert_send_sync::<BsdiffReaderOptions>();
+    assert_send_sync::<ControlEntryIter<'static>>();
+    assert_send_sync::<ControlEntryOffsetIter<'static>>();
+    assert_send_sync::<BsdumpError>();
+}
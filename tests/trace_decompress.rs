@@ -0,0 +1,69 @@
+#![cfg(feature = "bz2")]
+
+mod common;
+
+use std::sync::{Arc, Mutex};
+
+use bsdump::bsdiff_format::{BsdiffReader, CompressorType};
+use bsdump::options::{BsdiffReaderOptions, DecompressTrace};
+
+const BZ2: [CompressorType; 3] = [CompressorType::Bz2; 3];
+
+// Options that collect every trace into the returned vector.
+fn tracing_options() -> (BsdiffReaderOptions, Arc<Mutex<Vec<DecompressTrace>>>) {
+    let traces = Arc::new(Mutex::new(Vec::new()));
+    let sink = traces.clone();
+    let options = BsdiffReaderOptions::new()
+        .trace_decompress(move |trace| sink.lock().unwrap().push(trace.clone()));
+    return (options, traces);
+}
+
+#[test]
+fn every_decompressed_stream_is_traced() {
+    let fixture = common::generate(11, 4096, BZ2);
+    let (options, traces) = tracing_options();
+    let reader = options.read(&fixture.patch).unwrap();
+    let extra_stream = reader.decompressed_extra_stream().unwrap();
+
+    let traces = traces.lock().unwrap();
+    let streams: Vec<_> = traces.iter().map(|trace| trace.stream).collect();
+    assert_eq!(streams, ["ctrl", "diff", "extra"]);
+    assert!(traces.iter().all(|trace| trace.error.is_none()));
+    assert!(traces
+        .iter()
+        .all(|trace| trace.compressor == CompressorType::Bz2));
+    assert_eq!(
+        traces[0].input_len,
+        reader.compressed_ctrl_stream().len() as u64
+    );
+    assert_eq!(
+        traces[0].output_len,
+        reader.decompressed_ctrl_stream().len() as u64
+    );
+    assert_eq!(traces[2].output_len, extra_stream.len() as u64);
+    let zeros = extra_stream.iter().filter(|byte| **byte == 0).count();
+    assert_eq!(traces[2].zero_bytes, zeros as u64);
+    // The generated diff stream is mostly unchanged copies.
+    assert!(traces[1].zero_bytes * 2 > traces[1].output_len);
+}
+
+#[test]
+fn failed_stream_is_traced_with_its_error() {
+    let fixture = common::generate(12, 4096, BZ2);
+    let reader = BsdiffReader::new(&fixture.patch).unwrap();
+    let diff_start = 32 + reader.compressed_ctrl_stream().len();
+    let diff_len = reader.compressed_diff_stream().len();
+    let mut patch = fixture.patch.clone();
+    // Overwrite the back half of the diff stream, past the bzip2 header.
+    for byte in &mut patch[diff_start + diff_len / 2..diff_start + diff_len] {
+        *byte = 0x55;
+    }
+
+    let (options, traces) = tracing_options();
+    assert!(options.read(&patch).is_err());
+    let traces = traces.lock().unwrap();
+    let last = traces.last().unwrap();
+    assert_eq!(last.stream, "diff");
+    assert_eq!(last.input_len, diff_len as u64);
+    assert!(last.error.is_some());
+}
@@ -0,0 +1,44 @@
+#![cfg(feature = "bz2")]
+
+use bsdump::bsdiff_format::{BsdiffReader, CompressorType};
+use bsdump::compression::compress;
+use bsdump::error::BsdumpError;
+
+// A BDF3 patch with no entries whose header claims a mask of `mask_size`
+// bytes, with only the empty ctrl, diff and extra streams after it.
+fn bdf3_patch(mask_size: u64) -> Vec<u8> {
+    let ctrl = compress(&[], CompressorType::Bz2).unwrap();
+    let diff = compress(&[], CompressorType::Bz2).unwrap();
+    let extra = compress(&[], CompressorType::Bz2).unwrap();
+    let mut patch = b"BDF3\x00\x01\x01\x01".to_vec();
+    patch.extend_from_slice(&(ctrl.len() as u64).to_le_bytes());
+    patch.extend_from_slice(&(diff.len() as u64).to_le_bytes());
+    patch.extend_from_slice(&0_u64.to_le_bytes());
+    patch.extend_from_slice(&mask_size.to_le_bytes());
+    patch.extend_from_slice(&ctrl);
+    patch.extend_from_slice(&diff);
+    patch.extend_from_slice(&extra);
+    return patch;
+}
+
+#[test]
+fn mask_larger_than_the_file_is_reported() {
+    for mask_size in [1 << 20, u64::MAX] {
+        let patch = bdf3_patch(mask_size);
+        let extra_len = compress(&[], CompressorType::Bz2).unwrap().len() as u64;
+        match BsdiffReader::new(&patch) {
+            Err(BsdumpError::TruncatedStream {
+                name,
+                len,
+                available,
+            }) => {
+                assert_eq!(name, "mask");
+                assert_eq!(len, mask_size);
+                assert_eq!(available, extra_len);
+            }
+            Err(e) => panic!("unexpected error {}", e),
+            Ok(_) => panic!("oversized mask was accepted"),
+        }
+        assert!(BsdiffReader::patch_len(&patch).is_err());
+    }
+}
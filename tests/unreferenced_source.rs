@@ -0,0 +1,39 @@
+#![cfg(feature = "bz2")]
+
+use bsdump::bsdiff_format::{BsdiffReader, CompressorType};
+use bsdump::writer::BsdiffWriter;
+
+// The unread ranges as (start, end) pairs, which compare more readably.
+fn unread(reader: &BsdiffReader, source_len: u64) -> Vec<(u64, u64)> {
+    return reader
+        .unreferenced_source_ranges(source_len)
+        .into_iter()
+        .map(|range| (range.start, range.end))
+        .collect();
+}
+
+#[test]
+fn gaps_between_reads_are_reported() {
+    let mut writer = BsdiffWriter::new([CompressorType::Bz2; 3]);
+    // Reads [0, 4), then skips to 10 and reads [10, 12).
+    writer.add_entry(&[0; 4], b"", 6);
+    writer.add_entry(&[0; 2], b"x", -9);
+    // Reads [3, 5), overlapping the first read.
+    writer.add_entry(&[0; 2], b"", 0);
+    let patch = writer.finish().unwrap();
+    let reader = BsdiffReader::new(&patch).unwrap();
+    assert_eq!(unread(&reader, 20), [(5, 10), (12, 20)]);
+    // Reads past the end of a shorter source don't count.
+    assert_eq!(unread(&reader, 11), [(5, 10)]);
+    assert!(unread(&reader, 4).is_empty());
+}
+
+#[test]
+fn full_image_reads_nothing() {
+    let mut writer = BsdiffWriter::new([CompressorType::Bz2; 3]);
+    writer.add_entry(&[], b"new", 0);
+    let patch = writer.finish().unwrap();
+    let reader = BsdiffReader::new(&patch).unwrap();
+    assert_eq!(unread(&reader, 8), [(0, 8)]);
+    assert!(unread(&reader, 0).is_empty());
+}
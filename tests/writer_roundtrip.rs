@@ -0,0 +1,83 @@
+#![cfg(all(feature = "bz2", feature = "brotli", feature = "zstd"))]
+
+mod common;
+
+use bsdump::bsdiff_format::{BsdiffReader, CompressorType, EntrySizeStats};
+use bsdump::options::BsdiffReaderOptions;
+use bsdump::writer::BsdiffWriter;
+
+#[test]
+fn generated_patches_apply_with_every_compressor() {
+    for (seed, compressor_type) in CompressorType::ALL.iter().copied().enumerate() {
+        let fixture = common::generate(seed as u64, 4096, [compressor_type; 3]);
+        let reader = BsdiffReader::new(&fixture.patch).unwrap();
+        assert_eq!(reader.header.compressors().unwrap(), [compressor_type; 3]);
+        assert_eq!(reader.num_control_entries(), fixture.num_entries);
+        assert_eq!(reader.get_new_file_size(), fixture.target.len() as u64);
+        reader
+            .validate_against_source(fixture.source.len() as u64)
+            .unwrap();
+        assert_eq!(reader.apply(&fixture.source).unwrap(), fixture.target);
+        assert_eq!(reader.into_apply(&fixture.source).unwrap(), fixture.target);
+    }
+}
+
+#[test]
+fn mixed_compressors_and_negative_offsets() {
+    let compressors = [
+        CompressorType::Zstd,
+        CompressorType::Bz2,
+        CompressorType::Brotli,
+    ];
+    let fixture = common::generate(0x5eed, 8192, compressors);
+    let reader = BsdiffReader::new(&fixture.patch).unwrap();
+    assert_eq!(reader.header.compressors().unwrap(), compressors);
+    assert!(reader.has_backward_seeks());
+    assert_eq!(reader.apply(&fixture.source).unwrap(), fixture.target);
+}
+
+#[test]
+fn empty_patch() {
+    let fixture = common::generate(7, 0, [CompressorType::Bz2; 3]);
+    let reader = BsdiffReader::new(&fixture.patch).unwrap();
+    assert_eq!(reader.num_control_entries(), 0);
+    assert!(!reader.has_backward_seeks());
+    assert_eq!(reader.apply(b"").unwrap(), b"");
+}
+
+#[test]
+fn entry_size_stats() {
+    let mut writer = BsdiffWriter::new([CompressorType::Bz2; 3]);
+    writer.add_entry(&[0; 10], b"x", 0);
+    writer.add_entry(&[0; 2], b"", 0);
+    writer.add_entry(&[], b"abcde", 0);
+    let patch = writer.finish().unwrap();
+    let stats = BsdiffReader::new(&patch).unwrap().entry_size_stats();
+    assert_eq!(
+        stats,
+        EntrySizeStats {
+            avg_diff: 4.0,
+            max_diff: 10,
+            avg_extra: 2.0,
+            max_extra: 5,
+        }
+    );
+
+    let empty = BsdiffWriter::new([CompressorType::Bz2; 3])
+        .finish()
+        .unwrap();
+    let stats = BsdiffReader::new(&empty).unwrap().entry_size_stats();
+    assert_eq!(stats, EntrySizeStats::default());
+}
+
+#[test]
+fn backward_seeks_past_max_control_entries() {
+    let mut writer = BsdiffWriter::new([CompressorType::Bz2; 3]);
+    writer.add_entry(&[0; 4], b"", 2);
+    writer.add_entry(&[0; 2], b"", -6);
+    writer.add_entry(&[0; 2], b"", 0);
+    let patch = writer.finish().unwrap();
+    let options = BsdiffReaderOptions::new().max_control_entries(1);
+    let reader = BsdiffReader::with_options(&patch, options).unwrap();
+    assert!(reader.has_backward_seeks());
+}